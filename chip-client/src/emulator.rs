@@ -0,0 +1,158 @@
+use std::path::{Path, PathBuf};
+
+use chip8_emulator::{EmulatorError, Quirks, Rom, RomError, CPU, DEFAULT_LOAD_ADDRESS};
+
+/// Microseconds of a frame: the timers run at 60 Hz
+const FRAME_MICROSECONDS: f64 = 1_000_000.0 / 60.0;
+/// Most frames run at once after a stall (like a suspended window), so it
+/// does not try to catch up on the whole time
+const MAX_FRAMES_PER_TICK: u32 = 8;
+
+/// The interpreter behind the window: the ROM, the CPU running it, and
+/// how fast it runs
+pub struct Emulator {
+    cpu: CPU,
+    rom: Option<Rom>,
+    rom_path: Option<PathBuf>,
+    quirks: Quirks,
+    paused: bool,
+    /// 1.0 runs 60 frames per second
+    speed: f64,
+    /// The frame time of the last tick, in microseconds
+    last_tick: Option<i64>,
+    /// Frames due which have not run yet, as a fraction of a frame
+    pending: f64,
+    /// The CPU faulted, and it does not run until the next reset
+    error: Option<EmulatorError>,
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        let quirks = Quirks::default();
+
+        Self {
+            cpu: CPU::new(quirks),
+            rom: None,
+            rom_path: None,
+            quirks,
+            paused: false,
+            speed: 1.0,
+            last_tick: None,
+            pending: 0.0,
+            error: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn rom_path(&self) -> Option<&Path> {
+        self.rom_path.as_deref()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.last_tick = None;
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    /**
+     * Loads a ROM file and starts running it from a fresh machine
+     */
+    pub fn open<P: AsRef<Path>>(&mut self, path: P) -> Result<(), RomError> {
+        let rom = Rom::from_file(&path, DEFAULT_LOAD_ADDRESS)?;
+        let mut cpu = CPU::new(self.quirks);
+
+        cpu.load_rom(&rom)?;
+
+        self.rom = Some(rom);
+        self.rom_path = Some(path.as_ref().to_path_buf());
+        self.start(cpu);
+
+        Ok(())
+    }
+
+    /**
+     * Starts the ROM again from a fresh machine
+     */
+    pub fn reset(&mut self) {
+        let mut cpu = CPU::new(self.quirks);
+
+        if let Some(rom) = &self.rom {
+            // It already loaded when it was opened
+            let _ = cpu.load_rom(rom);
+        }
+
+        self.start(cpu);
+    }
+
+    fn start(&mut self, cpu: CPU) {
+        self.cpu = cpu;
+        self.error = None;
+        self.last_tick = None;
+        self.pending = 0.0;
+    }
+
+    pub fn press_key(&mut self, key: u8) {
+        self.cpu.press_key(key);
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        self.cpu.release_key(key);
+    }
+
+    /**
+     * Releases the 16 keys, as their releases are not received once the
+     * window has lost the focus
+     */
+    pub fn release_all_keys(&mut self) {
+        self.cpu.set_keys(0);
+    }
+
+    /**
+     * Runs the frames due since the last tick of the frame clock (its frame
+     * time, in microseconds). Returns the error if the CPU faults
+     */
+    pub fn tick(&mut self, frame_time: i64) -> Result<(), EmulatorError> {
+        let elapsed = match self.last_tick.replace(frame_time) {
+            Some(last) => (frame_time - last).max(0) as f64,
+            None => 0.0,
+        };
+
+        if self.paused || self.rom.is_none() || self.error.is_some() {
+            return Ok(());
+        }
+
+        self.pending += elapsed * self.speed / FRAME_MICROSECONDS;
+
+        let frames = (self.pending as u32).min(MAX_FRAMES_PER_TICK);
+        self.pending = self.pending.fract();
+
+        for _ in 0..frames {
+            match self.cpu.run_frame() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    self.error = Some(err);
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
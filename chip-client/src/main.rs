@@ -1,28 +1,338 @@
+mod emulator;
+mod screen;
+
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use chip8_emulator::{keypad_key, Palette};
 use gtk4::prelude::*;
-use gtk4::{glib, Application, ApplicationWindow, Button};
+use gtk4::{
+    gio, glib, glib::clone, Application, ApplicationWindow, ButtonsType, DrawingArea,
+    EventControllerFocus, EventControllerKey, FileChooserAction, FileChooserNative, FileFilter,
+    MessageDialog, MessageType, ResponseType,
+};
 
-const APLICATION_ID: &str = "org.gtk_rs.HelloWorld";
+use emulator::Emulator;
 
-fn build_ui(app: &Application) {
-    let button = Button::builder().label("Press Me!").margin_top(50).build();
+const APPLICATION_ID: &str = "io.github.Nimeavles.Chip8Client";
+const TITLE: &str = "CHIP-8";
+/// The low resolution screen scaled 10 times
+const DEFAULT_WIDTH: i32 = 640;
+const DEFAULT_HEIGHT: i32 = 320;
+/// The entries of the Speed menu, as a fraction of the 60 frames per second
+const SPEEDS: [(&str, &str); 5] = [
+    ("25%", "0.25"),
+    ("50%", "0.5"),
+    ("100%", "1"),
+    ("200%", "2"),
+    ("400%", "4"),
+];
 
-    button.connect_clicked(|btn| {
-        btn.set_label("Hello World!");
-    });
+/// A window running a ROM
+struct Client {
+    window: ApplicationWindow,
+    emulator: Rc<RefCell<Emulator>>,
+    /// GTK does not keep the native dialogs alive while they are shown
+    chooser: RefCell<Option<FileChooserNative>>,
+}
+
+impl Client {
+    /**
+     * Shows an error on a dialog over the window
+     */
+    fn show_error(&self, message: &str, detail: &str) {
+        let dialog = MessageDialog::builder()
+            .transient_for(&self.window)
+            .modal(true)
+            .message_type(MessageType::Error)
+            .buttons(ButtonsType::Close)
+            .text(message)
+            .secondary_text(detail)
+            .build();
+
+        dialog.connect_response(|dialog, _| dialog.destroy());
+        dialog.present();
+    }
+
+    /**
+     * Shows the name of the ROM, and whether it is paused
+     */
+    fn update_title(&self) {
+        let emulator = self.emulator.borrow();
+        let name = emulator
+            .rom_path()
+            .and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().into_owned());
+
+        let title = match (name, emulator.is_paused()) {
+            (Some(name), true) => format!("{TITLE} - {name} (Paused)"),
+            (Some(name), false) => format!("{TITLE} - {name}"),
+            (None, _) => TITLE.to_string(),
+        };
+
+        self.window.set_title(Some(&title));
+    }
+
+    fn open(&self, path: &Path) {
+        let result = self.emulator.borrow_mut().open(path);
+
+        if let Err(err) = result {
+            self.show_error(
+                "The ROM can not be opened",
+                &format!("{}: {err}", path.display()),
+            );
+        }
+
+        self.update_title();
+    }
+
+    /**
+     * Asks for a ROM to open
+     */
+    fn choose_rom(self: &Rc<Self>) {
+        let chooser = FileChooserNative::new(
+            Some("Open ROM"),
+            Some(&self.window),
+            FileChooserAction::Open,
+            Some("_Open"),
+            Some("_Cancel"),
+        );
+
+        let roms = FileFilter::new();
+        roms.set_name(Some("CHIP-8 ROMs"));
+        for pattern in ["*.ch8", "*.c8", "*.sc8", "*.xo8"] {
+            roms.add_pattern(pattern);
+        }
+
+        let all = FileFilter::new();
+        all.set_name(Some("All files"));
+        all.add_pattern("*");
+
+        chooser.add_filter(&roms);
+        chooser.add_filter(&all);
+
+        let client = Rc::clone(self);
+        chooser.connect_response(move |chooser, response| {
+            let path = chooser.file().and_then(|file| file.path());
+
+            if let (ResponseType::Accept, Some(path)) = (response, path) {
+                client.open(&path);
+            }
+
+            client.chooser.replace(None);
+        });
+
+        chooser.show();
+        self.chooser.replace(Some(chooser));
+    }
+
+    /**
+     * Adds the actions of the menus: `win.open`, `win.pause`,
+     * `win.reset` and `win.speed`
+     */
+    fn add_actions(self: &Rc<Self>) {
+        let open = gio::SimpleAction::new("open", None);
+        open.connect_activate(clone!(@weak self as client => move |_, _| client.choose_rom()));
+
+        let pause = gio::SimpleAction::new_stateful("pause", None, &false.to_variant());
+        pause.connect_activate(clone!(@weak self as client => move |action, _| {
+            let paused = !action
+                .state()
+                .and_then(|state| state.get::<bool>())
+                .unwrap_or_default();
+
+            action.set_state(&paused.to_variant());
+            client.emulator.borrow_mut().set_paused(paused);
+            client.update_title();
+        }));
+
+        let reset = gio::SimpleAction::new("reset", None);
+        reset.connect_activate(clone!(@weak self as client => move |_, _| {
+            client.emulator.borrow_mut().reset();
+        }));
+
+        let speed = gio::SimpleAction::new_stateful(
+            "speed",
+            Some(glib::VariantTy::STRING),
+            &"1".to_variant(),
+        );
+        speed.connect_activate(clone!(@weak self as client => move |action, parameter| {
+            let Some(parameter) = parameter else {
+                return;
+            };
+
+            if let Some(speed) = parameter
+                .get::<String>()
+                .and_then(|speed| speed.parse().ok())
+            {
+                action.set_state(parameter);
+                client.emulator.borrow_mut().set_speed(speed);
+            }
+        }));
+
+        self.window.add_action(&open);
+        self.window.add_action(&pause);
+        self.window.add_action(&reset);
+        self.window.add_action(&speed);
+    }
+
+    /**
+     * Forwards the keys 1234/QWER/ASDF/ZXCV to the hex keypad, and releases
+     * them all when the window loses the focus
+     */
+    fn add_key_controller(&self) {
+        let keys = EventControllerKey::new();
+
+        let emulator = Rc::clone(&self.emulator);
+        keys.connect_key_pressed(
+            move |_, key, _, _| match key.to_unicode().and_then(keypad_key) {
+                Some(key) => {
+                    emulator.borrow_mut().press_key(key);
+                    glib::Propagation::Stop
+                }
+                None => glib::Propagation::Proceed,
+            },
+        );
+
+        let emulator = Rc::clone(&self.emulator);
+        keys.connect_key_released(move |_, key, _, _| {
+            if let Some(key) = key.to_unicode().and_then(keypad_key) {
+                emulator.borrow_mut().release_key(key);
+            }
+        });
 
+        self.window.add_controller(keys);
+
+        // A key released on another window would be held forever
+        let focus = EventControllerFocus::new();
+
+        let emulator = Rc::clone(&self.emulator);
+        focus.connect_leave(move |_| emulator.borrow_mut().release_all_keys());
+
+        self.window.add_controller(focus);
+    }
+
+    /**
+     * Draws the framebuffer, and runs the CPU on each tick of the frame clock
+     */
+    fn add_screen(self: &Rc<Self>) {
+        let area = DrawingArea::builder().hexpand(true).vexpand(true).build();
+        let palette = Palette::default();
+
+        let emulator = Rc::clone(&self.emulator);
+        area.set_draw_func(move |_, cr, width, height| {
+            let emulator = emulator.borrow();
+
+            // A failed draw only loses this frame
+            let _ = screen::draw(emulator.cpu().framebuffer(), &palette, cr, width, height);
+        });
+
+        let client = Rc::downgrade(self);
+        area.add_tick_callback(move |area, clock| {
+            let Some(client) = client.upgrade() else {
+                return glib::ControlFlow::Break;
+            };
+
+            let result = client.emulator.borrow_mut().tick(clock.frame_time());
+            if let Err(err) = result {
+                client.show_error(
+                    "The CPU stopped",
+                    &format!("{err}\n\nReset to start again."),
+                );
+            }
+
+            area.queue_draw();
+
+            glib::ControlFlow::Continue
+        });
+
+        self.window.set_child(Some(&area));
+    }
+}
+
+/**
+ * Opens a window, with the menus and an empty screen
+ */
+fn build_ui(app: &Application) -> Rc<Client> {
     let window = ApplicationWindow::builder()
         .application(app)
-        .title("Hello World GTK!")
-        .child(&button)
+        .title(TITLE)
+        .default_width(DEFAULT_WIDTH)
+        .default_height(DEFAULT_HEIGHT)
+        .show_menubar(true)
         .build();
 
-    window.present();
+    let client = Rc::new(Client {
+        window,
+        emulator: Rc::new(RefCell::new(Emulator::new())),
+        chooser: RefCell::new(None),
+    });
+
+    client.add_actions();
+    client.add_key_controller();
+    client.add_screen();
+    client.window.present();
+
+    client
+}
+
+/**
+ * Builds the menu bar, shared by every window
+ */
+fn build_menu(app: &Application) {
+    let file = gio::Menu::new();
+    file.append(Some("_Open ROM…"), Some("win.open"));
+    file.append(Some("_Quit"), Some("app.quit"));
+
+    let emulation = gio::Menu::new();
+    emulation.append(Some("_Pause"), Some("win.pause"));
+    emulation.append(Some("_Reset"), Some("win.reset"));
+
+    let speed = gio::Menu::new();
+    for (label, value) in SPEEDS {
+        speed.append(Some(label), Some(format!("win.speed::{value}").as_str()));
+    }
+    emulation.append_submenu(Some("_Speed"), &speed);
+
+    let menubar = gio::Menu::new();
+    menubar.append_submenu(Some("_File"), &file);
+    menubar.append_submenu(Some("_Emulation"), &emulation);
+
+    let quit = gio::SimpleAction::new("quit", None);
+    let weak = app.downgrade();
+    quit.connect_activate(move |_, _| {
+        if let Some(app) = weak.upgrade() {
+            app.quit();
+        }
+    });
+    app.add_action(&quit);
+
+    app.set_menubar(Some(&menubar));
+    app.set_accels_for_action("win.open", &["<Primary>o"]);
+    app.set_accels_for_action("win.pause", &["<Primary>p"]);
+    app.set_accels_for_action("win.reset", &["<Primary>r"]);
+    app.set_accels_for_action("app.quit", &["<Primary>q"]);
 }
 
 fn main() -> glib::ExitCode {
-    let app = Application::builder().application_id(APLICATION_ID).build();
+    let app = Application::builder()
+        .application_id(APPLICATION_ID)
+        .flags(gio::ApplicationFlags::HANDLES_OPEN)
+        .build();
+
+    app.connect_startup(build_menu);
 
-    app.connect_activate(build_ui);
+    app.connect_activate(|app| {
+        build_ui(app);
+    });
+
+    // chip-client my_file.ch8
+    app.connect_open(|app, files, _| {
+        for file in files {
+            if let Some(path) = file.path() {
+                build_ui(app).open(&path);
+            }
+        }
+    });
 
     app.run()
 }
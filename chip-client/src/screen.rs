@@ -0,0 +1,56 @@
+use chip8_emulator::{Framebuffer, Palette};
+use gtk4::cairo;
+
+/**
+ * Draws the framebuffer on the whole area, as big as it fits keeping its
+ * shape, each pixel a square (nearest neighbour) and black bars around it
+ */
+pub fn draw(
+    framebuffer: &Framebuffer,
+    palette: &Palette,
+    cr: &cairo::Context,
+    width: i32,
+    height: i32,
+) -> Result<(), cairo::Error> {
+    let [red, green, blue] = palette.colors[0];
+    cr.set_source_rgb(
+        red as f64 / 255.0,
+        green as f64 / 255.0,
+        blue as f64 / 255.0,
+    );
+    cr.paint()?;
+
+    let (columns, rows) = (framebuffer.width(), framebuffer.height());
+    let mut surface =
+        cairo::ImageSurface::create(cairo::Format::Rgb24, columns as i32, rows as i32)?;
+    let stride = surface.stride() as usize;
+
+    {
+        let mut data = surface.data().map_err(|_| cairo::Error::SurfaceFinished)?;
+
+        for y in 0..rows {
+            for x in 0..columns {
+                let [red, green, blue] = palette.colors[framebuffer.color(x, y) as usize & 0b11];
+                let pixel = u32::from_be_bytes([0x0, red, green, blue]);
+                let offset = y * stride + x * 4;
+
+                // Each pixel is a native endian 0x00RRGGBB
+                data[offset..offset + 4].copy_from_slice(&pixel.to_ne_bytes());
+            }
+        }
+    }
+
+    let scale = (width as f64 / columns as f64).min(height as f64 / rows as f64);
+    let x = (width as f64 - columns as f64 * scale) / 2.0;
+    let y = (height as f64 - rows as f64 * scale) / 2.0;
+
+    cr.translate(x, y);
+    cr.scale(scale, scale);
+
+    let pattern = cairo::SurfacePattern::create(&surface);
+    pattern.set_filter(cairo::Filter::Nearest);
+
+    cr.set_source(&pattern)?;
+    cr.rectangle(0.0, 0.0, columns as f64, rows as f64);
+    cr.fill()
+}
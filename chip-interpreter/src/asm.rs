@@ -0,0 +1,1170 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{instruction::Instruction, rom::DEFAULT_LOAD_ADDRESS};
+
+/// Name shown on the diagnostics of a source which is not a file
+pub(crate) const INPUT_NAME: &str = "<input>";
+
+/// Every mnemonic of the README table
+const MNEMONICS: [&str; 32] = [
+    "CLS", "RET", "HALT", "EXIT", "LOW", "HIGH", "SCD", "SCU", "SCR", "SCL", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssemblyErrorKind {
+    /// A character which does not start any token
+    UnexpectedCharacter(char),
+    InvalidNumber(String),
+    UnterminatedString,
+    /// The line does not follow the `label: MNEMONIC operands ; comment` shape
+    Syntax(String),
+    UnknownMnemonic(String),
+    /// The mnemonic exists, but not with these operands
+    InvalidOperands(String),
+    UnknownSymbol(String),
+    DuplicateSymbol(String),
+    /// The value does not fit on the operand
+    OutOfRange {
+        value: i64,
+        max: u32,
+    },
+    /// `org` points before the address where the ROM is loaded
+    OrgBeforeLoadAddress(u16),
+    /// Two parts of the program are assembled on the same address
+    Overlap(u16),
+    /// The program goes beyond the 64 KiB address space
+    AddressOverflow,
+    /// The included file could not be read
+    Include(String),
+    /// The file includes itself, directly or not
+    IncludeCycle(String),
+    /// An Octo `:directive` which does not exist
+    UnknownDirective(String),
+    /// The statement needs a larger instruction set than the selected one
+    Unsupported {
+        statement: String,
+        instruction_set: String,
+    },
+    /// The program does not fit on the memory of the instruction set
+    OutOfMemory(usize),
+    /// An Octo `:assert` which evaluated to zero
+    AssertionFailed(String),
+}
+
+impl fmt::Display for AssemblyErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssemblyErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character '{c}'"),
+            AssemblyErrorKind::InvalidNumber(number) => write!(f, "invalid number <{number}>"),
+            AssemblyErrorKind::UnterminatedString => write!(f, "unterminated string"),
+            AssemblyErrorKind::Syntax(message) => write!(f, "{message}"),
+            AssemblyErrorKind::UnknownMnemonic(mnemonic) => {
+                write!(f, "unknown mnemonic <{mnemonic}>")
+            }
+            AssemblyErrorKind::InvalidOperands(mnemonic) => {
+                write!(f, "invalid operands for {mnemonic}")
+            }
+            AssemblyErrorKind::UnknownSymbol(symbol) => write!(f, "unknown symbol <{symbol}>"),
+            AssemblyErrorKind::DuplicateSymbol(symbol) => {
+                write!(f, "the symbol <{symbol}> is already defined")
+            }
+            AssemblyErrorKind::OutOfRange { value, max } => {
+                write!(f, "the value {value} is out of range (0 - {max:#X})")
+            }
+            AssemblyErrorKind::OrgBeforeLoadAddress(address) => write!(
+                f,
+                "org {address:#05X} is before the load address of the ROM"
+            ),
+            AssemblyErrorKind::Overlap(address) => {
+                write!(f, "the address {address:#05X} is assembled twice")
+            }
+            AssemblyErrorKind::AddressOverflow => {
+                write!(f, "the program does not fit on 64 KiB")
+            }
+            AssemblyErrorKind::Include(err) => write!(f, "the file could not be included: {err}"),
+            AssemblyErrorKind::IncludeCycle(file) => write!(f, "{file} includes itself"),
+            AssemblyErrorKind::UnknownDirective(directive) => {
+                write!(f, "unknown directive <{directive}>")
+            }
+            AssemblyErrorKind::Unsupported {
+                statement,
+                instruction_set,
+            } => write!(
+                f,
+                "<{statement}> needs the {instruction_set} instruction set"
+            ),
+            AssemblyErrorKind::OutOfMemory(size) => {
+                write!(f, "the program does not fit on {size} bytes of memory")
+            }
+            AssemblyErrorKind::AssertionFailed(message) => {
+                write!(f, "assertion failed: {message}")
+            }
+        }
+    }
+}
+
+/// Error found while assembling, with where it was found
+/// (the file, and the line and column starting from 1)
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub kind: AssemblyErrorKind,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.kind
+        )
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+/// An error and the column where it was found, before knowing the line
+type Spanned<T> = Result<T, (usize, AssemblyErrorKind)>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Identifier(String),
+    Number(i64),
+    Str(String),
+    Comma,
+    Colon,
+    Plus,
+    Minus,
+    OpenBracket,
+    CloseBracket,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+/**
+ * Splits a line into tokens, until the end of the line or a comment (`;`)
+ */
+fn tokenize(line: &str) -> Spanned<Vec<Token>> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let column = i + 1;
+
+        let kind = match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            ',' => TokenKind::Comma,
+            ':' => TokenKind::Colon,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '[' => TokenKind::OpenBracket,
+            ']' => TokenKind::CloseBracket,
+            '"' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|c| *c == '"')
+                    .ok_or((column, AssemblyErrorKind::UnterminatedString))?;
+                let text: String = chars[i + 1..i + 1 + end].iter().collect();
+
+                i += end + 2;
+                tokens.push(Token {
+                    kind: TokenKind::Str(text),
+                    column,
+                });
+                continue;
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '.' => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '.'))
+                    .map_or(chars.len(), |end| i + end);
+                let word: String = chars[i..end].iter().collect();
+
+                i = end;
+                tokens.push(Token {
+                    kind: if c.is_ascii_digit() {
+                        parse_number(&word)
+                            .ok_or((column, AssemblyErrorKind::InvalidNumber(word)))?
+                    } else {
+                        TokenKind::Identifier(word)
+                    },
+                    column,
+                });
+                continue;
+            }
+            c => return Err((column, AssemblyErrorKind::UnexpectedCharacter(c))),
+        };
+
+        tokens.push(Token { kind, column });
+        i += 1;
+    }
+
+    Ok(tokens)
+}
+
+/**
+ * Parses a decimal, hexadecimal (0x) or binary (0b) number
+ */
+fn parse_number(word: &str) -> Option<TokenKind> {
+    let lower = word.to_lowercase().replace('_', "");
+
+    if let Some(hex) = lower.strip_prefix("0x") {
+        return Some(TokenKind::Number(i64::from_str_radix(hex, 16).ok()?));
+    }
+
+    if let Some(binary) = lower.strip_prefix("0b") {
+        return Some(TokenKind::Number(i64::from_str_radix(binary, 2).ok()?));
+    }
+
+    Some(TokenKind::Number(lower.parse().ok()?))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Term {
+    Number(i64),
+    Symbol(String),
+}
+
+/// Sum of numbers and symbols (`label + 2`), solved once every label is known
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Expression {
+    terms: Vec<(i64, Term, usize)>,
+    column: usize,
+    /// Marked with `LONG`, or known to be above 0xFFF when it was laid out:
+    /// **LD I** takes it as the 16 bits of **LD I, NNNN**
+    long: bool,
+}
+
+impl Expression {
+    fn evaluate(&self, symbols: &HashMap<String, i64>) -> Spanned<i64> {
+        let mut value: i64 = 0;
+
+        for (sign, term, column) in &self.terms {
+            let term = match term {
+                Term::Number(number) => *number,
+                Term::Symbol(name) => *symbols
+                    .get(name)
+                    .ok_or((*column, AssemblyErrorKind::UnknownSymbol(name.clone())))?,
+            };
+
+            // Sums which do not fit on 64 bits do not fit on any operand either
+            value = sign
+                .checked_mul(term)
+                .and_then(|term| value.checked_add(term))
+                .ok_or_else(|| {
+                    let value = match sign * term.signum() {
+                        -1 => i64::MIN,
+                        _ => i64::MAX,
+                    };
+
+                    (
+                        *column,
+                        AssemblyErrorKind::OutOfRange { value, max: 0xFFFF },
+                    )
+                })?;
+        }
+
+        Ok(value)
+    }
+
+    /**
+     * Evaluates the expression, checking that it fits on `max`.
+     * Negative values are allowed down to the half of the range, as two's complement
+     */
+    fn evaluate_in_range(&self, symbols: &HashMap<String, i64>, max: u32) -> Spanned<u32> {
+        let value = self.evaluate(symbols)?;
+        let min = -((max as i64 + 1) / 2);
+
+        if value < min || value > max as i64 {
+            return Err((self.column, AssemblyErrorKind::OutOfRange { value, max }));
+        }
+
+        Ok(value as u32 & max)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    /// `Vx - Vy` (XO-CHIP **SAVE** and **LOAD**)
+    RegisterRange(u8, u8),
+    I,
+    /// `[I]`
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Value(Expression),
+}
+
+/**
+ * Parses `V0` - `VF`
+ */
+fn parse_register(name: &str) -> Option<u8> {
+    let index = name.strip_prefix(['V', 'v'])?;
+
+    if index.len() != 1 {
+        return None;
+    }
+
+    u8::from_str_radix(index, 16).ok()
+}
+
+/**
+ * Parses a sum of numbers and symbols
+ */
+fn parse_expression(tokens: &[Token]) -> Spanned<Expression> {
+    let column = tokens.first().map_or(1, |token| token.column);
+    let mut terms = Vec::new();
+    let mut long = false;
+    let mut sign = 1;
+    let mut expect_term = true;
+
+    for (index, token) in tokens.iter().enumerate() {
+        match (&token.kind, expect_term) {
+            (TokenKind::Identifier(name), true)
+                if index == 0 && name.eq_ignore_ascii_case("long") =>
+            {
+                long = true;
+            }
+            (TokenKind::Number(value), true) => {
+                terms.push((sign, Term::Number(*value), token.column));
+                expect_term = false;
+            }
+            (TokenKind::Identifier(name), true) => {
+                terms.push((sign, Term::Symbol(name.clone()), token.column));
+                expect_term = false;
+            }
+            (TokenKind::Minus, true) if terms.is_empty() => sign = -1,
+            (TokenKind::Plus, false) => {
+                sign = 1;
+                expect_term = true;
+            }
+            (TokenKind::Minus, false) => {
+                sign = -1;
+                expect_term = true;
+            }
+            _ => {
+                return Err((
+                    token.column,
+                    AssemblyErrorKind::Syntax("invalid expression".to_string()),
+                ))
+            }
+        }
+    }
+
+    if expect_term {
+        return Err((
+            column,
+            AssemblyErrorKind::Syntax("expected a value".to_string()),
+        ));
+    }
+
+    Ok(Expression {
+        terms,
+        column,
+        long,
+    })
+}
+
+/**
+ * Parses an operand: a register, a special name (`I`, `DT`, `[I]`...) or a value
+ */
+fn parse_operand(tokens: &[Token]) -> Spanned<Operand> {
+    let kinds: Vec<&TokenKind> = tokens.iter().map(|token| &token.kind).collect();
+
+    match kinds.as_slice() {
+        [TokenKind::Identifier(name)] => {
+            if let Some(register) = parse_register(name) {
+                return Ok(Operand::Register(register));
+            }
+
+            let operand = match name.to_uppercase().as_str() {
+                "I" => Operand::I,
+                "DT" => Operand::Dt,
+                "ST" => Operand::St,
+                "K" => Operand::K,
+                "F" => Operand::F,
+                "HF" => Operand::Hf,
+                "B" => Operand::B,
+                "R" => Operand::R,
+                _ => Operand::Value(parse_expression(tokens)?),
+            };
+
+            Ok(operand)
+        }
+        [TokenKind::OpenBracket, TokenKind::Identifier(name), TokenKind::CloseBracket]
+            if name.eq_ignore_ascii_case("I") =>
+        {
+            Ok(Operand::IndirectI)
+        }
+        [TokenKind::Identifier(x), TokenKind::Minus, TokenKind::Identifier(y)] => {
+            match (parse_register(x), parse_register(y)) {
+                (Some(x), Some(y)) => Ok(Operand::RegisterRange(x, y)),
+                _ => Ok(Operand::Value(parse_expression(tokens)?)),
+            }
+        }
+        _ => Ok(Operand::Value(parse_expression(tokens)?)),
+    }
+}
+
+/**
+ * Splits the tokens by the commas
+ */
+fn split_operands(tokens: &[Token]) -> Vec<&[Token]> {
+    if tokens.is_empty() {
+        return Vec::new();
+    }
+
+    tokens
+        .split(|token| token.kind == TokenKind::Comma)
+        .collect()
+}
+
+/// A `db` item: a value or the bytes of a string
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Datum {
+    Value(Expression),
+    Text(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Body {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Bytes(Vec<Datum>),
+    Words(Vec<Expression>),
+}
+
+/// Where a statement was written
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Location {
+    file: String,
+    line: usize,
+    column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Statement {
+    location: Location,
+    address: u32,
+    body: Body,
+}
+
+impl Statement {
+    /**
+     * Bytes the statement takes, known before solving the symbols
+     */
+    fn size(&self) -> u32 {
+        match &self.body {
+            Body::Instruction { mnemonic, operands } => {
+                match (mnemonic.to_uppercase().as_str(), operands.as_slice()) {
+                    ("LD", [Operand::I, Operand::Value(value)]) if value.long => 4,
+                    _ => 2,
+                }
+            }
+            Body::Bytes(data) => data
+                .iter()
+                .map(|datum| match datum {
+                    Datum::Value(_) => 1,
+                    Datum::Text(text) => text.len() as u32,
+                })
+                .sum(),
+            Body::Words(words) => 2 * words.len() as u32,
+        }
+    }
+}
+
+/// Assembles the README mnemonics into a ROM.
+///
+/// ```text
+/// SPEED equ 2            ; constant
+/// include "sprites.asm"  ; relative to the including file
+///
+/// start:
+///     LD V0, SPEED
+///     CALL draw
+///     JP start
+///
+/// org 0x300
+/// draw:
+///     DRW V0, V1, 5
+///     RET
+///
+/// sprite:
+///     db 0xF0, 0x90, 0xF0, "text"
+///     dw 0x1234, start
+/// ```
+///
+/// Mnemonics, registers and directives are case insensitive, while the
+/// labels and constants are not. **LD I, NNNN** (XO-CHIP) is chosen when the
+/// value is marked with `LONG`, or when it is above 0xFFF and only uses the
+/// constants and labels defined before it
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembler {
+    load_address: u16,
+    statements: Vec<Statement>,
+    symbols: HashMap<String, i64>,
+    address: u32,
+    includes: Vec<PathBuf>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::with_load_address(DEFAULT_LOAD_ADDRESS)
+    }
+
+    /**
+     * Creates an assembler whose program starts on the given address
+     */
+    pub fn with_load_address(load_address: u16) -> Self {
+        Self {
+            load_address,
+            statements: Vec::new(),
+            symbols: HashMap::new(),
+            address: load_address as u32,
+            includes: Vec::new(),
+        }
+    }
+
+    /**
+     * Assembles the source and returns the ROM bytes, from the load address
+     * to the last assembled byte. The includes are relative to the current directory
+     */
+    pub fn assemble(mut self, source: &str) -> Result<Vec<u8>, AssemblyError> {
+        self.layout(source, INPUT_NAME, Path::new("."))?;
+        self.emit()
+    }
+
+    /**
+     * Assembles a source file. The includes are relative to its directory
+     */
+    pub fn assemble_file<P: AsRef<Path>>(self, path: P) -> Result<Vec<u8>, AssemblyError> {
+        let path = path.as_ref();
+
+        let source = fs::read_to_string(path).map_err(|err| AssemblyError {
+            file: path.display().to_string(),
+            line: 0,
+            column: 0,
+            kind: AssemblyErrorKind::Include(err.to_string()),
+        })?;
+
+        self.assemble_source(&source, path)
+    }
+
+    /**
+     * Assembles the source of a file which has already been read. The
+     * errors name the file, and the includes are relative to its directory
+     */
+    pub fn assemble_source(mut self, source: &str, path: &Path) -> Result<Vec<u8>, AssemblyError> {
+        self.includes
+            .push(path.canonicalize().unwrap_or(path.into()));
+        self.layout(
+            source,
+            &path.display().to_string(),
+            path.parent().unwrap_or(Path::new(".")),
+        )?;
+        self.emit()
+    }
+
+    /**
+     * First pass: parses every line, gives an address to each statement,
+     * and defines the labels and the constants
+     */
+    fn layout(&mut self, source: &str, file: &str, directory: &Path) -> Result<(), AssemblyError> {
+        for (index, line) in source.lines().enumerate() {
+            let error = |(column, kind)| AssemblyError {
+                file: file.to_string(),
+                line: index + 1,
+                column,
+                kind,
+            };
+
+            let tokens = tokenize(line).map_err(error)?;
+
+            // include "file"
+            if let [Token {
+                kind: TokenKind::Identifier(directive),
+                ..
+            }, rest @ ..] = tokens.as_slice()
+            {
+                if directive.eq_ignore_ascii_case("include") {
+                    match rest {
+                        [Token {
+                            kind: TokenKind::Str(path),
+                            column,
+                        }] => self.include(&directory.join(path), *column, error)?,
+                        _ => {
+                            return Err(error((
+                                tokens[0].column,
+                                AssemblyErrorKind::Syntax("include expects a \"file\"".to_string()),
+                            )))
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            let location = Location {
+                file: file.to_string(),
+                line: index + 1,
+                column: tokens.first().map_or(1, |token| token.column),
+            };
+
+            self.layout_line(&tokens, location).map_err(error)?;
+        }
+
+        Ok(())
+    }
+
+    fn layout_line(&mut self, tokens: &[Token], location: Location) -> Spanned<()> {
+        let mut tokens = tokens;
+
+        // label:
+        if let [Token {
+            kind: TokenKind::Identifier(name),
+            column,
+        }, Token {
+            kind: TokenKind::Colon,
+            ..
+        }, rest @ ..] = tokens
+        {
+            self.define(name, self.address as i64, *column)?;
+            tokens = rest;
+        }
+
+        let Some((first, rest)) = tokens.split_first() else {
+            return Ok(());
+        };
+
+        let TokenKind::Identifier(word) = &first.kind else {
+            return Err((
+                first.column,
+                AssemblyErrorKind::Syntax("expected a mnemonic or a directive".to_string()),
+            ));
+        };
+
+        // NAME equ value
+        if let Some(Token {
+            kind: TokenKind::Identifier(directive),
+            ..
+        }) = rest.first()
+        {
+            if directive.eq_ignore_ascii_case("equ") {
+                let value = parse_expression(&rest[1..])?.evaluate(&self.symbols)?;
+                return self.define(word, value, first.column);
+            }
+        }
+
+        let location = Location {
+            column: first.column,
+            ..location
+        };
+
+        let mut body = match word.to_lowercase().as_str() {
+            "org" => {
+                let address = parse_expression(rest)?.evaluate_in_range(&self.symbols, 0xFFFF)?;
+
+                if address < self.load_address as u32 {
+                    return Err((
+                        first.column,
+                        AssemblyErrorKind::OrgBeforeLoadAddress(address as u16),
+                    ));
+                }
+
+                self.address = address;
+                return Ok(());
+            }
+            "db" => Body::Bytes(
+                split_operands(rest)
+                    .into_iter()
+                    .map(|tokens| match tokens {
+                        [Token {
+                            kind: TokenKind::Str(text),
+                            ..
+                        }] => Ok(Datum::Text(text.as_bytes().to_vec())),
+                        _ => Ok(Datum::Value(parse_expression(tokens)?)),
+                    })
+                    .collect::<Spanned<_>>()?,
+            ),
+            "dw" => Body::Words(
+                split_operands(rest)
+                    .into_iter()
+                    .map(parse_expression)
+                    .collect::<Spanned<_>>()?,
+            ),
+            _ => Body::Instruction {
+                mnemonic: word.clone(),
+                operands: split_operands(rest)
+                    .into_iter()
+                    .map(parse_operand)
+                    .collect::<Spanned<_>>()?,
+            },
+        };
+
+        // The size is chosen now, so only the symbols defined before can
+        // make LD I take the long form
+        if let Body::Instruction { mnemonic, operands } = &mut body {
+            if let ("LD", [Operand::I, Operand::Value(value)]) =
+                (mnemonic.to_uppercase().as_str(), operands.as_mut_slice())
+            {
+                value.long |= value
+                    .evaluate(&self.symbols)
+                    .is_ok_and(|value| value > 0xFFF);
+            }
+        }
+
+        let statement = Statement {
+            location,
+            address: self.address,
+            body,
+        };
+
+        self.address += statement.size();
+
+        if self.address > 0x10000 {
+            return Err((first.column, AssemblyErrorKind::AddressOverflow));
+        }
+
+        self.statements.push(statement);
+
+        Ok(())
+    }
+
+    /**
+     * Defines a label or a constant
+     */
+    fn define(&mut self, name: &str, value: i64, column: usize) -> Spanned<()> {
+        if self.symbols.insert(name.to_string(), value).is_some() {
+            return Err((column, AssemblyErrorKind::DuplicateSymbol(name.to_string())));
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Lays out the lines of another file, as if they were written here.
+     * `error` locates the errors of the include line itself
+     */
+    fn include(
+        &mut self,
+        path: &Path,
+        column: usize,
+        error: impl Fn((usize, AssemblyErrorKind)) -> AssemblyError,
+    ) -> Result<(), AssemblyError> {
+        let name = path.display().to_string();
+        let source = fs::read_to_string(path)
+            .map_err(|err| error((column, AssemblyErrorKind::Include(err.to_string()))))?;
+        let canonical = path.canonicalize().unwrap_or(path.into());
+
+        if self.includes.contains(&canonical) {
+            return Err(error((column, AssemblyErrorKind::IncludeCycle(name))));
+        }
+
+        self.includes.push(canonical);
+        let result = self.layout(&source, &name, path.parent().unwrap_or(Path::new(".")));
+        self.includes.pop();
+
+        result
+    }
+
+    /**
+     * Second pass: encodes each statement on its address, now that every symbol is known
+     */
+    fn emit(self) -> Result<Vec<u8>, AssemblyError> {
+        let start = self.load_address as usize;
+        let end = self
+            .statements
+            .iter()
+            .map(|statement| (statement.address + statement.size()) as usize)
+            .max()
+            .unwrap_or(start);
+
+        let mut rom = vec![0x0; end - start];
+        let mut written = vec![false; end - start];
+
+        for statement in &self.statements {
+            let error = |(column, kind)| AssemblyError {
+                file: statement.location.file.clone(),
+                line: statement.location.line,
+                column,
+                kind,
+            };
+
+            let bytes = self.encode(statement).map_err(error)?;
+            let offset = statement.address as usize - start;
+
+            for (index, byte) in bytes.into_iter().enumerate() {
+                if written[offset + index] {
+                    let address = (statement.address as usize + index) as u16;
+                    return Err(error((
+                        statement.location.column,
+                        AssemblyErrorKind::Overlap(address),
+                    )));
+                }
+
+                rom[offset + index] = byte;
+                written[offset + index] = true;
+            }
+        }
+
+        Ok(rom)
+    }
+
+    fn encode(&self, statement: &Statement) -> Spanned<Vec<u8>> {
+        let symbols = &self.symbols;
+
+        match &statement.body {
+            Body::Bytes(data) => {
+                let mut bytes = Vec::new();
+
+                for datum in data {
+                    match datum {
+                        Datum::Value(value) => {
+                            bytes.push(value.evaluate_in_range(symbols, 0xFF)? as u8)
+                        }
+                        Datum::Text(text) => bytes.extend(text),
+                    }
+                }
+
+                Ok(bytes)
+            }
+            Body::Words(words) => {
+                let mut bytes = Vec::new();
+
+                for word in words {
+                    bytes.extend((word.evaluate_in_range(symbols, 0xFFFF)? as u16).to_be_bytes());
+                }
+
+                Ok(bytes)
+            }
+            Body::Instruction { mnemonic, operands } => {
+                // The errors of a value point to it, the other ones to the mnemonic
+                let instruction =
+                    encode_instruction(mnemonic, operands, symbols).map_err(|(column, kind)| {
+                        (column.unwrap_or(statement.location.column), kind)
+                    })?;
+
+                Ok(instruction.encode())
+            }
+        }
+    }
+}
+
+impl Default for Assembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * Turns a mnemonic and its operands into an instruction
+ */
+fn encode_instruction(
+    mnemonic: &str,
+    operands: &[Operand],
+    symbols: &HashMap<String, i64>,
+) -> Result<Instruction, (Option<usize>, AssemblyErrorKind)> {
+    use Operand::*;
+
+    let value = |expression: &Expression, max: u32| {
+        expression
+            .evaluate_in_range(symbols, max)
+            .map_err(|(column, kind)| (Some(column), kind))
+    };
+    let nibble = |expression| value(expression, 0xF).map(|value| value as u8);
+    let byte = |expression| value(expression, 0xFF).map(|value| value as u8);
+    let address = |expression| value(expression, 0xFFF).map(|value| value as u16);
+
+    let upper = mnemonic.to_uppercase();
+
+    let instruction = match (upper.as_str(), operands) {
+        ("CLS", []) => Instruction::Cls,
+        ("RET", []) => Instruction::Ret,
+        ("HALT", []) => Instruction::Halt,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::Low,
+        ("HIGH", []) => Instruction::High,
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("AUDIO", []) => Instruction::Audio,
+        ("SCD", [Value(n)]) => Instruction::ScrollDown(nibble(n)?),
+        ("SCU", [Value(n)]) => Instruction::ScrollUp(nibble(n)?),
+        ("PLANE", [Value(n)]) => Instruction::Plane(nibble(n)?),
+        ("JP", [Value(a)]) => Instruction::Jp(address(a)?),
+        ("JP", [Register(0), Value(a)]) => Instruction::JpV0(address(a)?),
+        // BXNN of the chip48 quirk: the register is the first digit of the address
+        ("JP", [Register(x), Value(a)]) => match address(a)? {
+            address if address >> 8 == *x as u16 => Instruction::JpV0(address),
+            _ => return Err((None, AssemblyErrorKind::InvalidOperands(upper))),
+        },
+        ("CALL", [Value(a)]) => Instruction::Call(address(a)?),
+        ("SE", [Register(x), Value(nn)]) => Instruction::SeByte(*x, byte(nn)?),
+        ("SE", [Register(x), Register(y)]) => Instruction::SeRegister(*x, *y),
+        ("SNE", [Register(x), Value(nn)]) => Instruction::SneByte(*x, byte(nn)?),
+        ("SNE", [Register(x), Register(y)]) => Instruction::SneRegister(*x, *y),
+        ("SAVE", [RegisterRange(x, y)]) => Instruction::SaveRange(*x, *y),
+        ("LOAD", [RegisterRange(x, y)]) => Instruction::LoadRange(*x, *y),
+        ("LD", [Register(x), Value(nn)]) => Instruction::LdByte(*x, byte(nn)?),
+        ("LD", [Register(x), Register(y)]) => Instruction::LdRegister(*x, *y),
+        ("LD", [I, Value(a)]) if a.long => Instruction::LdLongI(value(a, 0xFFFF)? as u16),
+        ("LD", [I, Value(a)]) => Instruction::LdI(address(a)?),
+        ("LD", [Register(x), Dt]) => Instruction::LdFromDelayTimer(*x),
+        ("LD", [Register(x), K]) => Instruction::LdKey(*x),
+        ("LD", [Dt, Register(x)]) => Instruction::LdDelayTimer(*x),
+        ("LD", [St, Register(x)]) => Instruction::LdSoundTimer(*x),
+        ("LD", [F, Register(x)]) => Instruction::LdFont(*x),
+        ("LD", [Hf, Register(x)]) => Instruction::LdBigFont(*x),
+        ("LD", [B, Register(x)]) => Instruction::LdBcd(*x),
+        ("LD", [IndirectI, Register(x)]) => Instruction::StoreRegisters(*x),
+        ("LD", [Register(x), IndirectI]) => Instruction::LoadRegisters(*x),
+        ("LD", [R, Register(x)]) => Instruction::StoreFlags(*x),
+        ("LD", [Register(x), R]) => Instruction::LoadFlags(*x),
+        ("ADD", [Register(x), Value(nn)]) => Instruction::AddByte(*x, byte(nn)?),
+        ("ADD", [Register(x), Register(y)]) => Instruction::AddRegister(*x, *y),
+        ("ADD", [I, Register(x)]) => Instruction::AddI(*x),
+        ("OR", [Register(x), Register(y)]) => Instruction::Or(*x, *y),
+        ("AND", [Register(x), Register(y)]) => Instruction::And(*x, *y),
+        ("XOR", [Register(x), Register(y)]) => Instruction::Xor(*x, *y),
+        ("SUB", [Register(x), Register(y)]) => Instruction::Sub(*x, *y),
+        ("SUBN", [Register(x), Register(y)]) => Instruction::Subn(*x, *y),
+        // Without Vy, Vx is shifted whatever the quirks are
+        ("SHR", [Register(x)]) => Instruction::Shr(*x, *x),
+        ("SHR", [Register(x), Register(y)]) => Instruction::Shr(*x, *y),
+        ("SHL", [Register(x)]) => Instruction::Shl(*x, *x),
+        ("SHL", [Register(x), Register(y)]) => Instruction::Shl(*x, *y),
+        ("RND", [Register(x), Value(nn)]) => Instruction::Rnd(*x, byte(nn)?),
+        ("DRW", [Register(x), Register(y), Value(n)]) => Instruction::Drw(*x, *y, nibble(n)?),
+        ("SKP", [Register(x)]) => Instruction::Skp(*x),
+        ("SKNP", [Register(x)]) => Instruction::Sknp(*x),
+        ("PITCH", [Register(x)]) => Instruction::Pitch(*x),
+        _ if MNEMONICS.contains(&upper.as_str()) => {
+            return Err((None, AssemblyErrorKind::InvalidOperands(upper)))
+        }
+        _ => {
+            return Err((
+                None,
+                AssemblyErrorKind::UnknownMnemonic(mnemonic.to_string()),
+            ))
+        }
+    };
+
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{Assembler, AssemblyError, AssemblyErrorKind};
+    use crate::{disasm::Disassembly, rom::Rom, DEFAULT_LOAD_ADDRESS};
+
+    #[test]
+    fn test_assemble_instructions() {
+        let rom = Assembler::new()
+            .assemble(
+                "
+                start:  LD V0, 0x01     ; comment
+                        ld va, 255
+                        LD I, sprite
+                        LD I, LONG 0x0ABC
+                        DRW V0, V1, 5
+                        SAVE V1 - V2
+                        SHR V3
+                        ADD V0, -1
+                        JP start
+                sprite: db 0xF0, 0b1001_0000, \"AB\"
+                        dw sprite + 1
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(
+            rom,
+            vec![
+                0x60, 0x01, 0x6A, 0xFF, 0xA2, 0x14, 0xF0, 0x00, 0x0A, 0xBC, 0xD0, 0x15, 0x51, 0x22,
+                0x83, 0x36, 0x70, 0xFF, 0x12, 0x00, 0xF0, 0x90, 0x41, 0x42, 0x02, 0x15,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_long_load() {
+        let assemble = |source: &str| Assembler::new().assemble(source).unwrap();
+
+        // 4 digits alone do not make it long
+        assert_eq!(assemble("LD I, 0x0ABC"), vec![0xAA, 0xBC]);
+        assert_eq!(assemble("LD I, 0x1234"), vec![0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(
+            assemble("BIG equ 0x800\nLD I, BIG + BIG"),
+            vec![0xF0, 0x00, 0x10, 0x00]
+        );
+
+        // A label defined after it can not choose the size
+        let error = Assembler::new()
+            .assemble("LD I, far\norg 0x1000\nfar: CLS")
+            .unwrap_err();
+        assert_eq!(
+            error.kind,
+            AssemblyErrorKind::OutOfRange {
+                value: 0x1000,
+                max: 0xFFF
+            }
+        );
+    }
+
+    #[test]
+    fn test_assemble_org_and_constants() {
+        let rom = Assembler::new()
+            .assemble(
+                "
+                SPEED equ 3
+                DOUBLE equ SPEED + SPEED
+                    CALL draw
+                org 0x206
+                draw:
+                    LD V0, DOUBLE
+                    RET
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(
+            rom,
+            vec![0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0x60, 0x06, 0x00, 0xEE]
+        );
+        assert!(Rom::from_bytes(rom, DEFAULT_LOAD_ADDRESS).is_ok());
+    }
+
+    #[test]
+    fn test_assemble_diagnostics() {
+        let error = |source: &str| Assembler::new().assemble(source).unwrap_err();
+
+        assert_eq!(
+            error("CLS\n  LD V0, 256"),
+            AssemblyError {
+                file: "<input>".to_string(),
+                line: 2,
+                column: 10,
+                kind: AssemblyErrorKind::OutOfRange {
+                    value: 256,
+                    max: 0xFF
+                },
+            }
+        );
+        assert_eq!(
+            error("LD V0, 0x7FFFFFFFFFFFFFFF + 1"),
+            AssemblyError {
+                file: "<input>".to_string(),
+                line: 1,
+                column: 29,
+                kind: AssemblyErrorKind::OutOfRange {
+                    value: i64::MAX,
+                    max: 0xFFFF
+                },
+            }
+        );
+        assert_eq!(
+            error("  JP nowhere").kind,
+            AssemblyErrorKind::UnknownSymbol("nowhere".to_string())
+        );
+        assert_eq!(error("  JP nowhere").column, 6);
+        assert_eq!(
+            error("MOV V0, V1").kind,
+            AssemblyErrorKind::UnknownMnemonic("MOV".to_string())
+        );
+        assert_eq!(
+            error("LD DT, 5").kind,
+            AssemblyErrorKind::InvalidOperands("LD".to_string())
+        );
+        // BXNN only reaches the addresses which start with X
+        assert_eq!(
+            error("JP V3, 0x200").kind,
+            AssemblyErrorKind::InvalidOperands("JP".to_string())
+        );
+        assert_eq!(
+            error("a: CLS\na: CLS").kind,
+            AssemblyErrorKind::DuplicateSymbol("a".to_string())
+        );
+        assert_eq!(
+            error("org 0x100").kind,
+            AssemblyErrorKind::OrgBeforeLoadAddress(0x100)
+        );
+        assert_eq!(
+            error("CLS\norg 0x200\nCLS").kind,
+            AssemblyErrorKind::Overlap(0x200)
+        );
+        assert_eq!(error("LD V0, 0x1G").column, 8);
+    }
+
+    #[test]
+    fn test_assemble_includes() {
+        let directory = std::env::temp_dir().join("chip8-asm-include-test");
+        fs::create_dir_all(&directory).unwrap();
+
+        fs::write(directory.join("main.asm"), "CALL sub\ninclude \"sub.asm\"").unwrap();
+        fs::write(directory.join("sub.asm"), "sub: RET").unwrap();
+        fs::write(directory.join("cycle.asm"), "include \"cycle.asm\"").unwrap();
+
+        assert_eq!(
+            Assembler::new()
+                .assemble_file(directory.join("main.asm"))
+                .unwrap(),
+            vec![0x22, 0x02, 0x00, 0xEE]
+        );
+        assert!(matches!(
+            Assembler::new()
+                .assemble_file(directory.join("cycle.asm"))
+                .unwrap_err()
+                .kind,
+            AssemblyErrorKind::IncludeCycle(_)
+        ));
+    }
+
+    #[test]
+    fn test_disassembly_can_be_assembled() {
+        let rom: Vec<u8> = (0x0..=0xFFu8)
+            .flat_map(|byte| [byte, byte ^ 0x5A])
+            .collect();
+        let listing =
+            Disassembly::new(&Rom::from_bytes(rom.clone(), DEFAULT_LOAD_ADDRESS).unwrap());
+
+        // Only the mnemonics of the listing
+        let source: String = listing
+            .to_string()
+            .lines()
+            .map(|line| format!("{}\n", &line[20..]))
+            .collect();
+
+        assert_eq!(Assembler::new().assemble(&source).unwrap(), rom);
+    }
+}
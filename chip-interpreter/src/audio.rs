@@ -0,0 +1,77 @@
+/// The XO-CHIP audio pattern is 16 bytes long: 128 one bit samples
+pub const AUDIO_PATTERN_SIZE: usize = 16;
+/// Pitch which plays the pattern at 4000 samples per second
+pub const DEFAULT_PITCH: u8 = 64;
+
+/// XO-CHIP sound. While the sound timer is not zero, the frontend plays the
+/// pattern bit by bit (most significant bit first) in a loop, at the
+/// rate given by the pitch.
+///
+/// Until a game loads a pattern (**AUDIO**), frontends play their usual beep
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPattern {
+    pattern: Option<[u8; AUDIO_PATTERN_SIZE]>,
+    pitch: u8,
+}
+
+impl AudioPattern {
+    pub fn new() -> Self {
+        Self {
+            pattern: None,
+            pitch: DEFAULT_PITCH,
+        }
+    }
+
+    /**
+     * Returns the loaded pattern, if any
+     */
+    pub fn pattern(&self) -> Option<&[u8; AUDIO_PATTERN_SIZE]> {
+        self.pattern.as_ref()
+    }
+
+    pub fn set_pattern(&mut self, pattern: [u8; AUDIO_PATTERN_SIZE]) {
+        self.pattern = Some(pattern);
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /**
+     * Sets the pitch (**PITCH := Vx**)
+     */
+    pub fn set_pitch(&mut self, pitch: u8) {
+        self.pitch = pitch;
+    }
+
+    /**
+     * Samples per second at which the pattern is played:
+     * 4000 * 2 ^ ((pitch - 64) / 48)
+     */
+    pub fn sample_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((self.pitch as f64 - DEFAULT_PITCH as f64) / 48.0)
+    }
+}
+
+impl Default for AudioPattern {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AudioPattern;
+
+    #[test]
+    fn test_sample_rate() {
+        let mut audio = AudioPattern::new();
+
+        assert_eq!(audio.sample_rate(), 4000.0);
+
+        // 48 steps up doubles the rate
+        audio.set_pitch(112);
+        assert_eq!(audio.sample_rate(), 8000.0);
+    }
+}
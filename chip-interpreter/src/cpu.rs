@@ -1,82 +1,271 @@
 /// Opcodes -> https://en.wikipedia.org/wiki/CHIP-8
-use crate::memory::{Memory, Stack};
+use std::io;
+
+use crate::{
+    audio::{AudioPattern, AUDIO_PATTERN_SIZE},
+    display::Framebuffer,
+    error::{EmulatorError, FaultKind},
+    flags::{FlagStorage, N_RPL_FLAGS},
+    instruction::{Instruction, LONG_LOAD_OPCODE},
+    keypad::{Keypad, N_KEYS},
+    memory::{
+        Memory, Stack, BIG_FONT_ADDRESS, BIG_FONT_GLYPH_SIZE, FONT_ADDRESS, FONT_GLYPH_SIZE,
+        MAX_STACK_SIZE, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE,
+    },
+    quirks::Quirks,
+    rng::{Rng, SeededRng},
+    rom::{Rom, RomError},
+    savestate::SaveState,
+};
 
 const N_CPU_REGISTERS: u8 = 16;
-const ROM_SIZE: usize = 4096 - 0x200;
+/// ~660 instructions per second when the timers tick at 60 Hz
+pub const INSTRUCTIONS_PER_TICK: u32 = 11;
+/// Version of how the machine runs the programs. It goes up with any change
+/// which may change the frames of a run (an instruction, a quirk, the
+/// timers...), so the movies recorded before are not replayed
+pub const CORE_VERSION: u32 = 2;
+
+/// Result of executing a single instruction
+#[derive(Debug, PartialEq, Eq)]
+enum Cycle {
+    Executed,
+    WaitingForKey,
+    WaitingForDisplay,
+    Halted,
+}
+
+/// What happened on a single `step`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepEvent {
+    /// Raw opcode executed. None if nothing was executed, because
+    /// the CPU was waiting for a key or halted
+    pub opcode: Option<u16>,
+    /// Decoded instruction
+    pub instruction: Option<Instruction>,
+    pub pc_before: u16,
+    pub pc_after: u16,
+    /// Address of the instruction skipped by a skip instruction
+    pub skipped: Option<u16>,
+    /// The screen was cleared or drawn
+    pub display_changed: bool,
+    pub waiting_for_key: bool,
+    /// A sprite has already been drawn on this frame and the
+    /// `display_wait` quirk is set, so the draw waits for the next frame
+    pub waiting_for_display: bool,
+    pub halted: bool,
+}
+
+impl StepEvent {
+    /**
+     * True if the CPU can not go on until something happens
+     * (a key, a timers tick, or nothing at all if it is halted)
+     */
+    pub fn is_blocked(&self) -> bool {
+        self.waiting_for_key || self.waiting_for_display || self.halted
+    }
+}
 
-type Opcode = (u8, u8, u8, u8);
+/// State of the **LD Vx, K** instruction. As on the COSMAC VIP, the key
+/// is stored on Vx once it has been pressed and released
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct KeyWait {
+    register: u8,
+    pressed: Option<u8>,
+}
 
 #[derive(Debug)]
-#[allow(dead_code)]
+#[allow(dead_code, clippy::upper_case_acronyms)]
 pub struct CPU {
     pub registers: Vec<u8>,
+    pub i_register: u16,
     memory: Memory,
     stack_pointer: u16,
     stack: Stack,
+    display: Framebuffer,
+    quirks: Quirks,
+    drawn_this_frame: bool,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    instructions_per_tick: u32,
+    keypad: Keypad,
+    key_wait: Option<KeyWait>,
+    rng: Box<dyn Rng>,
+    halted: bool,
+    rpl_flags: [u8; N_RPL_FLAGS],
+    flag_storage: Option<Box<dyn FlagStorage>>,
+    /// The last save of the flags which failed, until the host takes it
+    flag_storage_error: Option<io::Error>,
+    skipped: Option<u16>,
+    display_changed: bool,
+    audio: AudioPattern,
 }
 
 #[allow(dead_code, unused_variables)]
 impl CPU {
-    pub fn new() -> Self {
+    /**
+     * Creates a CPU with the given quirks, whose random numbers
+     * are seeded with the current time
+     */
+    pub fn new(quirks: Quirks) -> Self {
+        Self::with_rng(quirks, Box::new(SeededRng::from_time()))
+    }
+
+    /**
+     * Creates a CPU whose random numbers are always the same for a given seed
+     */
+    pub fn with_seed(quirks: Quirks, seed: u64) -> Self {
+        Self::with_rng(quirks, Box::new(SeededRng::new(seed)))
+    }
+
+    /**
+     * Creates a CPU which takes the random numbers from the given generator
+     */
+    pub fn with_rng(quirks: Quirks, rng: Box<dyn Rng>) -> Self {
         Self {
             registers: vec![0x0; N_CPU_REGISTERS as usize],
-            memory: Memory::new(),
+            i_register: 0x0,
+            memory: Memory::with_size(Self::memory_size(&quirks)),
             stack: Stack::new(),
             stack_pointer: 0x0,
+            display: Framebuffer::new(),
+            quirks,
+            drawn_this_frame: false,
+            delay_timer: 0x0,
+            sound_timer: 0x0,
+            instructions_per_tick: INSTRUCTIONS_PER_TICK,
+            keypad: Keypad::new(),
+            key_wait: None,
+            rng,
+            halted: false,
+            rpl_flags: [0x0; N_RPL_FLAGS],
+            flag_storage: None,
+            flag_storage_error: None,
+            skipped: None,
+            display_changed: false,
+            audio: AudioPattern::new(),
         }
     }
 
     /**
-     * Return a buffer to allocate the ROM
+     * The XO-CHIP has 64 KiB of memory, the other platforms 4 KiB
      */
-    pub fn create_buffer() -> [u8; ROM_SIZE] {
-        [0x0; ROM_SIZE]
+    fn memory_size(quirks: &Quirks) -> usize {
+        if quirks.xo_chip {
+            XO_CHIP_MEMORY_SIZE
+        } else {
+            MEMORY_SIZE
+        }
     }
 
     /**
-     * Writes the given opcode on memory
+     * Returns the screen, so a frontend can read its pixels
+     */
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    /**
+     * Returns how many addresses are stored on the stack
+     */
+    pub fn stack_pointer(&self) -> u16 {
+        self.stack_pointer
+    }
+
+    /**
+     * Changes how many addresses of the stack are in use (0 - 16)
+     */
+    pub fn set_stack_pointer(&mut self, stack_pointer: u16) -> Result<(), FaultKind> {
+        if stack_pointer as usize > self.stack.entries().len() {
+            return Err(FaultKind::StackOverflow);
+        }
+
+        self.stack_pointer = stack_pointer;
+
+        Ok(())
+    }
+
+    /**
+     * Sets where the RPL user flags are persisted, and loads them
      */
-    fn set_opcode(&mut self, opcode: u16) {
-        self.memory.write(opcode);
+    pub fn set_flag_storage(&mut self, mut storage: Box<dyn FlagStorage>) {
+        self.rpl_flags = storage.load();
+        self.flag_storage = Some(storage);
     }
 
     /**
-     * Load the Rom to the memory
+     * Returns the error of the last save of the RPL flags which failed, if
+     * any since the last call. The game goes on anyway, so the host decides
+     * how to report it
      */
-    pub fn load_rom(&mut self, rom: &[u8]) {
-        self.memory.memcpy(rom);
+    pub fn take_flag_storage_error(&mut self) -> Option<io::Error> {
+        self.flag_storage_error.take()
+    }
+
+    pub fn rpl_flags(&self) -> &[u8; N_RPL_FLAGS] {
+        &self.rpl_flags
     }
 
-    // CPU OPCODE DECODING
-    // 0X80F12
-    // 8 -> First nibble (4bits)
-    // 0 -> Second nibble  (x) -> Register to look
-    // F -> Third nibble   (y) -> Register to look
-    // 1 -> A 4 bit number (n) -> value to use ?
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    /**
+     * Changes the quirks, even while a game is running.
+     * Switching from or to XO-CHIP resizes the memory
+     */
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.memory.resize(Self::memory_size(&quirks));
+        self.quirks = quirks;
+    }
 
     /**
-      Parses the given opcode and returns an
-      **u8 tuple** with each nibble
-    */
-    fn parse_opcode(&mut self) -> (u8, u8, u8, u8) {
-        let opcode = self.memory.read(2);
+     * Returns the XO-CHIP audio pattern and pitch
+     */
+    pub fn audio(&self) -> &AudioPattern {
+        &self.audio
+    }
 
-        let c = ((opcode & 0xF000) >> 12) as u8;
-        let x = ((opcode & 0x0F00) >> 8) as u8;
-        let y = ((opcode & 0x00F0) >> 4) as u8;
-        let d = ((opcode & 0x000F) >> 0) as u8;
+    /**
+     * Writes the given opcode on memory
+     */
+    fn set_opcode(&mut self, opcode: u16) -> Result<(), FaultKind> {
+        self.memory.write(opcode)
+    }
 
-        (c, x, y, d)
+    /**
+     * Load the Rom to the memory, and point the program counter to it
+     */
+    pub fn load_rom(&mut self, rom: &Rom) -> Result<(), RomError> {
+        rom.check_fits(self.memory.size())?;
+
+        self.memory
+            .memcpy(rom.load_address(), rom.data())
+            .map_err(|_| RomError::TooLarge {
+                size: rom.len(),
+                max: self.memory.size() - rom.load_address() as usize,
+            })?;
+        self.memory.read_pc = rom.load_address();
+
+        Ok(())
     }
 
     /**
      * Assign a value to a register. Vx = NN
      */
     fn set_value_to_register_operation(&mut self, register: u8, value: u8) {
-        if register > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {register}");
-        }
-
         self.registers[register as usize] = value;
     }
 
@@ -84,124 +273,115 @@ impl CPU {
      * Move to Vx, Xy. (**LD Vx, Vy**)
      */
     fn move_y_register_value_to_x_instruction(&mut self, x: u8, y: u8) {
-        if x > N_CPU_REGISTERS || y > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {x} or {y}");
-        }
-
         self.registers[x as usize] = self.registers[y as usize];
     }
 
     /**
-     * Performs the add operation
+     * Performs the add operation. VF is set to 1 on carry, otherwise 0
      */
-    fn add_operation(&mut self, x: u8, y: u8) -> bool {
-        println!("Add: V{x} += V{y}!");
-
-        let x_register = self.registers[x as usize];
-        let y_register = self.registers[y as usize];
-
-        // Cast to u16 to avoid panicking when attempting an overflow
-        if (x_register as u16 + y_register as u16) > 255 {
-            return true;
-        }
+    fn add_operation(&mut self, x: u8, y: u8) {
+        let (result, carry) =
+            self.registers[x as usize].overflowing_add(self.registers[y as usize]);
 
-        self.registers[x as usize] += y_register;
-
-        false
+        self.set_result_and_flag(x, result, carry);
     }
 
     /**
-     * Add a value to the Vx register
+     * Add a value to the Vx register. VF is not affected
      */
     fn add_value_to_register_operation(&mut self, register: u8, value: u8) {
-        if register > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {register}");
-        }
-
-        self.registers[register as usize] += value;
+        self.registers[register as usize] = self.registers[register as usize].wrapping_add(value);
     }
 
     /**
-     * Substract Vx - Vy
+     * Substract Vx - Vy. VF is set to 1 if there is no borrow, otherwise 0
      */
-    fn sub_operation(&mut self, x: u8, y: u8) -> bool {
-        let x_register = self.registers[x as usize];
-        let y_register = self.registers[y as usize];
-
-        // Cast to i16 to avoid panicking when attempting an overflow
-        if (x_register as i16 - y_register as i16) > 255
-            || (x_register as i16 - y_register as i16) < 0
-        {
-            return true;
-        }
+    fn sub_operation(&mut self, x: u8, y: u8) {
+        let (result, borrow) =
+            self.registers[x as usize].overflowing_sub(self.registers[y as usize]);
 
-        self.registers[x as usize] -= y_register;
-
-        false
+        self.set_result_and_flag(x, result, !borrow);
     }
 
     /**
-     * Substract Vx - Vy and save it on Vx
+     * Substract Vy - Vx and save it on Vx.
+     * VF is set to 1 if there is no borrow, otherwise 0
      */
     fn sub_vx_minus_vy_operation(&mut self, x: u8, y: u8) {
-        if self.registers[y as usize] < self.registers[x as usize] {
-            self.registers[15] = 1;
-            return;
-        }
+        let (result, borrow) =
+            self.registers[y as usize].overflowing_sub(self.registers[x as usize]);
 
-        self.registers[x as usize] = self.registers[y as usize] - self.registers[x as usize];
-        self.registers[15] = 0;
+        self.set_result_and_flag(x, result, !borrow);
+    }
+
+    /**
+     * Stores the result on Vx and then the flag on VF. VF is written
+     * the last one, so if Vx is VF the flag is kept, as the hardware does
+     */
+    fn set_result_and_flag(&mut self, x: u8, result: u8, flag: bool) {
+        self.registers[x as usize] = result;
+        self.registers[15] = flag as u8;
     }
 
     /**
      * Bitwise Or Operation among registers
      */
     fn bitwise_or_operation(&mut self, x: u8, y: u8) {
-        if x > N_CPU_REGISTERS || y > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {x} or {y}");
-        }
-
         self.registers[x as usize] |= self.registers[y as usize];
+        self.reset_vf_after_logic_operation();
     }
 
     /**
      * Bitwise And Operation among registers
      */
     fn bitwise_and_operation(&mut self, x: u8, y: u8) {
-        if x > N_CPU_REGISTERS || y > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {x} or {y}");
-        }
-
         self.registers[x as usize] &= self.registers[y as usize];
+        self.reset_vf_after_logic_operation();
     }
 
     /**
      * Bitwise Xor Operation among registers
      */
     fn bitwise_xor_operation(&mut self, x: u8, y: u8) {
-        if x > N_CPU_REGISTERS || y > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {x} or {y}");
+        self.registers[x as usize] ^= self.registers[y as usize];
+        self.reset_vf_after_logic_operation();
+    }
+
+    /**
+     * The COSMAC VIP leaves VF set to 0 after the logic operations
+     */
+    fn reset_vf_after_logic_operation(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.registers[15] = 0;
         }
+    }
 
-        self.registers[x as usize] ^= self.registers[y as usize];
+    /**
+     * The COSMAC VIP shifts Vy and stores the result on Vx,
+     * so Vy is copied into Vx before shifting
+     */
+    fn load_shift_source(&mut self, x: u8, y: u8) {
+        if self.quirks.shift_uses_vy {
+            self.registers[x as usize] = self.registers[y as usize];
+        }
     }
 
     /**
      * If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
      */
     fn bitwise_shr_operation(&mut self, x: u8) {
-        self.registers[15] = self.registers[x as usize] >> 1;
+        let value = self.registers[x as usize];
 
-        self.registers[x as usize] >>= 1;
+        self.set_result_and_flag(x, value >> 1, value & 1 == 1);
     }
 
     /**
      * If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
      */
     fn bitwise_shl_operation(&mut self, x: u8) {
-        self.registers[15] = (self.registers[x as usize] >> 7) & 1;
+        let value = self.registers[x as usize];
 
-        self.registers[x as usize] <<= 1;
+        self.set_result_and_flag(x, value << 1, value >> 7 == 1);
     }
 
     /**
@@ -214,37 +394,54 @@ impl CPU {
     /**
      * Performs the call operation
      */
-    fn call_operation(&mut self, address: u16) {
+    fn call_operation(&mut self, address: u16) -> Result<(), FaultKind> {
         // Store the current memory location on the stack.
-        self.stack.push(self.memory.read_pc, self.stack_pointer);
+        self.stack.push(self.memory.read_pc, self.stack_pointer)?;
 
         // Increment the stack pointer.
         self.stack_pointer += 1;
 
         // Jump to the called address
         self.memory.read_pc = address;
+
+        Ok(())
     }
 
     /**
      * Performs the ret operation
      */
-    fn ret_operation(&mut self) {
+    fn ret_operation(&mut self) -> Result<(), FaultKind> {
         // Decrement the stack pointer.
-        self.stack_pointer -= 1;
+        self.stack_pointer = self
+            .stack_pointer
+            .checked_sub(1)
+            .ok_or(FaultKind::StackUnderflow)?;
 
         // Retrieve and jump to the calling memory address from the stack.
-        self.memory.read_pc = self.stack.pop(self.stack_pointer);
+        self.memory.read_pc = self.stack.pop(self.stack_pointer)?;
+
+        Ok(())
+    }
+
+    /**
+     * Skips the next instruction, keeping its address for the `StepEvent`.
+     * On XO-CHIP, **LD I, NNNN** is 4 bytes long, so it is skipped whole
+     */
+    fn skip_next_instruction(&mut self) {
+        let pc = self.memory.read_pc;
+        let is_long_load = self.quirks.xo_chip
+            && self.memory.read_bytes(pc, 2) == Ok(&LONG_LOAD_OPCODE.to_be_bytes()[..]);
+
+        self.skipped = Some(pc);
+        self.memory.read_pc = pc.wrapping_add(if is_long_load { 4 } else { 2 });
     }
 
     /**
      * Skips the next instruction if Vx = NN
      */
     fn skip_next_instruction_if_equals(&mut self, register: u8, value: u8) {
-        if register > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {register}");
-        }
         if self.registers[register as usize] == value {
-            self.memory.read_pc += 2;
+            self.skip_next_instruction();
         }
     }
 
@@ -252,12 +449,8 @@ impl CPU {
      * Skips the next instruction if Vx != NN
      */
     fn skip_next_instruction_if_not_equals(&mut self, register: u8, value: u8) {
-        if register > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {register}");
-        }
-
         if self.registers[register as usize] != value {
-            self.memory.read_pc += 2;
+            self.skip_next_instruction();
         }
     }
 
@@ -265,12 +458,8 @@ impl CPU {
      * Skips the next instruction if Vx == Vy
      */
     fn skip_next_instruction_if_registers_equals(&mut self, x: u8, y: u8) {
-        if x > N_CPU_REGISTERS || y > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {x} or {y}");
-        }
-
         if self.registers[x as usize] == self.registers[y as usize] {
-            self.memory.read_pc += 2;
+            self.skip_next_instruction();
         }
     }
 
@@ -278,487 +467,2337 @@ impl CPU {
      * Skips the next instruction if Vx != Vy
      */
     fn skip_next_instruction_if_registers_not_equals(&mut self, x: u8, y: u8) {
-        if x > N_CPU_REGISTERS || y > N_CPU_REGISTERS {
-            panic!("Attempted to write on an undefined register: {x} or {y}");
+        if self.registers[x as usize] != self.registers[y as usize] {
+            self.skip_next_instruction();
         }
+    }
 
-        if self.registers[x as usize] != self.registers[y as usize] {
-            self.memory.read_pc += 2;
+    /**
+     * Skips the next instruction if the key Vx is pressed
+     */
+    fn skip_next_instruction_if_key_pressed(&mut self, register: u8) {
+        if self.keypad.is_pressed(self.registers[register as usize]) {
+            self.skip_next_instruction();
         }
     }
 
-    fn parse_12bit_address(&self, opcode: Opcode) -> u16 {
-        let op1 = opcode.1 as u16;
-        let op2 = opcode.2 as u16;
-        let op3 = opcode.3 as u16;
+    /**
+     * Skips the next instruction if the key Vx is not pressed
+     */
+    fn skip_next_instruction_if_key_not_pressed(&mut self, register: u8) {
+        if !self.keypad.is_pressed(self.registers[register as usize]) {
+            self.skip_next_instruction();
+        }
+    }
 
-        op3 << 0 | op2 << 4 | op1 << 8
+    /**
+     * Stops the execution until a key is pressed and released (**LD Vx, K**)
+     */
+    fn wait_for_key_operation(&mut self, register: u8) {
+        self.key_wait = Some(KeyWait {
+            register,
+            pressed: None,
+        });
     }
 
     /**
-     * Parse 2 nibbles into a 1 byte hex value
+     * Jumps to NNN plus V0, or to XNN plus Vx on CHIP-48 (**JP V0, NNN**)
      */
-    fn parse_8bit_address(&self, nibble1: u8, nibble2: u8) -> u8 {
-        nibble1 << 4 | nibble2
+    fn jp_with_offset_operation(&mut self, address: u16) {
+        let register = if self.quirks.jump_with_vx {
+            (address >> 8) & 0xF
+        } else {
+            0
+        };
+
+        self.memory.read_pc = address + self.registers[register as usize] as u16;
     }
 
     /**
-    * Enter into a loop with will fetch opcodes from memory,
-    then it would be parsed and matched to be executed.
-    * It is considered the entry point of the program
-    */
-    pub fn run(&mut self) {
-        loop {
-            let opcodes: Opcode = self.parse_opcode();
+     * Sets Vx to a random byte masked with NN (**RND Vx, NN**)
+     */
+    fn random_operation(&mut self, register: u8, mask: u8) {
+        self.registers[register as usize] = self.rng.next_u8() & mask;
+    }
 
-            let x_register = opcodes.1;
-            let y_register = opcodes.2;
+    /**
+     * Sets the I register to NNN (**LD I, NNN**)
+     */
+    fn set_i_register_operation(&mut self, address: u16) {
+        self.i_register = address;
+    }
 
-            match opcodes {
-                // Ret instruction
-                (0, 0, 0xE, 0xE) => {
-                    self.ret_operation();
-                }
-                // Jp instruction. jp NNN
-                (0x1, _, _, _) => {
-                    let parsed_address_to_jump = self.parse_12bit_address(opcodes);
-                    self.jp_operation(parsed_address_to_jump);
-                }
-                // Call instruction
-                (0x2, _, _, _) => {
-                    self.call_operation(self.parse_12bit_address(opcodes));
-                }
-                // if Vx == NN
-                (0x3, _, _, _) => {
-                    let parsed_value_to_compare = self.parse_8bit_address(opcodes.2, opcodes.3);
-                    self.skip_next_instruction_if_equals(x_register, parsed_value_to_compare);
-                }
-                // if Vx != NN
-                (0x4, _, _, _) => {
-                    let parsed_value_to_compare = self.parse_8bit_address(opcodes.2, opcodes.3);
-                    self.skip_next_instruction_if_not_equals(x_register, parsed_value_to_compare);
-                }
-                // if Vx == Vy
-                (0x5, _, _, 0x0) => {
-                    self.skip_next_instruction_if_registers_equals(x_register, y_register);
-                }
-                // Assign a value to a register. Vx = NN
-                (0x6, _, _, _) => {
-                    let value_to_set = self.parse_8bit_address(opcodes.2, opcodes.3);
+    /**
+     * Adds Vx to the I register (**ADD I, Vx**)
+     */
+    fn add_to_i_register_operation(&mut self, register: u8) {
+        self.i_register = self
+            .i_register
+            .wrapping_add(self.registers[register as usize] as u16);
+    }
 
-                    self.set_value_to_register_operation(opcodes.1, value_to_set);
-                }
-                // Add a value to a register
-                (0x7, _, _, _) => {
-                    let value_to_set = self.parse_8bit_address(opcodes.2, opcodes.3);
+    /**
+     * Points I to the font glyph of the digit in Vx (**LD F, Vx**)
+     */
+    fn load_font_glyph_operation(&mut self, register: u8) {
+        let digit = (self.registers[register as usize] & 0xF) as u16;
 
-                    self.add_value_to_register_operation(x_register, value_to_set);
-                }
-                // Move the Vy value to Vx
-                (0x8, _, _, 0x0) => {
-                    self.move_y_register_value_to_x_instruction(x_register, y_register);
-                }
-                // Bitwise OR operation
-                (0x8, _, _, 0x1) => {
-                    self.bitwise_or_operation(x_register, y_register);
-                }
-                // Bitwise AND operation
-                (0x8, _, _, 0x2) => {
-                    self.bitwise_and_operation(x_register, y_register);
-                }
-                // Bitwise XOR operation
-                (0x8, _, _, 0x3) => {
-                    self.bitwise_xor_operation(x_register, y_register);
-                }
-                // Add operation. Vx += Vy
-                (0x8, _, _, 0x4) => {
-                    let overflow = self.add_operation(x_register, y_register);
+        self.i_register = FONT_ADDRESS + digit * FONT_GLYPH_SIZE;
+    }
 
-                    if overflow {
-                        self.registers[15] = 1;
-                    }
-                }
-                // SUB Vx, Vy
-                (0x8, _, _, 0x5) => {
-                    let overflow = self.sub_operation(x_register, y_register);
+    /**
+     * Points I to the large font glyph of the digit in Vx (**LD HF, Vx**)
+     */
+    fn load_big_font_glyph_operation(&mut self, register: u8) {
+        let digit = (self.registers[register as usize] & 0xF) as u16;
 
-                    if overflow {
-                        self.registers[15] = 1;
-                    }
-                }
-                // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
-                (0x8, _, _, 0x6) => {
-                    self.bitwise_shr_operation(x_register);
-                }
-                // Vx = Vy - Vx
-                (0x8, _, _, 0x7) => {
-                    self.sub_vx_minus_vy_operation(x_register, y_register);
-                }
-                // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
-                (0x8, _, _, 0xE) => {
-                    self.bitwise_shl_operation(x_register);
-                }
-                // Skip next instruction if Vx != Vy
-                (0x9, _, _, 0x0) => {
-                    self.skip_next_instruction_if_registers_not_equals(x_register, y_register);
-                }
-                // Halt instruction
-                (0, 0, 0, 0) => {
-                    return;
-                }
-                _ => panic!("Opcode <{:?}> not identified!", opcodes),
-            }
-        }
+        self.i_register = BIG_FONT_ADDRESS + digit * BIG_FONT_GLYPH_SIZE;
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::CPU;
 
-    #[test]
-    fn test_cpu_add_instruction() {
-        let mut cpu = CPU::new();
+    /**
+     * Stores V0 to Vx on the RPL user flags, and persists them (**LD R, Vx**)
+     */
+    fn store_rpl_flags_operation(&mut self, register: u8) {
+        let last = register as usize;
 
-        cpu.set_opcode(0x8324);
+        self.rpl_flags[0..=last].copy_from_slice(&self.registers[0..=last]);
 
-        cpu.registers[3] = 3;
-        cpu.registers[2] = 2;
+        if let Some(storage) = self.flag_storage.as_mut() {
+            if let Err(err) = storage.save(&self.rpl_flags) {
+                self.flag_storage_error = Some(err);
+            }
+        }
+    }
 
-        cpu.run();
+    /**
+     * Loads V0 to Vx from the RPL user flags (**LD Vx, R**)
+     */
+    fn load_rpl_flags_operation(&mut self, register: u8) {
+        let last = register as usize;
 
-        assert_eq!(cpu.registers[3], 5);
-        assert_eq!(cpu.registers[2], 2);
+        self.registers[0..=last].copy_from_slice(&self.rpl_flags[0..=last]);
     }
 
-    #[test]
-    fn test_cpu_add_overflow() {
-        let mut cpu = CPU::new();
+    /**
+     * Stores the BCD representation of Vx on I, I+1 and I+2 (**LD B, Vx**)
+     */
+    fn store_bcd_operation(&mut self, register: u8) -> Result<(), FaultKind> {
+        let value = self.registers[register as usize];
 
-        cpu.set_opcode(0x8324);
+        self.memory
+            .write_bytes(self.i_register, &[value / 100, value / 10 % 10, value % 10])
+    }
 
-        cpu.registers[3] = 255;
-        cpu.registers[2] = 1;
+    /**
+     * Stores the registers V0 to Vx on memory, starting from I (**LD [I], Vx**)
+     */
+    fn store_registers_operation(&mut self, register: u8) -> Result<(), FaultKind> {
+        let last = register as usize;
 
-        cpu.run();
+        self.memory
+            .write_bytes(self.i_register, &self.registers[0..=last])?;
+        self.increment_i_after_load_store(register);
 
-        assert_eq!(cpu.registers[15], 1);
+        Ok(())
     }
 
-    #[should_panic]
-    #[test]
-    fn test_cpu_call_instruction() {
-        std::panic::set_hook(Box::new(|_info| {
-            // In order to not to show any panic info
-        }));
-
-        let mut cpu = CPU::new();
+    /**
+     * Loads the registers V0 to Vx from memory, starting from I (**LD Vx, [I]**)
+     */
+    fn load_registers_operation(&mut self, register: u8) -> Result<(), FaultKind> {
+        let last = register as usize;
+        let values = self.memory.load_bytes(self.i_register, last + 1)?;
 
-        cpu.set_opcode(0x8324);
+        self.registers[0..=last].copy_from_slice(values);
+        self.increment_i_after_load_store(register);
 
-        cpu.registers[3] = 2;
-        cpu.registers[2] = 1;
+        Ok(())
+    }
 
-        cpu.set_opcode(0x2200);
+    /**
+     * Returns the indexes from Vx to Vy, backwards if X is greater than Y
+     */
+    fn register_range(&self, x: u8, y: u8) -> Vec<usize> {
+        if x <= y {
+            (x as usize..=y as usize).collect()
+        } else {
+            (y as usize..=x as usize).rev().collect()
+        }
+    }
 
-        cpu.run();
+    /**
+     * Stores Vx to Vy on memory, starting from I. I is not changed (**SAVE Vx - Vy**)
+     */
+    fn store_register_range_operation(&mut self, x: u8, y: u8) -> Result<(), FaultKind> {
+        let values: Vec<u8> = self
+            .register_range(x, y)
+            .into_iter()
+            .map(|register| self.registers[register])
+            .collect();
+
+        self.memory.write_bytes(self.i_register, &values)
     }
 
-    #[test]
-    fn test_cpu_ret_instruction() {
-        let mut cpu = CPU::new();
+    /**
+     * Loads Vx to Vy from memory, starting from I. I is not changed (**LOAD Vx - Vy**)
+     */
+    fn load_register_range_operation(&mut self, x: u8, y: u8) -> Result<(), FaultKind> {
+        let range = self.register_range(x, y);
+        let values = self.memory.load_bytes(self.i_register, range.len())?;
 
-        // set the instructions to exec on 0x50 address
-        cpu.memory.write_into(0x8324, 0x300);
+        for (register, value) in range.into_iter().zip(values) {
+            self.registers[register] = *value;
+        }
 
-        cpu.set_opcode(0x8014);
+        Ok(())
+    }
 
-        cpu.registers[0 as usize] = 1;
-        cpu.registers[1 as usize] = 2;
+    /**
+     * Loads the 16 bytes audio pattern from I (**AUDIO**)
+     */
+    fn load_audio_pattern_operation(&mut self) -> Result<(), FaultKind> {
+        let mut pattern = [0x0; AUDIO_PATTERN_SIZE];
 
-        cpu.registers[2 as usize] = 2;
+        pattern.copy_from_slice(
+            self.memory
+                .load_bytes(self.i_register, AUDIO_PATTERN_SIZE)?,
+        );
+        self.audio.set_pattern(pattern);
 
-        cpu.set_opcode(0x2300);
-        cpu.set_opcode(0x8424);
+        Ok(())
+    }
 
-        // Writes the ret opcode after the executed function on 0x100
-        cpu.memory.write_into(0xEE, 0x302);
+    /**
+     * The COSMAC VIP leaves I pointing after the last register,
+     * and CHIP-48 on the last one
+     */
+    fn increment_i_after_load_store(&mut self, register: u8) {
+        if self.quirks.load_store_increments_i {
+            let skip_last = !self.quirks.load_store_i_on_last as u16;
 
-        cpu.run();
+            self.i_register = self.i_register.wrapping_add(register as u16 + skip_last);
+        }
+    }
 
-        assert_eq!(cpu.registers[3], 2);
-        assert_eq!(cpu.registers[4], 2);
+    /**
+     * Scrolls the screen down N pixels (**SCD N**)
+     */
+    fn scroll_down_operation(&mut self, rows: u8) {
+        self.display.scroll_down(rows as usize);
+        self.display_changed = true;
     }
 
-    #[test]
-    fn test_cpu_set_value_to_register_instruction() {
-        let mut cpu = CPU::new();
+    /**
+     * Scrolls the screen up N pixels (**SCU N**)
+     */
+    fn scroll_up_operation(&mut self, rows: u8) {
+        self.display.scroll_up(rows as usize);
+        self.display_changed = true;
+    }
 
-        cpu.set_opcode(0x6012);
+    /**
+     * Scrolls the screen right 4 pixels (**SCR**)
+     */
+    fn scroll_right_operation(&mut self) {
+        self.display.scroll_right();
+        self.display_changed = true;
+    }
 
-        cpu.run();
+    /**
+     * Scrolls the screen left 4 pixels (**SCL**)
+     */
+    fn scroll_left_operation(&mut self) {
+        self.display.scroll_left();
+        self.display_changed = true;
+    }
 
-        // 0x12 -> 18
-        assert_eq!(cpu.registers[0], 18);
+    /**
+     * Switches to the low (**LOW**) or the high (**HIGH**) resolution
+     */
+    fn set_resolution_operation(&mut self, hires: bool) {
+        self.display.set_hires(hires, self.quirks.hires_clears);
+        self.display_changed = true;
     }
 
-    #[test]
-    fn test_cpu_jp_instruction() {
-        let mut cpu = CPU::new();
-        // Write into 0x200 -> 0x8014 which is an add
-        cpu.memory.write_into(0x8014, 0x200);
+    /**
+     * Clears the screen (**CLS**)
+     */
+    fn clear_screen_operation(&mut self) {
+        self.display.clear();
+        self.display_changed = true;
+    }
 
-        // ld registers[0], 1
-        cpu.set_opcode(0x6001);
-        // JP 0x200
-        cpu.set_opcode(0x1200);
+    /**
+     * Draws a N bytes sprite, read from the I register, at (Vx, Vy).
+     * VF is set to 1 if any pixel was erased, otherwise 0 (**DRW Vx, Vy, N**).
+     * Each selected XO-CHIP plane takes its own N bytes, one after another
+     */
+    fn draw_operation(&mut self, x: u8, y: u8, size: u8) -> Result<(), FaultKind> {
+        let pos_x = self.registers[x as usize];
+        let pos_y = self.registers[y as usize];
+        let wrap = self.quirks.wrap_sprites;
+        let planes = self.display.selected_planes();
+
+        // DRW Vx, Vy, 0 draws a 16x16 sprite (SUPER-CHIP), or nothing
+        let collision = match size {
+            0 if self.quirks.large_sprites => {
+                let sprite = self.memory.load_bytes(self.i_register, 32 * planes)?;
+                self.display.draw_large_sprite(pos_x, pos_y, sprite, wrap)
+            }
+            0 => false,
+            _ => {
+                let sprite = self
+                    .memory
+                    .load_bytes(self.i_register, size as usize * planes)?;
+                self.display.draw_sprite(pos_x, pos_y, sprite, wrap)
+            }
+        };
 
-        cpu.run();
+        self.registers[15] = collision as u8;
+        self.display_changed = true;
+        self.drawn_this_frame = true;
 
-        // If not fails means that the add has been carried on,
-        // so thats means that the code has jumped
-        assert_eq!(cpu.registers[0], 1);
+        Ok(())
     }
 
-    #[test]
-    fn test_cpu_skip_instruction_if_equals() {
-        let mut cpu = CPU::new();
+    /**
+     * Decrements the delay and sound timers if they are not zero.
+     * It must be called at 60 Hz, no matter how many instructions are executed
+     */
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.drawn_this_frame = false;
+    }
 
-        // LD V0, 0x01
-        cpu.set_opcode(0x6001);
+    /**
+     * The buzzer sounds while the sound timer is not zero
+     */
+    pub fn is_sound_playing(&self) -> bool {
+        self.sound_timer > 0
+    }
 
-        // If V0 == 0x01 -> Skip 1 instruction
-        cpu.set_opcode(0x3001);
+    /**
+     * Returns the keypad state
+     */
+    pub fn keypad(&self) -> &Keypad {
+        &self.keypad
+    }
 
-        cpu.run();
+    /**
+     * Presses and releases the keys whose state differs from the mask
+     * (bit N is the key N), as `press_key` and `release_key` do
+     */
+    pub fn set_keys(&mut self, keys: u16) {
+        let changed = self.keypad.bits() ^ keys;
 
-        // 8 = 2 bytes + 2 bytes + 2 bytes (skipped instruction) + 2 bytes (Halt)
-        assert_eq!(cpu.memory.read_pc, 0x200 + 8);
+        for key in (0..N_KEYS as u8).filter(|key| changed & (1 << key) != 0) {
+            match keys & (1 << key) != 0 {
+                true => self.press_key(key),
+                false => self.release_key(key),
+            }
+        }
     }
 
-    #[test]
-    fn test_cpu_skip_instruction_if_not_equals() {
-        let mut cpu = CPU::new();
-
-        // If V0 == 0x01 -> Skip 1 instruction
-        cpu.set_opcode(0x4001);
+    /**
+     * Presses a key of the hexadecimal keypad (0x0 - 0xF)
+     */
+    pub fn press_key(&mut self, key: u8) {
+        self.keypad.press(key);
 
-        cpu.run();
+        if let Some(wait) = self.key_wait.as_mut() {
+            if wait.pressed.is_none() {
+                wait.pressed = Some(key & 0xF);
+            }
+        }
+    }
+
+    /**
+     * Releases a key of the hexadecimal keypad (0x0 - 0xF).
+     * If the CPU is waiting for a key and it is the pressed one,
+     * it is stored and the execution goes on
+     */
+    pub fn release_key(&mut self, key: u8) {
+        self.keypad.release(key);
+
+        if let Some(wait) = self.key_wait {
+            if wait.pressed == Some(key & 0xF) {
+                self.registers[wait.register as usize] = key & 0xF;
+                self.key_wait = None;
+            }
+        }
+    }
+
+    /**
+     * True while a **LD Vx, K** instruction is waiting for a key
+     */
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait.is_some()
+    }
+
+    /**
+     * Sets how many instructions are executed on each `run_frame` call
+     */
+    pub fn set_instructions_per_tick(&mut self, instructions: u32) {
+        self.instructions_per_tick = instructions;
+    }
+
+    pub fn instructions_per_tick(&self) -> u32 {
+        self.instructions_per_tick
+    }
+
+    /**
+     * Executes a frame: the configured instructions per tick followed by a
+     * timers tick. Call it at 60 Hz to run the game at the right speed.
+     * Returns false once the halt instruction has been reached
+     */
+    pub fn run_frame(&mut self) -> Result<bool, EmulatorError> {
+        self.run_cycles(self.instructions_per_tick)?;
+
+        self.tick_timers();
+
+        Ok(!self.halted)
+    }
+
+    /**
+     * Executes up to `cycles` instructions. It stops before if the CPU
+     * halts or starts waiting for a key.
+     * Returns how many instructions have been executed
+     */
+    pub fn run_cycles(&mut self, cycles: u32) -> Result<u32, EmulatorError> {
+        for executed in 0..cycles {
+            let event = self.step()?;
+
+            if event.is_blocked() {
+                return Ok(executed + event.opcode.is_some() as u32);
+            }
+        }
+
+        Ok(cycles)
+    }
+
+    /**
+     * Enter into a loop with will fetch opcodes from memory,
+     * then it would be parsed and matched to be executed.
+     * It is considered the entry point of the program.
+     * It returns on the halt instruction, or when waiting for a key
+     */
+    pub fn run(&mut self) -> Result<(), EmulatorError> {
+        loop {
+            let event = self.step()?;
+
+            if event.is_blocked() {
+                return Ok(());
+            }
+        }
+    }
+
+    /**
+     * True once the halt instruction has been executed
+     */
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /**
+     * Returns the address of the next instruction to execute
+     */
+    pub fn program_counter(&self) -> u16 {
+        self.memory.read_pc
+    }
+
+    /**
+     * Moves the execution to the given address
+     */
+    pub fn set_program_counter(&mut self, address: u16) {
+        self.memory.read_pc = address;
+    }
+
+    /**
+     * Takes a snapshot of the whole machine, which `restore_state` puts back
+     */
+    pub fn save_state(&self) -> SaveState {
+        let mut registers = [0x0; N_CPU_REGISTERS as usize];
+        let mut stack = [0x0; MAX_STACK_SIZE];
+
+        registers.copy_from_slice(&self.registers);
+        stack.copy_from_slice(self.stack.entries());
+
+        SaveState {
+            quirks: self.quirks,
+            registers,
+            i_register: self.i_register,
+            program_counter: self.memory.read_pc,
+            memory: self.memory.bytes().to_vec(),
+            stack,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            framebuffer: self.display.clone(),
+            keypad: self.keypad.clone(),
+            key_wait: self.key_wait.map(|wait| (wait.register, wait.pressed)),
+            halted: self.halted,
+            drawn_this_frame: self.drawn_this_frame,
+            rpl_flags: self.rpl_flags,
+            audio: self.audio,
+            rng_state: self.rng.state(),
+            instructions_per_tick: self.instructions_per_tick,
+        }
+    }
+
+    /**
+     * Puts the machine back on a state taken by `save_state`. The random
+     * generator is only restored if both of them have a state
+     */
+    pub fn restore_state(&mut self, state: &SaveState) {
+        self.quirks = state.quirks;
+        self.registers.copy_from_slice(&state.registers);
+        self.i_register = state.i_register;
+        self.memory.set_bytes(&state.memory);
+        self.memory.read_pc = state.program_counter;
+        self.stack = Stack::from_entries(state.stack);
+        self.stack_pointer = state.stack_pointer;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.display = state.framebuffer.clone();
+        self.keypad = state.keypad.clone();
+        self.key_wait = state
+            .key_wait
+            .map(|(register, pressed)| KeyWait { register, pressed });
+        self.halted = state.halted;
+        self.drawn_this_frame = state.drawn_this_frame;
+        self.rpl_flags = state.rpl_flags;
+        self.audio = state.audio;
+        self.instructions_per_tick = state.instructions_per_tick;
+        self.skipped = None;
+        self.display_changed = false;
+
+        if let (Some(rng_state), Some(_)) = (state.rng_state, self.rng.state()) {
+            self.rng.set_state(rng_state);
+        }
+    }
+
+    /**
+     * Fetches, decodes and executes a single instruction, and returns
+     * what happened. Nothing is executed while waiting for a key or halted
+     */
+    pub fn step(&mut self) -> Result<StepEvent, EmulatorError> {
+        let pc = self.memory.read_pc;
+
+        let mut event = StepEvent {
+            opcode: None,
+            instruction: None,
+            pc_before: pc,
+            pc_after: pc,
+            skipped: None,
+            display_changed: false,
+            waiting_for_key: self.key_wait.is_some(),
+            waiting_for_display: false,
+            halted: self.halted,
+        };
+
+        if event.waiting_for_key || event.halted {
+            return Ok(event);
+        }
+
+        let (opcode, instruction, cycle) = match self.fetch_and_execute(pc) {
+            Ok(result) => result,
+            Err(err) => {
+                // Stopped on the faulting instruction, so it is not skipped
+                // if the frontend goes on
+                self.memory.read_pc = pc;
+                return Err(err);
+            }
+        };
+
+        if cycle == Cycle::WaitingForDisplay {
+            event.waiting_for_display = true;
+            return Ok(event);
+        }
+
+        self.halted = cycle == Cycle::Halted;
+
+        event.opcode = Some(opcode);
+        event.instruction = Some(instruction);
+        event.pc_after = self.memory.read_pc;
+        event.skipped = self.skipped;
+        event.display_changed = self.display_changed;
+        event.waiting_for_key = cycle == Cycle::WaitingForKey;
+        event.halted = self.halted;
+
+        Ok(event)
+    }
+
+    /**
+     * Reads the instruction on the program counter and executes it
+     */
+    fn fetch_and_execute(&mut self, pc: u16) -> Result<(u16, Instruction, Cycle), EmulatorError> {
+        let opcode = self
+            .memory
+            .read(2)
+            .map_err(|kind| EmulatorError::new(pc, 0x0, kind))?;
+
+        let instruction = self
+            .fetch_instruction(opcode)
+            .map_err(|kind| EmulatorError::new(pc, opcode, kind))?;
+
+        self.skipped = None;
+        self.display_changed = false;
+
+        let cycle = self
+            .execute(instruction)
+            .map_err(|kind| EmulatorError::new(pc, opcode, kind))?;
+
+        Ok((opcode, instruction, cycle))
+    }
+
+    /**
+     * Decodes the given opcode. On XO-CHIP, **LD I, NNNN** also reads
+     * the address from the next 2 bytes
+     */
+    fn fetch_instruction(&mut self, opcode: u16) -> Result<Instruction, FaultKind> {
+        let next = if opcode == LONG_LOAD_OPCODE && self.quirks.xo_chip {
+            self.memory.read(2)?
+        } else {
+            0x0
+        };
+
+        Ok(Instruction::decode(opcode, next))
+    }
+
+    /**
+     * Executes the given instruction
+     */
+    fn execute(&mut self, instruction: Instruction) -> Result<Cycle, FaultKind> {
+        if instruction.is_super_chip() && !self.quirks.super_chip
+            || instruction.is_xo_chip() && !self.quirks.xo_chip
+        {
+            return Err(FaultKind::UnknownOpcode);
+        }
+
+        match instruction {
+            Instruction::Cls => {
+                self.clear_screen_operation();
+            }
+            Instruction::Ret => {
+                self.ret_operation()?;
+            }
+            Instruction::ScrollDown(rows) => {
+                self.scroll_down_operation(rows);
+            }
+            Instruction::ScrollUp(rows) => {
+                self.scroll_up_operation(rows);
+            }
+            Instruction::ScrollRight => {
+                self.scroll_right_operation();
+            }
+            Instruction::ScrollLeft => {
+                self.scroll_left_operation();
+            }
+            Instruction::Exit => {
+                return Ok(Cycle::Halted);
+            }
+            Instruction::Low => {
+                self.set_resolution_operation(false);
+            }
+            Instruction::High => {
+                self.set_resolution_operation(true);
+            }
+            Instruction::Jp(address) => {
+                self.jp_operation(address);
+            }
+            Instruction::Call(address) => {
+                self.call_operation(address)?;
+            }
+            Instruction::SeByte(x, value) => {
+                self.skip_next_instruction_if_equals(x, value);
+            }
+            Instruction::SneByte(x, value) => {
+                self.skip_next_instruction_if_not_equals(x, value);
+            }
+            Instruction::SeRegister(x, y) => {
+                self.skip_next_instruction_if_registers_equals(x, y);
+            }
+            Instruction::SaveRange(x, y) => {
+                self.store_register_range_operation(x, y)?;
+            }
+            Instruction::LoadRange(x, y) => {
+                self.load_register_range_operation(x, y)?;
+            }
+            Instruction::LdByte(x, value) => {
+                self.set_value_to_register_operation(x, value);
+            }
+            Instruction::AddByte(x, value) => {
+                self.add_value_to_register_operation(x, value);
+            }
+            Instruction::LdRegister(x, y) => {
+                self.move_y_register_value_to_x_instruction(x, y);
+            }
+            Instruction::Or(x, y) => {
+                self.bitwise_or_operation(x, y);
+            }
+            Instruction::And(x, y) => {
+                self.bitwise_and_operation(x, y);
+            }
+            Instruction::Xor(x, y) => {
+                self.bitwise_xor_operation(x, y);
+            }
+            Instruction::AddRegister(x, y) => {
+                self.add_operation(x, y);
+            }
+            Instruction::Sub(x, y) => {
+                self.sub_operation(x, y);
+            }
+            // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
+            Instruction::Shr(x, y) => {
+                self.load_shift_source(x, y);
+                self.bitwise_shr_operation(x);
+            }
+            Instruction::Subn(x, y) => {
+                self.sub_vx_minus_vy_operation(x, y);
+            }
+            // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
+            Instruction::Shl(x, y) => {
+                self.load_shift_source(x, y);
+                self.bitwise_shl_operation(x);
+            }
+            Instruction::SneRegister(x, y) => {
+                self.skip_next_instruction_if_registers_not_equals(x, y);
+            }
+            Instruction::LdI(address) | Instruction::LdLongI(address) => {
+                self.set_i_register_operation(address);
+            }
+            Instruction::JpV0(address) => {
+                self.jp_with_offset_operation(address);
+            }
+            Instruction::Rnd(x, mask) => {
+                self.random_operation(x, mask);
+            }
+            Instruction::Drw(x, y, size) => {
+                if self.quirks.display_wait && self.drawn_this_frame {
+                    // Execute it again once the next frame starts
+                    self.memory.read_pc -= 2;
+                    return Ok(Cycle::WaitingForDisplay);
+                }
+
+                self.draw_operation(x, y, size)?;
+            }
+            Instruction::Skp(x) => {
+                self.skip_next_instruction_if_key_pressed(x);
+            }
+            Instruction::Sknp(x) => {
+                self.skip_next_instruction_if_key_not_pressed(x);
+            }
+            Instruction::Plane(planes) => {
+                self.display.set_planes(planes);
+            }
+            Instruction::Audio => {
+                self.load_audio_pattern_operation()?;
+            }
+            Instruction::LdFromDelayTimer(x) => {
+                self.registers[x as usize] = self.delay_timer;
+            }
+            Instruction::LdKey(x) => {
+                self.wait_for_key_operation(x);
+                return Ok(Cycle::WaitingForKey);
+            }
+            Instruction::LdDelayTimer(x) => {
+                self.delay_timer = self.registers[x as usize];
+            }
+            Instruction::LdSoundTimer(x) => {
+                self.sound_timer = self.registers[x as usize];
+            }
+            Instruction::AddI(x) => {
+                self.add_to_i_register_operation(x);
+            }
+            Instruction::LdFont(x) => {
+                self.load_font_glyph_operation(x);
+            }
+            Instruction::LdBigFont(x) => {
+                self.load_big_font_glyph_operation(x);
+            }
+            Instruction::LdBcd(x) => {
+                self.store_bcd_operation(x)?;
+            }
+            Instruction::Pitch(x) => {
+                self.audio.set_pitch(self.registers[x as usize]);
+            }
+            Instruction::StoreRegisters(x) => {
+                self.store_registers_operation(x)?;
+            }
+            Instruction::LoadRegisters(x) => {
+                self.load_registers_operation(x)?;
+            }
+            Instruction::StoreFlags(x) => {
+                self.store_rpl_flags_operation(x);
+            }
+            Instruction::LoadFlags(x) => {
+                self.load_rpl_flags_operation(x);
+            }
+            Instruction::Halt => {
+                return Ok(Cycle::Halted);
+            }
+            Instruction::Unknown(_) => return Err(FaultKind::UnknownOpcode),
+        }
+
+        Ok(Cycle::Executed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CPU;
+    use crate::asm::Assembler;
+    use crate::error::{EmulatorError, FaultKind};
+    use crate::flags::{FileFlagStorage, FlagStorage, N_RPL_FLAGS};
+    use crate::instruction::Instruction;
+    use crate::quirks::Quirks;
+    use crate::rng::Rng;
+    use crate::rom::{Rom, RomError, DEFAULT_LOAD_ADDRESS, ETI660_LOAD_ADDRESS};
+
+    /// Always returns the same byte
+    #[derive(Debug)]
+    struct FixedRng(u8);
+
+    /**
+     * Assembles the program and writes it on 0x200
+     */
+    fn load_program(cpu: &mut CPU, source: &str) {
+        let program = Assembler::new().assemble(source).unwrap();
+
+        cpu.memory
+            .write_bytes(DEFAULT_LOAD_ADDRESS, &program)
+            .unwrap();
+    }
+
+    impl Rng for FixedRng {
+        fn next_u8(&mut self) -> u8 {
+            self.0
+        }
+    }
+
+    /// Keeps the flags on a shared buffer, so the test can look at them
+    #[derive(Debug)]
+    struct SharedFlagStorage(std::rc::Rc<std::cell::RefCell<[u8; N_RPL_FLAGS]>>);
+
+    impl FlagStorage for SharedFlagStorage {
+        fn load(&mut self) -> [u8; N_RPL_FLAGS] {
+            *self.0.borrow()
+        }
+
+        fn save(&mut self, flags: &[u8; N_RPL_FLAGS]) -> std::io::Result<()> {
+            *self.0.borrow_mut() = *flags;
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_cpu_add_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "ADD V3, V2");
+
+        cpu.registers[3] = 3;
+        cpu.registers[2] = 2;
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[3], 5);
+        assert_eq!(cpu.registers[2], 2);
+    }
+
+    #[test]
+    fn test_cpu_add_overflow() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "ADD V3, V2");
+
+        cpu.registers[3] = 255;
+        cpu.registers[2] = 1;
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[3], 0);
+        assert_eq!(cpu.registers[15], 1);
+    }
+
+    #[test]
+    fn test_cpu_call_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            ADD V3, V2
+            CALL 0x200
+            ",
+        );
+
+        cpu.registers[3] = 2;
+        cpu.registers[2] = 1;
+
+        // Calls itself until the stack is full
+        assert_eq!(
+            cpu.run(),
+            Err(EmulatorError::new(0x202, 0x2200, FaultKind::StackOverflow))
+        );
+        assert_eq!(cpu.registers[3], 2 + 17);
+    }
+
+    #[test]
+    fn test_cpu_ret_with_empty_stack() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "RET");
+
+        assert_eq!(
+            cpu.run(),
+            Err(EmulatorError::new(0x200, 0x00EE, FaultKind::StackUnderflow))
+        );
+        // Stopped on the faulting instruction
+        assert_eq!(cpu.program_counter(), 0x200);
+    }
+
+    #[test]
+    fn test_cpu_unknown_opcode() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "DW 0xF0FF");
+
+        assert_eq!(
+            cpu.run(),
+            Err(EmulatorError::new(0x200, 0xF0FF, FaultKind::UnknownOpcode))
+        );
+        assert_eq!(cpu.program_counter(), 0x200);
+    }
+
+    #[test]
+    fn test_cpu_memory_out_of_range() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD I, 0xFFF
+            LD B, V0
+            ",
+        );
+
+        assert_eq!(
+            cpu.run(),
+            Err(EmulatorError::new(
+                0x202,
+                0xF033,
+                FaultKind::MemoryOutOfRange(0xFFF)
+            ))
+        );
+    }
+
+    #[test]
+    fn test_cpu_pc_out_of_range() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0xFF
+            JP V0, 0xFFF
+            ",
+        );
+
+        assert_eq!(
+            cpu.run(),
+            Err(EmulatorError::new(0x10FE, 0x0, FaultKind::PcOutOfRange))
+        );
+    }
+
+    #[test]
+    fn test_cpu_ret_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            ADD V0, V1
+            CALL add
+            ADD V4, V2
+
+            org 0x300
+        add:
+            ADD V3, V2
+            RET
+            ",
+        );
+
+        cpu.registers[0] = 1;
+        cpu.registers[1] = 2;
+
+        cpu.registers[2] = 2;
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[3], 2);
+        assert_eq!(cpu.registers[4], 2);
+    }
+
+    #[test]
+    fn test_cpu_set_value_to_register_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "LD V0, 0x12");
+
+        cpu.run().unwrap();
+
+        // 0x12 -> 18
+        assert_eq!(cpu.registers[0], 18);
+    }
+
+    #[test]
+    fn test_cpu_jp_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+        start:
+            LD V0, 1
+            JP start
+            ",
+        );
+
+        // The JP loops back forever, so `run` would never return
+        cpu.run_cycles(2).unwrap();
+
+        // If not fails means that the add has been carried on,
+        // so thats means that the code has jumped
+        assert_eq!(cpu.registers[0], 1);
+        assert_eq!(cpu.program_counter(), 0x200);
+    }
+
+    #[test]
+    fn test_cpu_skip_instruction_if_equals() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x01
+            ; If V0 == 0x01 -> Skip 1 instruction
+            SE V0, 0x01
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        // 8 = 2 bytes + 2 bytes + 2 bytes (skipped instruction) + 2 bytes (Halt)
+        assert_eq!(cpu.memory.read_pc, 0x200 + 8);
+    }
+
+    #[test]
+    fn test_cpu_skip_instruction_if_not_equals() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            ; If V0 == 0x01 -> Skip 1 instruction
+            SNE V0, 0x01
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        // 6 = 2 bytes + 2 bytes (skipped instruction) + 2 bytes (Halt)
+        assert_eq!(cpu.memory.read_pc, 0x200 + 6);
+    }
+
+    #[test]
+    fn test_cpu_skip_instruction_if_registers_equals() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x01
+            LD V1, 0x01
+            ; If V0 == V1 -> Skip 1 instruction
+            SE V0, V1
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        // 10 = 2 bytes + 2 bytes + 2 bytes + 2 bytes (skipped instruction) + 2 bytes (Halt)
+        assert_eq!(cpu.memory.read_pc, 0x200 + 10);
+    }
+
+    #[test]
+    fn test_cpu_move_y_register_value_to_x() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V1, 0x01
+            LD V0, V1
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 1);
+    }
+
+    #[test]
+    fn test_cpu_bitwise_or_operation() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V1, 0x01
+            OR V0, V1
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 1);
+    }
+
+    #[test]
+    fn test_cpu_bitwise_and_operation() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V1, 0x01
+            AND V0, V1
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 0);
+    }
+
+    #[test]
+    fn test_cpu_bitwise_xor_operation() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V1, 0x01
+            XOR V0, V1
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 1);
+    }
+
+    #[test]
+    fn test_cpu_sub_operation() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "SUB V0, V1");
+
+        cpu.registers[0] = 2;
+        cpu.registers[1] = 1;
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 1);
+    }
+
+    #[test]
+    fn test_cpu_sub_overflow_operation() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "SUB V0, V1");
+
+        cpu.registers[1] = 1;
+
+        cpu.run().unwrap();
+
+        // 0 - 1 borrows, so VF is 0
+        assert_eq!(cpu.registers[0], 255);
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn test_cpu_add_value_to_register() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "ADD V0, 0x01");
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 1);
+    }
+
+    #[test]
+    fn test_cpu_shr_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "SHR V0, V1");
+
+        cpu.registers[0] = 4;
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 2);
+    }
+
+    #[test]
+    fn test_cpu_shr_vf_set_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "SHR V0, V1");
+
+        cpu.registers[0] = 3;
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 1);
+        assert_eq!(cpu.registers[15], 1);
+    }
+
+    #[test]
+    fn test_cpu_sub_y_minus_x_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "SUBN V0, V1");
+
+        cpu.registers[0] = 2;
+        cpu.registers[1] = 3;
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 1);
+    }
+
+    #[test]
+    fn test_cpu_sub_y_minus_x_vf_set_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "SUBN V0, V1");
+
+        cpu.registers[0] = 3;
+        cpu.registers[1] = 2;
+
+        cpu.run().unwrap();
+
+        // 2 - 3 borrows, so VF is 0
+        assert_eq!(cpu.registers[0], 255);
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn test_cpu_shl_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "SHL V0, V1");
+
+        cpu.registers[0] = 72;
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 144);
+    }
+
+    #[test]
+    fn test_cpu_shl_vf_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "SHL V0, V1");
+
+        cpu.registers[0] = 218;
+
+        cpu.run().unwrap();
+
+        // Due to registers size, the result is 180.
+        assert_eq!(cpu.registers[0], 180);
+        assert_eq!(cpu.registers[15], 1);
+    }
+
+    #[test]
+    fn test_cpu_skip_instruction_if_registers_not_equals() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x01
+            ; If V0 == V1 -> Skip 1 instruction
+            SNE V0, V1
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        // 8 = 2 bytes + 2 bytes + 2 bytes (skipped instruction) + 2 bytes (Halt)
+        assert_eq!(cpu.memory.read_pc, 0x200 + 8);
+    }
+
+    #[test]
+    fn test_cpu_draw_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        // Sprite: a 2 rows square on 0x300
+        cpu.memory.write_into(0xF0F0, 0x300).unwrap();
+        cpu.i_register = 0x300;
+
+        cpu.registers[0] = 8;
+        cpu.registers[1] = 4;
+
+        load_program(&mut cpu, "DRW V0, V1, 2");
+
+        cpu.run().unwrap();
+
+        let fb = cpu.framebuffer();
+
+        for x in 8..12 {
+            assert!(fb.pixel(x, 4));
+            assert!(fb.pixel(x, 5));
+        }
+        assert!(!fb.pixel(12, 4));
+        assert!(!fb.pixel(8, 6));
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn test_cpu_draw_collision_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        cpu.memory.write_into(0x8000, 0x300).unwrap();
+        cpu.i_register = 0x300;
+
+        load_program(
+            &mut cpu,
+            "
+            ; Twice, so the pixel is erased
+            DRW V0, V1, 1
+            DRW V0, V1, 1
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert!(!cpu.framebuffer().pixel(0, 0));
+        assert_eq!(cpu.registers[15], 1);
+    }
+
+    #[test]
+    fn test_cpu_draw_clips_at_the_edge() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        cpu.memory.write_into(0xFF00, 0x300).unwrap();
+        cpu.i_register = 0x300;
+
+        cpu.registers[0] = 60;
+
+        load_program(&mut cpu, "DRW V0, V1, 1");
+
+        cpu.run().unwrap();
+
+        let fb = cpu.framebuffer();
+
+        assert!(fb.pixel(63, 0));
+        assert!(!fb.pixel(0, 0));
+    }
+
+    #[test]
+    fn test_cpu_draw_wraps_at_the_edge() {
+        let mut cpu = CPU::new(Quirks {
+            wrap_sprites: true,
+            ..Quirks::default()
+        });
+
+        cpu.memory.write_into(0xFF00, 0x300).unwrap();
+        cpu.i_register = 0x300;
+
+        cpu.registers[0] = 60;
+
+        load_program(&mut cpu, "DRW V0, V1, 1");
+
+        cpu.run().unwrap();
+
+        let fb = cpu.framebuffer();
+
+        assert!(fb.pixel(63, 0));
+        assert!(fb.pixel(3, 0));
+        assert!(!fb.pixel(4, 0));
+    }
+
+    #[test]
+    fn test_cpu_clear_screen_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        // 6 = 2 bytes + 2 bytes (skipped instruction) + 2 bytes (Halt)
-        assert_eq!(cpu.memory.read_pc, 0x200 + 6);
+        cpu.memory.write_into(0x8000, 0x300).unwrap();
+        cpu.i_register = 0x300;
+
+        load_program(
+            &mut cpu,
+            "
+            DRW V0, V1, 1
+            CLS
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert!(cpu.framebuffer().pixels().iter().all(|pixel| *pixel == 0));
     }
 
     #[test]
-    fn test_cpu_skip_instruction_if_registers_equals() {
-        let mut cpu = CPU::new();
+    fn test_cpu_delay_timer_instructions() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        // LD V0, 0x01
-        cpu.set_opcode(0x6001);
-        // LD V1, 0x01
-        cpu.set_opcode(0x6101);
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x05
+            LD DT, V0
+            ",
+        );
 
-        // If V0 == V1 -> Skip 1 instruction
-        cpu.set_opcode(0x5010);
+        cpu.run().unwrap();
 
-        cpu.run();
+        cpu.tick_timers();
+        cpu.tick_timers();
 
-        // 10 = 2 bytes + 2 bytes + 2 bytes + 2 bytes (skipped instruction) + 2 bytes (Halt)
-        assert_eq!(cpu.memory.read_pc, 0x200 + 10);
+        assert_eq!(cpu.delay_timer, 3);
     }
 
     #[test]
-    fn test_cpu_move_y_register_value_to_x() {
-        let mut cpu = CPU::new();
+    fn test_cpu_load_delay_timer_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        // LD V1, 0x01
-        cpu.set_opcode(0x6101);
+        cpu.delay_timer = 3;
 
-        // LD V0, V1
-        cpu.set_opcode(0x8010);
+        load_program(&mut cpu, "LD V1, DT");
 
-        cpu.run();
+        cpu.run().unwrap();
 
-        assert_eq!(cpu.registers[0], 1);
+        assert_eq!(cpu.registers[1], 3);
     }
 
     #[test]
-    fn test_cpu_bitwise_or_operation() {
-        let mut cpu = CPU::new();
+    fn test_cpu_sound_timer_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        // LD V1, 0x01
-        cpu.set_opcode(0x6101);
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x01
+            LD ST, V0
+            ",
+        );
 
-        // OR V0, V1
-        cpu.set_opcode(0x8011);
+        cpu.run().unwrap();
 
-        cpu.run();
+        assert!(cpu.is_sound_playing());
 
-        assert_eq!(cpu.registers[0], 1);
+        cpu.tick_timers();
+        cpu.tick_timers();
+
+        assert!(!cpu.is_sound_playing());
+        assert_eq!(cpu.sound_timer, 0);
     }
 
     #[test]
-    fn test_cpu_bitwise_and_operation() {
-        let mut cpu = CPU::new();
+    fn test_cpu_run_frame() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        // LD V1, 0x01
-        cpu.set_opcode(0x6101);
+        cpu.set_instructions_per_tick(2);
+        cpu.delay_timer = 10;
 
-        // AND V0, V1
-        cpu.set_opcode(0x8012);
+        load_program(
+            &mut cpu,
+            "
+        loop:
+            ADD V0, 1
+            JP loop
+            ",
+        );
 
-        cpu.run();
+        assert!(cpu.run_frame().unwrap());
+        assert!(cpu.run_frame().unwrap());
 
-        assert_eq!(cpu.registers[0], 0);
+        // 2 frames of 2 instructions, the half of them are adds
+        assert_eq!(cpu.registers[0], 2);
+        assert_eq!(cpu.delay_timer, 8);
     }
 
     #[test]
-    fn test_cpu_bitwise_xor_operation() {
-        let mut cpu = CPU::new();
+    fn test_cpu_skip_instruction_if_key_pressed() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        // LD V1, 0x01
-        cpu.set_opcode(0x6101);
+        cpu.press_key(0xA);
 
-        // XOR V0, V1
-        cpu.set_opcode(0x8013);
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x0A
+            SKP V0
+            ",
+        );
 
-        cpu.run();
+        cpu.run().unwrap();
 
-        assert_eq!(cpu.registers[0], 1);
+        // 8 = 2 bytes + 2 bytes + 2 bytes (skipped instruction) + 2 bytes (Halt)
+        assert_eq!(cpu.memory.read_pc, 0x200 + 8);
     }
 
     #[test]
-    fn test_cpu_sub_operation() {
-        let mut cpu = CPU::new();
+    fn test_cpu_skip_instruction_if_key_not_pressed() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        cpu.set_opcode(0x8015);
+        cpu.press_key(0xA);
+        cpu.release_key(0xA);
 
-        cpu.registers[0] = 2;
-        cpu.registers[1] = 1;
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x0A
+            SKNP V0
+            ",
+        );
 
-        cpu.run();
+        cpu.run().unwrap();
 
-        assert_eq!(cpu.registers[0], 1);
+        assert!(!cpu.keypad().is_pressed(0xA));
+        assert_eq!(cpu.memory.read_pc, 0x200 + 8);
     }
 
     #[test]
-    fn test_cpu_sub_overflow_operation() {
-        let mut cpu = CPU::new();
+    fn test_cpu_wait_for_key_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        cpu.set_opcode(0x8015);
+        load_program(
+            &mut cpu,
+            "
+            LD V3, K
+            LD V4, 0x01
+            ",
+        );
 
-        cpu.registers[1] = 1;
+        cpu.run().unwrap();
 
-        cpu.run();
+        assert!(cpu.is_waiting_for_key());
+        assert_eq!(cpu.memory.read_pc, 0x202);
 
-        assert_eq!(cpu.registers[15], 1);
+        // Nothing is executed while waiting
+        cpu.run().unwrap();
+        assert_eq!(cpu.memory.read_pc, 0x202);
+
+        // The key must be released to go on
+        cpu.press_key(0x7);
+        assert!(cpu.is_waiting_for_key());
+
+        cpu.release_key(0x7);
+        assert!(!cpu.is_waiting_for_key());
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[3], 0x7);
+        assert_eq!(cpu.registers[4], 0x1);
     }
 
     #[test]
-    fn test_cpu_add_value_to_register() {
-        let mut cpu = CPU::new();
+    fn test_cpu_wait_for_key_keeps_the_timers_running() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        cpu.delay_timer = 2;
+
+        load_program(&mut cpu, "LD V0, K");
+
+        assert!(cpu.run_frame().unwrap());
+        assert!(cpu.is_waiting_for_key());
+        assert_eq!(cpu.delay_timer, 1);
+    }
+
+    #[test]
+    fn test_cpu_set_i_register_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "LD I, 0x123");
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.i_register, 0x123);
+    }
+
+    #[test]
+    fn test_cpu_add_to_i_register_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD I, 0x300
+            LD V2, 0x10
+            ADD I, V2
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.i_register, 0x310);
+    }
+
+    #[test]
+    fn test_cpu_load_font_glyph_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x0A
+            LD F, V0
+            DRW V1, V1, 5
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.i_register, 0x50 + 0xA * 5);
+
+        // The glyph "A" has been drawn: 0xF0, 0x90, 0xF0, 0x90, 0x90
+        let fb = cpu.framebuffer();
+
+        assert!(fb.pixel(0, 0) && fb.pixel(3, 0));
+        assert!(fb.pixel(0, 1) && !fb.pixel(1, 1) && fb.pixel(3, 1));
+        assert!(fb.pixel(0, 4) && !fb.pixel(1, 4));
+    }
+
+    #[test]
+    fn test_cpu_store_bcd_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V5, 234
+            LD I, 0x300
+            LD B, V5
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.memory.read_bytes(0x300, 3).unwrap(), &[2, 3, 4]);
+    }
+
+    #[test]
+    fn test_cpu_store_and_load_registers_instructions() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        cpu.registers[0] = 1;
+        cpu.registers[1] = 2;
+        cpu.registers[2] = 3;
+        cpu.registers[3] = 4;
+
+        load_program(
+            &mut cpu,
+            "
+            LD I, 0x300
+            LD [I], V2
+            ; LD Vx, [I]: the registers V0..V3 are loaded to V0..V3
+            ; after overwriting them, so V3 must be read as 0
+            LD V0, 0x00
+            LD V3, [I]
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.memory.read_bytes(0x300, 4).unwrap(), &[1, 2, 3, 0]);
+        assert_eq!(cpu.registers[0..4], [1, 2, 3, 0]);
+        assert_eq!(cpu.i_register, 0x300);
+    }
+
+    #[test]
+    fn test_cpu_jp_with_offset_instruction() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x02
+            LD V3, 0x10
+            JP V0, 0x300
+            ",
+        );
+
+        // LD V1, 0x01 on 0x302
+        cpu.memory.write_into(0x6101, 0x302).unwrap();
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[1], 1);
+        assert_eq!(cpu.memory.read_pc, 0x306);
+    }
+
+    #[test]
+    fn test_cpu_jp_with_vx_offset_instruction() {
+        let mut cpu = CPU::new(Quirks::chip48());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x02
+            LD V3, 0x10
+            JP V3, 0x300
+            ",
+        );
+
+        // LD V1, 0x01 on 0x310
+        cpu.memory.write_into(0x6101, 0x310).unwrap();
+
+        cpu.run().unwrap();
 
-        cpu.set_opcode(0x7001);
+        assert_eq!(cpu.registers[1], 1);
+        assert_eq!(cpu.memory.read_pc, 0x314);
+    }
+
+    #[test]
+    fn test_cpu_random_instruction() {
+        let mut cpu = CPU::with_rng(Quirks::default(), Box::new(FixedRng(0xAB)));
+
+        load_program(&mut cpu, "RND V0, 0x0F");
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 0x0B);
+    }
+
+    #[test]
+    fn test_cpu_random_instruction_is_seedable() {
+        let mut cpu1 = CPU::with_seed(Quirks::default(), 1234);
+        let mut cpu2 = CPU::with_seed(Quirks::default(), 1234);
+
+        for cpu in [&mut cpu1, &mut cpu2] {
+            // RND V0..V3, 0xFF
+            for register in 0..4 {
+                cpu.set_opcode(0xC0FF | register << 8).unwrap();
+            }
+
+            cpu.run().unwrap();
+        }
 
-        cpu.run();
+        assert_eq!(cpu1.registers, cpu2.registers);
+    }
 
+    #[test]
+    fn test_cpu_step() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x01
+            SE V0, 0x01
+            LD V1, 0x01 ; skipped
+            CLS
+            ",
+        );
+
+        let event = cpu.step().unwrap();
+
+        assert_eq!(event.opcode, Some(0x6001));
+        assert_eq!(event.instruction, Some(Instruction::LdByte(0x0, 0x01)));
+        assert_eq!(event.pc_before, 0x200);
+        assert_eq!(event.pc_after, 0x202);
+        assert_eq!(event.skipped, None);
         assert_eq!(cpu.registers[0], 1);
+
+        let event = cpu.step().unwrap();
+
+        assert_eq!(event.skipped, Some(0x204));
+        assert_eq!(event.pc_after, 0x206);
+        assert!(!event.display_changed);
+
+        let event = cpu.step().unwrap();
+
+        assert!(event.display_changed);
+        assert_eq!(cpu.registers[1], 0);
+
+        let event = cpu.step().unwrap();
+
+        assert!(event.halted);
+
+        // Nothing else is executed once halted
+        let event = cpu.step().unwrap();
+
+        assert!(event.halted);
+        assert_eq!(event.opcode, None);
+        assert_eq!(event.pc_before, event.pc_after);
     }
 
     #[test]
-    fn test_cpu_shr_instruction() {
-        let mut cpu = CPU::new();
+    fn test_cpu_step_waiting_for_key() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        cpu.set_opcode(0x8016);
+        load_program(&mut cpu, "LD V0, K");
 
-        cpu.registers[0] = 4;
+        let event = cpu.step().unwrap();
 
-        cpu.run();
+        assert_eq!(event.opcode, Some(0xF00A));
+        assert!(event.waiting_for_key);
 
-        assert_eq!(cpu.registers[0], 2);
+        let event = cpu.step().unwrap();
+
+        assert_eq!(event.opcode, None);
+        assert!(event.waiting_for_key);
+        assert_eq!(event.pc_after, 0x202);
     }
 
     #[test]
-    fn test_cpu_shr_vf_set_instruction() {
-        let mut cpu = CPU::new();
+    fn test_cpu_run_cycles() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        cpu.set_opcode(0x8016);
+        // ADD V0, 1 ; JP 0x200
+        cpu.set_opcode(0x7001).unwrap();
+        cpu.set_opcode(0x1200).unwrap();
 
-        cpu.registers[0] = 2;
+        assert_eq!(cpu.run_cycles(10).unwrap(), 10);
+        assert_eq!(cpu.registers[0], 5);
+
+        let mut cpu = CPU::new(Quirks::default());
+
+        // ADD V0, 1 ; Halt
+        cpu.set_opcode(0x7001).unwrap();
+
+        assert_eq!(cpu.run_cycles(10).unwrap(), 2);
+        assert!(cpu.is_halted());
+    }
+
+    #[test]
+    fn test_cpu_shift_uses_vy_quirk() {
+        let mut cpu = CPU::new(Quirks::cosmac_vip());
+
+        cpu.registers[1] = 0x81;
+
+        load_program(
+            &mut cpu,
+            "
+            SHR V0, V1
+            SHL V2, V1
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 0x40);
+        assert_eq!(cpu.registers[2], 0x02);
+        assert_eq!(cpu.registers[1], 0x81);
+    }
+
+    #[test]
+    fn test_cpu_logic_resets_vf_quirk() {
+        for (quirks, vf) in [(Quirks::cosmac_vip(), 0), (Quirks::modern(), 5)] {
+            let mut cpu = CPU::new(quirks);
+
+            cpu.registers[15] = 5;
+
+            load_program(&mut cpu, "OR V0, V1");
+
+            cpu.run().unwrap();
+
+            assert_eq!(cpu.registers[15], vf);
+        }
+    }
+
+    #[test]
+    fn test_cpu_load_store_increments_i_quirk() {
+        let mut cpu = CPU::new(Quirks::cosmac_vip());
+
+        load_program(
+            &mut cpu,
+            "
+            LD I, 0x300
+            LD [I], V2
+            LD V3, [I]
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.i_register, 0x300 + 3 + 4);
+
+        let mut cpu = CPU::new(Quirks::chip48());
+
+        load_program(&mut cpu, "LD I, 0x300\nLD [I], V2");
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.i_register, 0x300 + 2);
+    }
+
+    #[test]
+    fn test_cpu_display_wait_quirk() {
+        let mut cpu = CPU::new(Quirks::cosmac_vip());
+
+        load_program(
+            &mut cpu,
+            "
+            DRW V0, V0, 1
+            DRW V0, V0, 1
+            ",
+        );
+
+        assert!(cpu.step().unwrap().display_changed);
+
+        let event = cpu.step().unwrap();
+
+        assert!(event.waiting_for_display);
+        assert_eq!(event.pc_after, 0x202);
+
+        cpu.tick_timers();
+
+        assert!(cpu.step().unwrap().display_changed);
+        assert_eq!(cpu.program_counter(), 0x204);
+    }
+
+    #[test]
+    fn test_cpu_quirks_can_change_at_runtime() {
+        let mut cpu = CPU::new(Quirks::modern());
+
+        cpu.registers[1] = 0x10;
+        cpu.set_quirks(Quirks::chip48());
+
+        load_program(
+            &mut cpu,
+            "
+            JP V1, 0x100
+            ",
+        );
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.program_counter(), 0x110);
+        assert_eq!(cpu.quirks(), &Quirks::chip48());
+    }
+
+    /**
+     * Runs `8XYN` with the given operands and returns (Vx, VF)
+     */
+    fn run_arithmetic(opcode: u16, vx: u8, vy: u8) -> (u8, u8) {
+        let mut cpu = CPU::new(Quirks::default());
+
+        cpu.registers[0] = vx;
+        cpu.registers[1] = vy;
+
+        cpu.set_opcode(opcode).unwrap();
+        cpu.run().unwrap();
+
+        (cpu.registers[0], cpu.registers[15])
+    }
+
+    #[test]
+    fn test_cpu_arithmetic_table() {
+        for vx in 0..=255u8 {
+            for vy in 0..=255u8 {
+                let sum = vx as u16 + vy as u16;
+
+                // ADD V0, V1
+                assert_eq!(
+                    run_arithmetic(0x8014, vx, vy),
+                    (sum as u8, (sum > 255) as u8),
+                    "{vx} + {vy}"
+                );
+                // SUB V0, V1
+                assert_eq!(
+                    run_arithmetic(0x8015, vx, vy),
+                    (vx.wrapping_sub(vy), (vx >= vy) as u8),
+                    "{vx} - {vy}"
+                );
+                // SUBN V0, V1
+                assert_eq!(
+                    run_arithmetic(0x8017, vx, vy),
+                    (vy.wrapping_sub(vx), (vy >= vx) as u8),
+                    "{vy} - {vx}"
+                );
+            }
+
+            // SHR V0
+            assert_eq!(run_arithmetic(0x8016, vx, 0), (vx >> 1, vx & 1));
+            // SHL V0
+            assert_eq!(run_arithmetic(0x801E, vx, 0), (vx << 1, vx >> 7));
+        }
+    }
+
+    #[test]
+    fn test_cpu_arithmetic_vf_is_written_last() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        cpu.registers[15] = 200;
+        cpu.registers[1] = 100;
+
+        load_program(
+            &mut cpu,
+            "
+            ADD VF, V1 ; the carry wins over the sum
+            SUB V2, V3 ; 0 - 0, no borrow
+            SHR VF
+            ",
+        );
+
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers[15], 1);
 
-        cpu.run();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers[15], 1);
 
+        cpu.step().unwrap();
         assert_eq!(cpu.registers[15], 1);
     }
 
     #[test]
-    fn test_cpu_sub_y_minus_x_instruction() {
-        let mut cpu = CPU::new();
+    fn test_cpu_add_value_to_register_wraps() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        cpu.set_opcode(0x8017);
+        cpu.registers[0] = 0xFF;
+        cpu.registers[15] = 7;
 
-        cpu.registers[0] = 2;
-        cpu.registers[1] = 3;
+        load_program(&mut cpu, "ADD V0, 0x02");
 
-        cpu.run();
+        cpu.run().unwrap();
 
         assert_eq!(cpu.registers[0], 1);
+        assert_eq!(cpu.registers[15], 7);
     }
 
     #[test]
-    fn test_cpu_sub_y_minus_x_vf_set_instruction() {
-        let mut cpu = CPU::new();
+    fn test_cpu_load_rom() {
+        let mut cpu = CPU::new(Quirks::default());
 
-        cpu.set_opcode(0x8017);
+        // LD V0, 0x01 ; Halt
+        let rom = Rom::from_bytes(vec![0x60, 0x01, 0x00, 0x00], ETI660_LOAD_ADDRESS).unwrap();
 
-        cpu.registers[0] = 3;
-        cpu.registers[1] = 2;
+        cpu.load_rom(&rom).unwrap();
 
-        cpu.run();
+        assert_eq!(cpu.program_counter(), 0x600);
 
-        assert_eq!(cpu.registers[15], 1);
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.registers[0], 1);
     }
 
     #[test]
-    fn test_cpu_shl_instruction() {
-        let mut cpu = CPU::new();
+    fn test_cpu_load_rom_too_large() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        let rom = Rom::from_bytes(vec![0x0; 3585], DEFAULT_LOAD_ADDRESS).unwrap();
+
+        assert!(matches!(
+            cpu.load_rom(&rom),
+            Err(RomError::TooLarge {
+                size: 3585,
+                max: 3584
+            })
+        ));
+    }
 
-        cpu.set_opcode(0x801E);
+    #[test]
+    fn test_cpu_resolution_instructions() {
+        let mut cpu = CPU::new(Quirks::super_chip());
 
-        cpu.registers[0] = 72;
+        // HIGH
+        cpu.set_opcode(0x00FF).unwrap();
 
-        cpu.run();
+        cpu.step().unwrap();
 
-        assert_eq!(cpu.registers[0], 144);
+        assert!(cpu.framebuffer().is_hires());
+        assert_eq!(cpu.framebuffer().width(), 128);
+
+        // LOW
+        cpu.set_opcode(0x00FE).unwrap();
+
+        cpu.step().unwrap();
+
+        assert!(!cpu.framebuffer().is_hires());
+        assert_eq!(cpu.framebuffer().height(), 32);
     }
 
     #[test]
-    fn test_cpu_shl_vf_instruction() {
-        let mut cpu = CPU::new();
+    fn test_cpu_draw_large_sprite_instruction() {
+        let mut cpu = CPU::new(Quirks::super_chip());
 
-        cpu.set_opcode(0x801E);
+        cpu.memory.write_bytes(0x300, &[0xFF; 32]).unwrap();
+        cpu.i_register = 0x300;
+        cpu.registers[0] = 100;
+        cpu.registers[1] = 40;
 
-        cpu.registers[0] = 218;
+        load_program(
+            &mut cpu,
+            "
+            HIGH
+            DRW V0, V1, 0
+            ",
+        );
 
-        cpu.run();
+        cpu.run().unwrap();
 
-        // Due to registers size, the result is 180.
-        assert_eq!(cpu.registers[0], 180);
-        assert_eq!(cpu.registers[15], 1);
+        let fb = cpu.framebuffer();
+
+        assert!(fb.pixel(100, 40) && fb.pixel(115, 55));
+        assert!(!fb.pixel(116, 40) && !fb.pixel(100, 56));
+        assert_eq!(fb.pixels().iter().filter(|pixel| **pixel != 0).count(), 256);
+
+        // Without the quirk it draws nothing
+        let mut cpu = CPU::new(Quirks {
+            large_sprites: false,
+            ..Quirks::super_chip()
+        });
+
+        cpu.memory.write_bytes(0x300, &[0xFF; 32]).unwrap();
+        cpu.i_register = 0x300;
+
+        load_program(&mut cpu, "DRW V0, V1, 0");
+        cpu.run().unwrap();
+
+        assert!(cpu.framebuffer().pixels().iter().all(|pixel| *pixel == 0));
+
+        // And CHIP-8 does not know it
+        let mut cpu = CPU::new(Quirks::cosmac_vip());
+
+        load_program(&mut cpu, "DRW V0, V1, 0");
+
+        assert_eq!(
+            cpu.run(),
+            Err(EmulatorError::new(0x200, 0xD010, FaultKind::UnknownOpcode))
+        );
     }
 
     #[test]
-    fn test_cpu_skip_instruction_if_registers_not_equals() {
-        let mut cpu = CPU::new();
+    fn test_cpu_scroll_instructions() {
+        let mut cpu = CPU::new(Quirks::super_chip());
+
+        cpu.memory.write_into(0x8000, 0x300).unwrap();
+        cpu.i_register = 0x300;
+
+        load_program(
+            &mut cpu,
+            "
+            DRW V0, V0, 1
+            SCD 2
+            SCR
+            SCR
+            SCL
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert!(cpu.framebuffer().pixel(4, 2));
+        assert!(!cpu.framebuffer().pixel(0, 0));
+    }
 
-        // LD V0, 0x01
-        cpu.set_opcode(0x6001);
-        // If V0 == V1 -> Skip 1 instruction
-        cpu.set_opcode(0x9010);
+    #[test]
+    fn test_cpu_exit_instruction() {
+        let mut cpu = CPU::new(Quirks::super_chip());
 
-        cpu.run();
+        load_program(
+            &mut cpu,
+            "
+            EXIT
+            LD V0, 0x01
+            ",
+        );
 
-        // 8 = 2 bytes + 2 bytes + 2 bytes (skipped instruction) + 2 bytes (Halt)
-        assert_eq!(cpu.memory.read_pc, 0x200 + 8);
+        cpu.run().unwrap();
+
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.registers[0], 0);
+    }
+
+    #[test]
+    fn test_cpu_load_big_font_glyph_instruction() {
+        let mut cpu = CPU::new(Quirks::super_chip());
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0x08
+            LD HF, V0
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.i_register, 0xA0 + 8 * 10);
+        assert_eq!(
+            cpu.memory.read_bytes(cpu.i_register, 2).unwrap(),
+            &[0x3C, 0x7E]
+        );
+    }
+
+    #[test]
+    fn test_cpu_rpl_flags_instructions() {
+        let stored = std::rc::Rc::new(std::cell::RefCell::new([0x0; N_RPL_FLAGS]));
+        stored.borrow_mut()[2] = 9;
+
+        let mut cpu = CPU::new(Quirks::super_chip());
+
+        cpu.set_flag_storage(Box::new(SharedFlagStorage(stored.clone())));
+
+        // LD V2, R
+        cpu.set_opcode(0xF285).unwrap();
+
+        cpu.step().unwrap();
+
+        assert_eq!(cpu.registers[2], 9);
+
+        cpu.registers[0] = 1;
+        cpu.registers[1] = 2;
+
+        // LD R, V1
+        cpu.set_opcode(0xF175).unwrap();
+
+        cpu.step().unwrap();
+
+        assert_eq!(&stored.borrow()[0..3], &[1, 2, 9]);
+        assert_eq!(&cpu.rpl_flags()[0..3], &[1, 2, 9]);
+        assert!(cpu.take_flag_storage_error().is_none());
+
+        // A failed save is kept for the host, and the game goes on
+        let mut cpu = CPU::new(Quirks::super_chip());
+
+        cpu.set_flag_storage(Box::new(FileFlagStorage::new("/nonexistent/flags")));
+        load_program(
+            &mut cpu,
+            "LD R, V1
+LD V0, 1",
+        );
+
+        cpu.run_cycles(2).unwrap();
+
+        assert_eq!(cpu.registers[0], 1);
+        assert!(cpu.take_flag_storage_error().is_some());
+        assert!(cpu.take_flag_storage_error().is_none());
+    }
+
+    #[test]
+    fn test_cpu_super_chip_opcodes_need_the_quirk() {
+        let mut cpu = CPU::new(Quirks::cosmac_vip());
+
+        load_program(&mut cpu, "HIGH");
+
+        assert_eq!(
+            cpu.run(),
+            Err(EmulatorError::new(0x200, 0x00FF, FaultKind::UnknownOpcode))
+        );
+        assert!(!cpu.framebuffer().is_hires());
+    }
+
+    #[test]
+    fn test_cpu_xo_chip_opcodes_need_the_quirk() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        load_program(&mut cpu, "SAVE V0 - V1");
+
+        assert_eq!(
+            cpu.run(),
+            Err(EmulatorError::new(0x200, 0x5012, FaultKind::UnknownOpcode))
+        );
+    }
+
+    #[test]
+    fn test_cpu_xo_chip_long_load_instruction() {
+        let mut cpu = CPU::new(Quirks::xo_chip());
+
+        assert_eq!(cpu.memory.size(), 0x10000);
+
+        load_program(
+            &mut cpu,
+            "
+            LD V0, 0xFF
+            SE V0, 0xFF
+            LD I, 0x1234 ; skipped as a whole
+            LD I, 0xFFFD
+            LD B, V0
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.i_register, 0xFFFD);
+        assert_eq!(cpu.memory.read_bytes(0xFFFD, 3).unwrap(), &[2, 5, 5]);
+    }
+
+    #[test]
+    fn test_cpu_xo_chip_register_range_instructions() {
+        let mut cpu = CPU::new(Quirks::xo_chip());
+
+        cpu.registers[1] = 1;
+        cpu.registers[2] = 2;
+        cpu.registers[3] = 3;
+        cpu.i_register = 0x300;
+
+        load_program(
+            &mut cpu,
+            "
+            SAVE V3 - V1
+            LOAD V4 - V6
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.memory.read_bytes(0x300, 3).unwrap(), &[3, 2, 1]);
+        assert_eq!(&cpu.registers[4..7], &[3, 2, 1]);
+        assert_eq!(cpu.i_register, 0x300);
+    }
+
+    #[test]
+    fn test_cpu_xo_chip_plane_instruction() {
+        let mut cpu = CPU::new(Quirks::xo_chip());
+
+        // One row for each plane
+        cpu.memory.write_bytes(0x300, &[0x80, 0xC0]).unwrap();
+        cpu.i_register = 0x300;
+
+        load_program(
+            &mut cpu,
+            "
+            PLANE 3
+            DRW V0, V0, 1
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.framebuffer().color(0, 0), 3);
+        assert_eq!(cpu.framebuffer().color(1, 0), 2);
+        assert_eq!(cpu.registers[15], 0);
+    }
+
+    #[test]
+    fn test_cpu_xo_chip_audio_instructions() {
+        let mut cpu = CPU::new(Quirks::xo_chip());
+
+        cpu.memory.write_bytes(0x300, &[0xAA; 16]).unwrap();
+        cpu.i_register = 0x300;
+        cpu.registers[0] = 112;
+
+        load_program(
+            &mut cpu,
+            "
+            AUDIO
+            PITCH V0
+            ",
+        );
+
+        cpu.run().unwrap();
+
+        assert_eq!(cpu.audio().pattern(), Some(&[0xAA; 16]));
+        assert_eq!(cpu.audio().pitch(), 112);
+        assert_eq!(cpu.audio().sample_rate(), 8000.0);
     }
 }
@@ -0,0 +1,1184 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    cpu::{StepEvent, CPU},
+    error::EmulatorError,
+    expression::{Expression, Register},
+    instruction::{Instruction, LONG_LOAD_OPCODE},
+    memory::{AccessKind, MemoryAccess},
+    octo::SymbolTable,
+    savestate::{slot_path, SaveState},
+};
+
+/// Instructions `continue`, `next` and `finish` execute before giving the
+/// prompt back, so a game which never reaches a breakpoint does not hang
+pub const DEFAULT_RUN_LIMIT: u32 = 1_000_000;
+
+/// Bytes `memory` shows when no length is given
+const DEFAULT_HEXDUMP_LENGTH: usize = 64;
+/// Instructions `list` shows when no count is given
+const DEFAULT_LIST_LENGTH: usize = 8;
+
+const PROMPT: &str = "(chip8) ";
+
+const HELP: &str = "Commands (an empty line repeats the last one):
+  break, b [address] [if <condition>]  set a breakpoint, or list them
+  delete, d [address]       clear a breakpoint, or all of them
+  watch <address> [n]       stop when the program writes on the n bytes (1)
+  rwatch, awatch <address> [n]  stop when the program reads them, or reads and writes them
+  watch <register>          stop when V0 - VF, I, PC, DT, ST or SP changes
+  watch <condition>         stop when the condition becomes true: V3 == 0x10 && I > 0x300
+  watches                   list the watchpoints
+  unwatch [number]          delete a watchpoint, or all of them
+  step, s [count]           execute one instruction (or count)
+  next, n                   step over a CALL
+  finish, f                 run until the current subroutine returns
+  continue, c [limit]       run until a breakpoint (1000000 instructions at most)
+  registers, r              show V0 - VF, I, PC, the timers and the stack
+  stack                     show the stack and the stack pointer
+  memory, x <address> [n]   hexdump n bytes (64)
+  list, l [address] [n]     disassemble n instructions (8) from the address (PC)
+  set <register> <value>    write V0 - VF, I, PC, DT, ST or SP
+  write, w <address> <byte>...  write bytes on memory
+  key <key>                 press and release a key of the keypad (0 - F)
+  save [slot]               save the state of the machine on a quick-save slot (0 - 9)
+  load [slot]               load the state of a quick-save slot (0 - 9)
+  help, h                   show this help
+  quit, q                   exit the debugger
+The addresses are decimal, 0x hexadecimal or the labels of the symbols.
+The conditions use ! && || == != < <= > >= | ^ & + - and [address] to read a byte";
+
+/// The accesses a memory watchpoint stops on. The instruction fetches
+/// never stop it, the breakpoints are there for them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAccess {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl WatchAccess {
+    pub fn matches(&self, kind: AccessKind) -> bool {
+        matches!(
+            (self, kind),
+            (WatchAccess::Read | WatchAccess::ReadWrite, AccessKind::Read)
+                | (
+                    WatchAccess::Write | WatchAccess::ReadWrite,
+                    AccessKind::Write
+                )
+        )
+    }
+}
+
+/// Stops the execution on something other than the program counter
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Watchpoint {
+    /// An access to the bytes from `start` to `end` (included)
+    Memory {
+        start: u16,
+        end: u16,
+        access: WatchAccess,
+    },
+    /// A change of the value of the register
+    Register(Register),
+    /// The condition turning from false to true
+    Condition(Expression),
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory { start, end, access } => {
+                let access = match access {
+                    WatchAccess::Read => "read",
+                    WatchAccess::Write => "write",
+                    WatchAccess::ReadWrite => "read or write",
+                };
+
+                match start == end {
+                    true => write!(f, "{access} of {start:#05X}"),
+                    false => write!(f, "{access} of {start:#05X} - {end:#05X}"),
+                }
+            }
+            Watchpoint::Register(register) => write!(f, "change of {register}"),
+            Watchpoint::Condition(condition) => write!(f, "{condition}"),
+        }
+    }
+}
+
+/// Why the execution stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// `step` executed its instructions
+    Stepped,
+    Breakpoint(u16),
+    /// The program accessed the memory a watchpoint watches
+    MemoryAccess {
+        watchpoint: usize,
+        access: MemoryAccess,
+    },
+    RegisterChanged {
+        watchpoint: usize,
+        register: Register,
+        old: u32,
+        new: u32,
+    },
+    /// The condition of the watchpoint became true
+    Condition(usize),
+    /// `next` got over the call, or `finish` returned from the subroutine
+    Finished,
+    Halted,
+    WaitingForKey,
+    /// The instruction limit was reached first
+    Limit(u32),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Stepped | StopReason::Finished => Ok(()),
+            StopReason::Breakpoint(address) => write!(f, "Breakpoint at {address:#05X}"),
+            StopReason::MemoryAccess { watchpoint, access } => {
+                let kind = match access.kind {
+                    AccessKind::Fetch => "Fetch",
+                    AccessKind::Read => "Read",
+                    AccessKind::Write => "Write",
+                };
+
+                write!(
+                    f,
+                    "Watchpoint {watchpoint}: {kind} of {} bytes at {:#05X}",
+                    access.len, access.address
+                )
+            }
+            StopReason::RegisterChanged {
+                watchpoint,
+                register,
+                old,
+                new,
+            } => write!(
+                f,
+                "Watchpoint {watchpoint}: {register} changed from {old:#X} to {new:#X}"
+            ),
+            StopReason::Condition(watchpoint) => {
+                write!(f, "Watchpoint {watchpoint}: the condition is true")
+            }
+            StopReason::Halted => write!(f, "The program has halted"),
+            StopReason::WaitingForKey => write!(f, "Waiting for a key (use `key`)"),
+            StopReason::Limit(limit) => write!(f, "Stopped after {limit} instructions"),
+        }
+    }
+}
+
+/// Runs a [`CPU`] under the control of breakpoints and stepping commands.
+/// [`Debugger::repl`] reads the commands of `help` from any input, so it
+/// works on a terminal or on a test.
+///
+/// The memory access log is only enabled while there are memory
+/// watchpoints, and the other watchpoints are only evaluated while there
+/// are any, so without them the CPU runs as fast as on a normal run.
+///
+/// The timers tick every `instructions_per_tick` instructions, so the game
+/// sees the same timing it would see running at full speed
+
+#[derive(Debug)]
+pub struct Debugger {
+    cpu: CPU,
+    /// The address of each breakpoint, with its condition
+    breakpoints: BTreeMap<u16, Option<Expression>>,
+    /// The watchpoints by number, which never change once given
+    watchpoints: BTreeMap<usize, Watchpoint>,
+    next_watchpoint: usize,
+    symbols: SymbolTable,
+    /// The ROM the quick-save slots are kept next to
+    rom_path: Option<PathBuf>,
+    /// Instructions executed since the last timers tick
+    cycles: u32,
+    last_command: String,
+}
+
+impl Debugger {
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            cpu,
+            breakpoints: BTreeMap::new(),
+            watchpoints: BTreeMap::new(),
+            next_watchpoint: 1,
+            symbols: SymbolTable::default(),
+            rom_path: None,
+            cycles: 0,
+            last_command: String::new(),
+        }
+    }
+
+    /**
+     * Lets the commands use the labels of the table, and sets its breakpoints
+     */
+    pub fn with_symbols(mut self, symbols: SymbolTable) -> Self {
+        for address in symbols.breakpoints().keys() {
+            self.breakpoints.insert(*address, None);
+        }
+
+        self.symbols = symbols;
+        self
+    }
+
+    /**
+     * Enables `save` and `load`, whose slots are kept next to the ROM
+     */
+    pub fn with_rom_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.rom_path = Some(path.into());
+        self
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn breakpoints(&self) -> &BTreeMap<u16, Option<Expression>> {
+        &self.breakpoints
+    }
+
+    /**
+     * Returns false if there was already a breakpoint on the address
+     */
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address, None).is_none()
+    }
+
+    /**
+     * Sets a breakpoint which only stops if the condition is true,
+     * replacing the one on the address
+     */
+    pub fn add_conditional_breakpoint(&mut self, address: u16, condition: Expression) {
+        self.breakpoints.insert(address, Some(condition));
+    }
+
+    /**
+     * Returns false if there was no breakpoint on the address
+     */
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address).is_some()
+    }
+
+    pub fn watchpoints(&self) -> &BTreeMap<usize, Watchpoint> {
+        &self.watchpoints
+    }
+
+    /**
+     * Returns the number of the new watchpoint
+     */
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> usize {
+        let number = self.next_watchpoint;
+
+        self.watchpoints.insert(number, watchpoint);
+        self.next_watchpoint += 1;
+        self.update_access_log();
+
+        number
+    }
+
+    pub fn remove_watchpoint(&mut self, number: usize) -> Option<Watchpoint> {
+        let watchpoint = self.watchpoints.remove(&number);
+
+        self.update_access_log();
+
+        watchpoint
+    }
+
+    /**
+     * Enables the memory access log only while a watchpoint needs it
+     */
+    fn update_access_log(&mut self) {
+        let enabled = self
+            .watchpoints
+            .values()
+            .any(|watchpoint| matches!(watchpoint, Watchpoint::Memory { .. }));
+
+        self.cpu.memory_mut().set_access_log(enabled);
+    }
+
+    /**
+     * Returns the value each watchpoint is compared with after the step:
+     * the value of the registers and whether the conditions are true
+     */
+    fn watched_values(&self) -> Vec<u32> {
+        self.watchpoints
+            .values()
+            .map(|watchpoint| match watchpoint {
+                Watchpoint::Memory { .. } => 0,
+                Watchpoint::Register(register) => register.value(&self.cpu),
+                Watchpoint::Condition(condition) => condition.is_true(&self.cpu) as u32,
+            })
+            .collect()
+    }
+
+    /**
+     * Returns the first watchpoint the last step triggered
+     */
+    fn check_watchpoints(&mut self, before: &[u32]) -> Option<StopReason> {
+        let accesses = self.cpu.memory_mut().take_accesses();
+
+        for ((number, watchpoint), old) in self.watchpoints.iter().zip(before) {
+            let watchpoint_number = *number;
+
+            match watchpoint {
+                Watchpoint::Memory { start, end, access } => {
+                    let hit = accesses
+                        .iter()
+                        .find(|hit| access.matches(hit.kind) && hit.overlaps(*start, *end));
+
+                    if let Some(hit) = hit {
+                        return Some(StopReason::MemoryAccess {
+                            watchpoint: watchpoint_number,
+                            access: *hit,
+                        });
+                    }
+                }
+                Watchpoint::Register(register) => {
+                    let new = register.value(&self.cpu);
+
+                    if new != *old {
+                        return Some(StopReason::RegisterChanged {
+                            watchpoint: watchpoint_number,
+                            register: *register,
+                            old: *old,
+                            new,
+                        });
+                    }
+                }
+                Watchpoint::Condition(condition) => {
+                    if *old == 0 && condition.is_true(&self.cpu) {
+                        return Some(StopReason::Condition(watchpoint_number));
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /**
+     * Returns true if there is a breakpoint on the address, whose condition is true
+     */
+    fn breaks_at(&self, address: u16) -> bool {
+        match self.breakpoints.get(&address) {
+            Some(Some(condition)) => condition.is_true(&self.cpu),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /**
+     * Executes a single instruction, ticking the timers when a frame has passed
+     */
+    pub fn step(&mut self) -> Result<StepEvent, EmulatorError> {
+        let event = self.cpu.step()?;
+
+        self.cycles += 1;
+
+        if event.waiting_for_display || self.cycles >= self.cpu.instructions_per_tick() {
+            self.cpu.tick_timers();
+            self.cycles = 0;
+        }
+
+        Ok(event)
+    }
+
+    /**
+     * Executes up to `count` instructions, stopping on the breakpoints
+     */
+    pub fn step_instructions(&mut self, count: u32) -> Result<StopReason, EmulatorError> {
+        match self.run_until(count, |_| false)? {
+            StopReason::Limit(_) => Ok(StopReason::Stepped),
+            reason => Ok(reason),
+        }
+    }
+
+    /**
+     * Runs until a breakpoint, or until `limit` instructions have been executed
+     */
+    pub fn resume(&mut self, limit: u32) -> Result<StopReason, EmulatorError> {
+        self.run_until(limit, |_| false)
+    }
+
+    /**
+     * Steps over a **CALL**: runs until the subroutine returns to the next
+     * instruction. Any other instruction is a single step
+     */
+    pub fn step_over(&mut self, limit: u32) -> Result<StopReason, EmulatorError> {
+        let pc = self.cpu.program_counter();
+
+        match self.instruction_at(pc) {
+            Some((Instruction::Call(_), _)) => {
+                let depth = self.cpu.stack_pointer();
+
+                self.run_until(limit, |cpu| {
+                    cpu.program_counter() == pc.wrapping_add(2) && cpu.stack_pointer() == depth
+                })
+            }
+            _ => self.step_instructions(1),
+        }
+    }
+
+    /**
+     * Runs until the current subroutine returns (**RET**).
+     * Outside of a subroutine it runs like `resume`
+     */
+    pub fn step_out(&mut self, limit: u32) -> Result<StopReason, EmulatorError> {
+        let depth = self.cpu.stack_pointer();
+
+        self.run_until(limit, |cpu| cpu.stack_pointer() < depth)
+    }
+
+    fn run_until(
+        &mut self,
+        limit: u32,
+        done: impl Fn(&CPU) -> bool,
+    ) -> Result<StopReason, EmulatorError> {
+        let watching = !self.watchpoints.is_empty();
+
+        // The accesses of the commands (`write`) do not count
+        self.cpu.memory_mut().take_accesses();
+
+        for _ in 0..limit {
+            let before = match watching {
+                true => self.watched_values(),
+                false => Vec::new(),
+            };
+
+            let event = self.step()?;
+
+            if event.halted {
+                return Ok(StopReason::Halted);
+            }
+
+            if event.waiting_for_key {
+                return Ok(StopReason::WaitingForKey);
+            }
+
+            if watching {
+                if let Some(reason) = self.check_watchpoints(&before) {
+                    return Ok(reason);
+                }
+            }
+
+            if done(&self.cpu) {
+                return Ok(StopReason::Finished);
+            }
+
+            let pc = self.cpu.program_counter();
+            if self.breaks_at(pc) {
+                return Ok(StopReason::Breakpoint(pc));
+            }
+        }
+
+        Ok(StopReason::Limit(limit))
+    }
+
+    /**
+     * Decodes the instruction on the address, with its bytes
+     */
+    fn instruction_at(&self, address: u16) -> Option<(Instruction, Vec<u8>)> {
+        let memory = self.cpu.memory();
+        let bytes = memory.read_bytes(address, 2).ok()?;
+        let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+
+        if opcode == LONG_LOAD_OPCODE && self.cpu.quirks().xo_chip {
+            let bytes = memory.read_bytes(address, 4).ok()?;
+            let next = u16::from_be_bytes([bytes[2], bytes[3]]);
+
+            return Some((Instruction::decode(opcode, next), bytes.to_vec()));
+        }
+
+        Some((Instruction::decode(opcode, 0x0), bytes.to_vec()))
+    }
+
+    /**
+     * Parses a decimal or 0x hexadecimal number, or the name of a label or a constant
+     */
+    fn value(&self, text: &str) -> Option<u32> {
+        let value = match text.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => text.parse().ok(),
+        };
+
+        value.or_else(|| self.symbol(text))
+    }
+
+    /**
+     * Returns the address of the label, or the value of the constant
+     */
+    fn symbol(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .labels()
+            .get(name)
+            .map(|address| *address as u32)
+            .or_else(|| {
+                self.symbols
+                    .constants()
+                    .get(name)
+                    .and_then(|value| u32::try_from(*value).ok())
+            })
+    }
+
+    fn condition(&self, source: &str) -> Result<Expression, String> {
+        Expression::parse(source, |name| self.symbol(name)).map_err(|err| err.to_string())
+    }
+
+    /**
+     * Parses the arguments of `watch`, `rwatch` and `awatch`: an address and
+     * an optional length, a register or a condition
+     */
+    fn watchpoint(&self, args: &[&str], access: WatchAccess) -> Result<Watchpoint, String> {
+        let length = match args {
+            [_] => Some(1),
+            [_, length] => self.value(length),
+            _ => None,
+        };
+
+        if let Some(register) = args.first().and_then(|name| Register::from_name(name)) {
+            if args.len() == 1 && access == WatchAccess::Write {
+                return Ok(Watchpoint::Register(register));
+            }
+        }
+
+        match (args.first().and_then(|arg| self.address(arg)), length) {
+            (Some(_), Some(0)) => Err("the length must be at least 1".to_string()),
+            (Some(start), Some(length)) => {
+                let Some(end) = (start as u32).checked_add(length - 1) else {
+                    return Err(format!("the length {length} is out of the memory"));
+                };
+
+                if end as usize >= self.cpu.memory().size() {
+                    return Err(format!("{end:#X} is out of the memory"));
+                }
+
+                Ok(Watchpoint::Memory {
+                    start,
+                    end: end as u16,
+                    access,
+                })
+            }
+            _ if access == WatchAccess::Write => {
+                self.condition(&args.join(" ")).map(Watchpoint::Condition)
+            }
+            _ => Err(format!("invalid address <{}>", args.join(" "))),
+        }
+    }
+
+    fn address(&self, text: &str) -> Option<u16> {
+        self.value(text).and_then(|value| u16::try_from(value).ok())
+    }
+
+    /**
+     * Writes `count` instructions from the address, as the disassembler does
+     */
+    fn write_listing(&self, out: &mut impl Write, address: u16, count: usize) -> io::Result<()> {
+        let mut address = address;
+
+        for _ in 0..count {
+            let Some((instruction, bytes)) = self.instruction_at(address) else {
+                break;
+            };
+
+            if let Some(label) = self.symbols.label_at(address) {
+                writeln!(out, "{label}:")?;
+            }
+
+            let marker = match address == self.cpu.program_counter() {
+                true => "=>",
+                false => "  ",
+            };
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+            let target_label = instruction
+                .target()
+                .and_then(|target| self.symbols.label_at(target));
+
+            write!(out, "{marker} {address:#05X}  {:<12} ", bytes.join(" "))?;
+
+            match target_label {
+                Some(label) => writeln!(out, "{}", instruction.with_label(label))?,
+                None => writeln!(out, "{instruction}")?,
+            }
+
+            address = address.wrapping_add(bytes.len() as u16);
+        }
+
+        Ok(())
+    }
+
+    fn write_registers(&self, out: &mut impl Write) -> io::Result<()> {
+        let cpu = &self.cpu;
+
+        for row in cpu.registers.chunks(8).enumerate() {
+            let (index, registers) = row;
+            let registers: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(x, value)| format!("V{:X} {value:02X}", index * 8 + x))
+                .collect();
+
+            writeln!(out, "{}", registers.join("  "))?;
+        }
+
+        writeln!(
+            out,
+            "PC {:#05X}  I {:#05X}  DT {}  ST {}",
+            cpu.program_counter(),
+            cpu.i_register,
+            cpu.delay_timer,
+            cpu.sound_timer
+        )?;
+
+        self.write_stack(out)
+    }
+
+    fn write_stack(&self, out: &mut impl Write) -> io::Result<()> {
+        let depth = self.cpu.stack_pointer() as usize;
+        let entries: Vec<String> = self.cpu.stack().entries()[..depth]
+            .iter()
+            .map(|address| format!("{address:#05X}"))
+            .collect();
+
+        match entries.is_empty() {
+            true => writeln!(out, "SP {depth}  stack empty"),
+            false => writeln!(out, "SP {depth}  stack {}", entries.join(" ")),
+        }
+    }
+
+    /**
+     * Writes 16 bytes per line: the address, the bytes and their ASCII characters
+     */
+    fn write_hexdump(&self, out: &mut impl Write, address: u16, length: usize) -> io::Result<()> {
+        let memory = self.cpu.memory();
+        let end = (address as usize + length).min(memory.size());
+
+        for start in (address as usize..end).step_by(16) {
+            let bytes = memory
+                .read_bytes(start as u16, (end - start).min(16))
+                .unwrap_or_default();
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+            let ascii: String = bytes
+                .iter()
+                .map(|byte| match byte.is_ascii_graphic() || *byte == b' ' {
+                    true => *byte as char,
+                    false => '.',
+                })
+                .collect();
+
+            writeln!(out, "{start:#06X}  {:<47}  {ascii}", hex.join(" "))?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Saves the state on a quick-save slot (0 - 9), or loads it.
+     * Returns the file of the slot
+     */
+    fn quick_save(&mut self, save: bool, slot: Option<u8>) -> Result<PathBuf, String> {
+        let path = match (&self.rom_path, slot) {
+            (Some(rom_path), Some(slot @ 0..=9)) => slot_path(rom_path, slot),
+            (None, _) => return Err("the quick-save slots need the path of the ROM".to_string()),
+            _ => return Err("invalid slot (0 - 9)".to_string()),
+        };
+
+        let result = match save {
+            true => self.cpu.save_state().save_to_file(&path),
+            false => SaveState::from_file(&path).map(|state| self.cpu.restore_state(&state)),
+        };
+
+        match result {
+            Ok(()) => Ok(path),
+            Err(err) => Err(format!("{err} ({})", path.display())),
+        }
+    }
+
+    /**
+     * Shows why the execution stopped and the next instruction
+     */
+    fn report(
+        &self,
+        out: &mut impl Write,
+        result: Result<StopReason, EmulatorError>,
+    ) -> io::Result<()> {
+        match result {
+            Ok(reason) => {
+                let reason = reason.to_string();
+
+                if !reason.is_empty() {
+                    writeln!(out, "{reason}")?;
+                }
+            }
+            Err(err) => writeln!(out, "Error: {err}")?,
+        }
+
+        self.write_listing(out, self.cpu.program_counter(), 1)
+    }
+
+    /**
+     * Executes a command line. Returns false on `quit`
+     */
+    pub fn execute(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => {
+                self.last_command = line.to_string();
+                line.to_string()
+            }
+        };
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = words.split_first() else {
+            return Ok(true);
+        };
+
+        let count = |arg: Option<&&str>, default: u32| match arg {
+            Some(arg) => self.value(arg),
+            None => Some(default),
+        };
+
+        match (*command, args) {
+            ("help" | "h", []) => writeln!(out, "{HELP}")?,
+            ("quit" | "q", []) => return Ok(false),
+            ("break" | "b", []) => {
+                if self.breakpoints.is_empty() {
+                    writeln!(out, "No breakpoints")?;
+                }
+
+                for (address, condition) in &self.breakpoints {
+                    let name = self
+                        .symbols
+                        .breakpoints()
+                        .get(address)
+                        .map(String::as_str)
+                        .or_else(|| self.symbols.label_at(*address));
+
+                    write!(out, "Breakpoint at {address:#05X}")?;
+
+                    if let Some(name) = name {
+                        write!(out, " ({name})")?;
+                    }
+
+                    match condition {
+                        Some(condition) => writeln!(out, " if {condition}")?,
+                        None => writeln!(out)?,
+                    }
+                }
+            }
+            ("break" | "b", [address]) => match self.address(address) {
+                Some(address) => {
+                    self.add_breakpoint(address);
+                    writeln!(out, "Breakpoint at {address:#05X}")?;
+                }
+                None => writeln!(out, "Invalid address <{address}>")?,
+            },
+            ("break" | "b", [address, "if", condition @ ..]) => {
+                match (self.address(address), self.condition(&condition.join(" "))) {
+                    (Some(address), Ok(condition)) => {
+                        writeln!(out, "Breakpoint at {address:#05X} if {condition}")?;
+                        self.add_conditional_breakpoint(address, condition);
+                    }
+                    (None, _) => writeln!(out, "Invalid address <{address}>")?,
+                    (_, Err(message)) => writeln!(out, "Error: {message}")?,
+                }
+            }
+            ("watch" | "rwatch" | "awatch", [_, ..]) => {
+                let access = match *command {
+                    "rwatch" => WatchAccess::Read,
+                    "awatch" => WatchAccess::ReadWrite,
+                    _ => WatchAccess::Write,
+                };
+
+                match self.watchpoint(args, access) {
+                    Ok(watchpoint) => {
+                        let description = watchpoint.to_string();
+                        let number = self.add_watchpoint(watchpoint);
+
+                        writeln!(out, "Watchpoint {number}: {description}")?;
+                    }
+                    Err(message) => writeln!(out, "Error: {message}")?,
+                }
+            }
+            ("watches", []) => {
+                if self.watchpoints.is_empty() {
+                    writeln!(out, "No watchpoints")?;
+                }
+
+                for (number, watchpoint) in &self.watchpoints {
+                    writeln!(out, "Watchpoint {number}: {watchpoint}")?;
+                }
+            }
+            ("unwatch", []) => {
+                self.watchpoints.clear();
+                self.update_access_log();
+                writeln!(out, "All watchpoints deleted")?;
+            }
+            ("unwatch", [number]) => {
+                let removed = number
+                    .parse()
+                    .ok()
+                    .and_then(|number| self.remove_watchpoint(number));
+
+                match removed {
+                    Some(_) => writeln!(out, "Watchpoint {number} deleted")?,
+                    None => writeln!(out, "No watchpoint <{number}>")?,
+                }
+            }
+            ("delete" | "d", []) => {
+                self.breakpoints.clear();
+                writeln!(out, "All breakpoints deleted")?;
+            }
+            ("delete" | "d", [address]) => match self.address(address) {
+                Some(address) if self.remove_breakpoint(address) => {
+                    writeln!(out, "Breakpoint at {address:#05X} deleted")?
+                }
+                _ => writeln!(out, "No breakpoint at <{address}>")?,
+            },
+            ("step" | "s", [] | [_]) => match count(args.first(), 1) {
+                Some(count) => {
+                    let result = self.step_instructions(count);
+                    self.report(out, result)?;
+                }
+                None => writeln!(out, "Invalid count <{}>", args[0])?,
+            },
+            ("next" | "n", []) => {
+                let result = self.step_over(DEFAULT_RUN_LIMIT);
+                self.report(out, result)?;
+            }
+            ("finish" | "f", []) => {
+                if self.cpu.stack_pointer() == 0 {
+                    writeln!(out, "Not in a subroutine")?;
+                } else {
+                    let result = self.step_out(DEFAULT_RUN_LIMIT);
+                    self.report(out, result)?;
+                }
+            }
+            ("continue" | "c", [] | [_]) => match count(args.first(), DEFAULT_RUN_LIMIT) {
+                Some(limit) => {
+                    let result = self.resume(limit);
+                    self.report(out, result)?;
+                }
+                None => writeln!(out, "Invalid limit <{}>", args[0])?,
+            },
+            ("registers" | "r", []) => self.write_registers(out)?,
+            ("stack", []) => self.write_stack(out)?,
+            ("memory" | "x", [address] | [address, _]) => {
+                let length = args
+                    .get(1)
+                    .map_or(Some(DEFAULT_HEXDUMP_LENGTH as u32), |arg| self.value(arg));
+
+                match (self.address(address), length) {
+                    (Some(address), Some(length)) => {
+                        self.write_hexdump(out, address, length as usize)?
+                    }
+                    _ => writeln!(out, "Invalid address or length")?,
+                }
+            }
+            ("list" | "l", [] | [_] | [_, _]) => {
+                let address = args
+                    .first()
+                    .map_or(Some(self.cpu.program_counter()), |arg| self.address(arg));
+                let length = args
+                    .get(1)
+                    .map_or(Some(DEFAULT_LIST_LENGTH as u32), |arg| self.value(arg));
+
+                match (address, length) {
+                    (Some(address), Some(length)) => {
+                        self.write_listing(out, address, length as usize)?
+                    }
+                    _ => writeln!(out, "Invalid address or count")?,
+                }
+            }
+            ("set", [register, value]) => {
+                let result = match (Register::from_name(register), self.value(value)) {
+                    (Some(register), Some(value)) => register.write(&mut self.cpu, value),
+                    (None, _) => Err(format!("unknown register <{register}>")),
+                    (_, None) => Err(format!("invalid value <{value}>")),
+                };
+
+                if let Err(message) = result {
+                    writeln!(out, "Error: {message}")?;
+                }
+            }
+            ("write" | "w", [address, bytes @ ..]) if !bytes.is_empty() => {
+                let bytes: Option<Vec<u8>> = bytes
+                    .iter()
+                    .map(|byte| self.value(byte).and_then(|value| u8::try_from(value).ok()))
+                    .collect();
+
+                let result = match (self.address(address), bytes) {
+                    (Some(address), Some(bytes)) => self
+                        .cpu
+                        .memory_mut()
+                        .write_bytes(address, &bytes)
+                        .map_err(|err| err.to_string()),
+                    _ => Err("invalid address or bytes".to_string()),
+                };
+
+                if let Err(message) = result {
+                    writeln!(out, "Error: {message}")?;
+                }
+            }
+            ("save" | "load", [] | [_]) => {
+                let slot = args.first().map_or(Some(0), |slot| slot.parse().ok());
+
+                match self.quick_save(*command == "save", slot) {
+                    Ok(path) if *command == "save" => {
+                        writeln!(out, "State saved on {}", path.display())?
+                    }
+                    Ok(_) => self.write_listing(out, self.cpu.program_counter(), 1)?,
+                    Err(message) => writeln!(out, "Error: {message}")?,
+                }
+            }
+            ("key", [key]) => match self.value(key).filter(|key| *key <= 0xF) {
+                Some(key) => {
+                    self.cpu.press_key(key as u8);
+                    self.cpu.release_key(key as u8);
+                }
+                None => writeln!(out, "Invalid key <{key}> (0 - F)")?,
+            },
+            _ => writeln!(out, "Unknown command <{line}>, type `help`")?,
+        }
+
+        Ok(true)
+    }
+
+    /**
+     * Reads and executes commands until `quit` or the end of the input
+     */
+    pub fn repl(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        self.write_listing(&mut output, self.cpu.program_counter(), 1)?;
+        write!(output, "{PROMPT}")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            if !self.execute(&line?, &mut output)? {
+                break;
+            }
+
+            write!(output, "{PROMPT}")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{Debugger, StopReason, WatchAccess, Watchpoint, DEFAULT_RUN_LIMIT};
+    use crate::{
+        asm::Assembler,
+        cpu::CPU,
+        expression::{Expression, Register},
+        memory::{AccessKind, MemoryAccess},
+        octo::{InstructionSet, OctoCompiler},
+        quirks::Quirks,
+        rom::{Rom, DEFAULT_LOAD_ADDRESS},
+    };
+
+    fn debugger(source: &str) -> Debugger {
+        let program = Assembler::new().assemble(source).unwrap();
+        let mut cpu = CPU::new(Quirks::default());
+
+        cpu.load_rom(&Rom::from_bytes(program, DEFAULT_LOAD_ADDRESS).unwrap())
+            .unwrap();
+
+        Debugger::new(cpu)
+    }
+
+    #[test]
+    fn test_breakpoints_and_stepping() {
+        let mut debugger = debugger(
+            "
+                CALL add       ; 0x200
+                LD V1, 1       ; 0x202
+                HALT           ; 0x204
+            add:
+                CALL inner     ; 0x206
+                ADD V0, 1      ; 0x208
+                RET
+            inner:
+                ADD V0, 1      ; 0x20C
+                RET
+            ",
+        );
+
+        // Over the whole call
+        assert_eq!(
+            debugger.step_over(DEFAULT_RUN_LIMIT),
+            Ok(StopReason::Finished)
+        );
+        assert_eq!(debugger.cpu().program_counter(), 0x202);
+        assert_eq!(debugger.cpu().registers[0], 2);
+
+        debugger.cpu_mut().set_program_counter(0x200);
+        debugger.add_breakpoint(0x20C);
+
+        assert_eq!(
+            debugger.resume(DEFAULT_RUN_LIMIT),
+            Ok(StopReason::Breakpoint(0x20C))
+        );
+        assert_eq!(debugger.cpu().stack_pointer(), 2);
+
+        // Out of inner, then out of add
+        assert_eq!(
+            debugger.step_out(DEFAULT_RUN_LIMIT),
+            Ok(StopReason::Finished)
+        );
+        assert_eq!(debugger.cpu().program_counter(), 0x208);
+        assert_eq!(
+            debugger.step_out(DEFAULT_RUN_LIMIT),
+            Ok(StopReason::Finished)
+        );
+        assert_eq!(debugger.cpu().program_counter(), 0x202);
+
+        assert_eq!(debugger.step_instructions(1), Ok(StopReason::Stepped));
+        assert_eq!(debugger.resume(DEFAULT_RUN_LIMIT), Ok(StopReason::Halted));
+    }
+
+    #[test]
+    fn test_watchpoints_and_conditional_breakpoints() {
+        let mut debugger = debugger(
+            "
+                LD I, 0x300    ; 0x200
+                LD V0, 5       ; 0x202
+                LD [I], V0     ; 0x204
+                LD V3, 0x10    ; 0x206
+                LD I, 0x310    ; 0x208
+                LD V0, [I]     ; 0x20A
+                LD V1, 1       ; 0x20C
+                HALT           ; 0x20E
+            ",
+        );
+        let condition = |source| Expression::parse(source, |_| None).unwrap();
+
+        debugger.add_watchpoint(Watchpoint::Memory {
+            start: 0x2FF,
+            end: 0x300,
+            access: WatchAccess::ReadWrite,
+        });
+        debugger.add_watchpoint(Watchpoint::Register(Register::V(3)));
+        debugger.add_watchpoint(Watchpoint::Memory {
+            start: 0x310,
+            end: 0x310,
+            access: WatchAccess::Read,
+        });
+        debugger.add_watchpoint(Watchpoint::Condition(condition("V1 == 1 && I > 0x300")));
+
+        assert!(debugger.cpu().memory().is_logging_accesses());
+        assert_eq!(
+            debugger.resume(DEFAULT_RUN_LIMIT),
+            Ok(StopReason::MemoryAccess {
+                watchpoint: 1,
+                access: MemoryAccess {
+                    kind: AccessKind::Write,
+                    address: 0x300,
+                    len: 1
+                }
+            })
+        );
+        assert_eq!(
+            debugger.resume(DEFAULT_RUN_LIMIT),
+            Ok(StopReason::RegisterChanged {
+                watchpoint: 2,
+                register: Register::V(3),
+                old: 0x0,
+                new: 0x10
+            })
+        );
+        assert!(matches!(
+            debugger.resume(DEFAULT_RUN_LIMIT),
+            Ok(StopReason::MemoryAccess { watchpoint: 3, .. })
+        ));
+        assert_eq!(
+            debugger.resume(DEFAULT_RUN_LIMIT),
+            Ok(StopReason::Condition(4))
+        );
+        assert_eq!(debugger.cpu().program_counter(), 0x20E);
+
+        for number in 1..=4 {
+            assert!(debugger.remove_watchpoint(number).is_some());
+        }
+        assert!(!debugger.cpu().memory().is_logging_accesses());
+
+        // The byte on 0x300 is still 0 when 0x206 is reached the first time
+        debugger.cpu_mut().set_program_counter(0x200);
+        debugger
+            .cpu_mut()
+            .memory_mut()
+            .write_bytes(0x300, &[0])
+            .unwrap();
+        debugger.add_conditional_breakpoint(0x206, condition("[0x300] == 6"));
+        assert_eq!(debugger.resume(4), Ok(StopReason::Limit(4)));
+        assert_eq!(debugger.cpu().program_counter(), 0x208);
+
+        debugger.cpu_mut().set_program_counter(0x200);
+        debugger.add_conditional_breakpoint(0x206, condition("[I] == V0"));
+        assert_eq!(
+            debugger.resume(DEFAULT_RUN_LIMIT),
+            Ok(StopReason::Breakpoint(0x206))
+        );
+    }
+
+    #[test]
+    fn test_timers_tick_while_stepping() {
+        let mut debugger = debugger("loop: JP loop");
+
+        debugger.cpu_mut().delay_timer = 2;
+
+        assert_eq!(debugger.resume(22), Ok(StopReason::Limit(22)));
+        assert_eq!(debugger.cpu().delay_timer, 0);
+    }
+
+    #[test]
+    fn test_repl_commands() {
+        let program = OctoCompiler::new(InstructionSet::XoChip)
+            .compile(": main v0 := 1 :breakpoint check v1 := 2 : data 0 0")
+            .unwrap();
+        let mut cpu = CPU::new(Quirks::default());
+        cpu.load_rom(&Rom::from_bytes(program.bytes, DEFAULT_LOAD_ADDRESS).unwrap())
+            .unwrap();
+
+        let mut debugger = Debugger::new(cpu).with_symbols(program.symbols);
+        let input = "continue\nset va 0x2A\nset i data\nwrite data 0x41 0x42\n\nregisters\nmemory data 2\nbogus\nwatch data\nwatch v1\nwatch va == 0x2A && i == data\nrwatch 0xFFFF 2\nawatch 0x300 0xFFFFFFFF\nwatch 0 0\nbreak main if v0 > 1\nbreak\nunwatch 1\nwatches\nsave\nquit\nstep\n";
+        let mut output = Vec::new();
+
+        debugger.repl(Cursor::new(input), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+
+        assert!(output.contains("Breakpoint at 0x202\n=> 0x202  61 02"));
+        assert!(output.contains("V8 00  V9 00  VA 2A"));
+        assert!(output.contains("PC 0x202  I 0x204  DT 0  ST 0\nSP 0  stack empty"));
+        assert!(output.contains("0x0204  41 42"));
+        assert!(output.contains("Unknown command <bogus>"));
+        assert!(output.contains("Error: the quick-save slots need the path of the ROM"));
+        assert!(output.contains("Watchpoint 1: write of 0x204\n"));
+        assert!(output.contains("Error: 0x10000 is out of the memory"));
+        assert!(output.contains("Error: the length 4294967295 is out of the memory"));
+        assert!(output.contains("Error: the length must be at least 1"));
+        assert!(
+            output.contains("Breakpoint at 0x200 (main) if v0 > 1\nBreakpoint at 0x202 (check)\n")
+        );
+        assert!(output.contains(
+            "Watchpoint 2: change of V1\nWatchpoint 3: va == 0x2A && i == data\n(chip8)"
+        ));
+        // The empty line repeated the write, and quit stopped before the last step
+        assert_eq!(debugger.cpu().registers[1], 0);
+    }
+}
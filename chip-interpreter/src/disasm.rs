@@ -0,0 +1,185 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::{instruction::Instruction, rom::Rom};
+
+/// An instruction of a ROM, with the address and the bytes it was decoded from
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledInstruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub instruction: Instruction,
+}
+
+/// Listing of a whole ROM. Its `Display` prints a line per instruction:
+/// address, raw bytes and mnemonic, with the labels before their targets
+///
+/// ```text
+/// sub_206:
+/// 0x206  60 01        LD V0, 0x01
+/// ```
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+    instructions: Vec<DisassembledInstruction>,
+    labels: BTreeMap<u16, String>,
+}
+
+impl Disassembly {
+    /**
+     * Decodes the ROM from its start to its end, one instruction after another.
+     * Code and data are not told apart, so the data is shown as instructions too
+     */
+    pub fn new(rom: &Rom) -> Self {
+        let data = rom.data();
+        let mut instructions = Vec::new();
+        let mut offset = 0;
+
+        while offset < data.len() {
+            let word = |at: usize| match data.get(at..at + 2) {
+                Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+                None => (*data.get(at).unwrap_or(&0) as u16) << 8,
+            };
+
+            let mut instruction = Instruction::decode(word(offset), word(offset + 2));
+            let mut size = instruction.size() as usize;
+
+            // A truncated instruction at the end of the ROM is only data
+            if offset + size > data.len() {
+                instruction = Instruction::Unknown(word(offset));
+                size = (data.len() - offset).min(2);
+            }
+
+            instructions.push(DisassembledInstruction {
+                address: rom.load_address().wrapping_add(offset as u16),
+                bytes: data[offset..offset + size].to_vec(),
+                instruction,
+            });
+
+            offset += size;
+        }
+
+        Self {
+            instructions,
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /**
+     * Names the targets of the jumps (`label_NNN`) and the calls (`sub_NNN`).
+     * Only the targets which start an instruction of the listing get a label
+     */
+    pub fn with_labels(mut self) -> Self {
+        let starts: Vec<u16> = self.instructions.iter().map(|line| line.address).collect();
+
+        for line in &self.instructions {
+            let Some(target) = line.instruction.target() else {
+                continue;
+            };
+
+            if !starts.contains(&target) {
+                continue;
+            }
+
+            let name = match line.instruction {
+                Instruction::Call(_) => format!("sub_{target:03X}"),
+                _ => format!("label_{target:03X}"),
+            };
+
+            // A call names the target better than a jump
+            let label = self.labels.entry(target).or_insert(name.clone());
+            if name.starts_with("sub_") {
+                *label = name;
+            }
+        }
+
+        self
+    }
+
+    pub fn instructions(&self) -> &[DisassembledInstruction] {
+        &self.instructions
+    }
+
+    /**
+     * Returns the label of each address which has one
+     */
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.instructions {
+            if let Some(label) = self.labels.get(&line.address) {
+                writeln!(f, "{label}:")?;
+            }
+
+            let bytes: Vec<String> = line
+                .bytes
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect();
+            let target_label = line
+                .instruction
+                .target()
+                .and_then(|target| self.labels.get(&target));
+
+            write!(f, "{:#05X}  {:<12} ", line.address, bytes.join(" "))?;
+
+            match target_label {
+                Some(label) => writeln!(f, "{}", line.instruction.with_label(label))?,
+                None => writeln!(f, "{}", line.instruction)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Disassembly;
+    use crate::{instruction::Instruction, rom::Rom, DEFAULT_LOAD_ADDRESS};
+
+    #[test]
+    fn test_disassemble() {
+        // LD V0, 0x01 ; LD I, 0x1234 ; and a truncated byte
+        let rom = Rom::from_bytes(
+            vec![0x60, 0x01, 0xF0, 0x00, 0x12, 0x34, 0xAB],
+            DEFAULT_LOAD_ADDRESS,
+        )
+        .unwrap();
+
+        let disassembly = Disassembly::new(&rom);
+        let instructions = disassembly.instructions();
+
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions[1].address, 0x202);
+        assert_eq!(instructions[1].instruction, Instruction::LdLongI(0x1234));
+        assert_eq!(instructions[2].bytes, vec![0xAB]);
+        assert_eq!(instructions[2].instruction, Instruction::Unknown(0xAB00));
+    }
+
+    #[test]
+    fn test_disassemble_with_labels() {
+        // CALL 0x206 ; JP 0x202 ; JP 0x201 ; RET
+        let rom = Rom::from_bytes(
+            vec![0x22, 0x06, 0x12, 0x02, 0x12, 0x01, 0x00, 0xEE],
+            DEFAULT_LOAD_ADDRESS,
+        )
+        .unwrap();
+
+        let listing = Disassembly::new(&rom).with_labels().to_string();
+
+        assert_eq!(
+            listing,
+            "0x200  22 06        CALL sub_206\n\
+             label_202:\n\
+             0x202  12 02        JP label_202\n\
+             0x204  12 01        JP 0x201\n\
+             sub_206:\n\
+             0x206  00 EE        RET\n"
+        );
+    }
+}
@@ -0,0 +1,513 @@
+/// Low resolution screen (CHIP-8)
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+/// High resolution screen (SUPER-CHIP)
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+
+/// XO-CHIP bit planes. Each pixel has a bit per plane, so it can take 4 colours
+pub const N_PLANES: usize = 2;
+
+/// Framebuffer of 64x32 pixels (low resolution) or 128x64 pixels
+/// (high resolution). It is stored row by row, so the pixel (x, y)
+/// lives at `y * width + x`.
+///
+/// Each pixel keeps a bit per plane: bit 0 is the first plane (the only one
+/// the CHIP-8 and SUPER-CHIP games use) and bit 1 is the second XO-CHIP plane.
+/// So a pixel is either off (0) or one of the colours 1, 2 or 3.
+///
+/// Both resolutions fill the same physical screen, so a frontend must
+/// scale the low resolution pixels twice as much as the high resolution ones
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    pixels: Vec<u8>,
+    hires: bool,
+    planes: u8,
+}
+
+#[allow(dead_code)]
+impl Framebuffer {
+    pub fn new() -> Self {
+        Self {
+            pixels: vec![0x0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            hires: false,
+            planes: 0b01,
+        }
+    }
+
+    /**
+     * Rebuilds a framebuffer from its pixels, which must fill the resolution
+     */
+    pub fn from_pixels(pixels: Vec<u8>, hires: bool, planes: u8) -> Option<Self> {
+        let mut framebuffer = Self::new();
+
+        framebuffer.set_hires(hires, true);
+        framebuffer.set_planes(planes);
+
+        if pixels.len() != framebuffer.pixels.len() || pixels.iter().any(|pixel| *pixel > 0b11) {
+            return None;
+        }
+
+        framebuffer.pixels = pixels;
+
+        Some(framebuffer)
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_WIDTH
+        } else {
+            SCREEN_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_SCREEN_HEIGHT
+        } else {
+            SCREEN_HEIGHT
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /**
+     * Switches between the low (64x32) and the high (128x64) resolution.
+     * With `clear` every plane is cleared (XO-CHIP). Otherwise the picture
+     * stays (SUPER-CHIP): each low resolution pixel becomes 2x2 high
+     * resolution ones, and going back keeps the top left one of each 2x2
+     */
+    pub fn set_hires(&mut self, hires: bool, clear: bool) {
+        let (old_width, was_hires) = (self.width(), self.hires);
+
+        self.hires = hires;
+
+        if clear {
+            self.pixels = vec![0x0; self.width() * self.height()];
+            return;
+        }
+
+        if hires == was_hires {
+            return;
+        }
+
+        let width = self.width();
+        self.pixels = (0..width * self.height())
+            .map(|index| {
+                let (x, y) = (index % width, index / width);
+
+                match hires {
+                    true => self.pixels[y / 2 * old_width + x / 2],
+                    false => self.pixels[y * 2 * old_width + x * 2],
+                }
+            })
+            .collect();
+    }
+
+    /**
+     * Returns the mask of the planes which are drawn, cleared and scrolled
+     */
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /**
+     * Selects the planes to draw on (**PLANE N**). Only the low 2 bits are used
+     */
+    pub fn set_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
+    }
+
+    /**
+     * Returns how many planes are selected
+     */
+    pub fn selected_planes(&self) -> usize {
+        self.planes.count_ones() as usize
+    }
+
+    /**
+     * Returns the colour (0 - 3) of every pixel, row by row
+     */
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /**
+     * Returns true if the pixel on (x, y) is on at any plane.
+     * Coordinates out of the screen are always off
+     */
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.color(x, y) != 0
+    }
+
+    /**
+     * Returns the colour (0 - 3) of the pixel on (x, y).
+     * Coordinates out of the screen are always off
+     */
+    pub fn color(&self, x: usize, y: usize) -> u8 {
+        if x >= self.width() || y >= self.height() {
+            return 0;
+        }
+
+        self.pixels[y * self.width() + x]
+    }
+
+    /**
+     * Turns off every pixel of the selected planes (**CLS**)
+     */
+    pub fn clear(&mut self) {
+        let planes = self.planes;
+
+        self.pixels.iter_mut().for_each(|pixel| *pixel &= !planes);
+    }
+
+    /**
+     * XORs a 8 pixels wide sprite into the selected planes and returns
+     * true if any pixel was erased. When both planes are selected, the
+     * sprite holds the rows of the first plane followed by the rows of
+     * the second one.
+     *
+     * The starting position always wraps around the screen. The pixels of the
+     * sprite which fall out of the screen are clipped, unless `wrap` is set,
+     * in which case they are drawn on the opposite side.
+     */
+    pub fn draw_sprite(&mut self, x: u8, y: u8, sprite: &[u8], wrap: bool) -> bool {
+        let rows: Vec<u16> = sprite.iter().map(|byte| (*byte as u16) << 8).collect();
+
+        self.draw_planes(x, y, &rows, wrap)
+    }
+
+    /**
+     * XORs a 16x16 sprite (2 bytes per row) into the selected planes,
+     * the same way as `draw_sprite`
+     */
+    pub fn draw_large_sprite(&mut self, x: u8, y: u8, sprite: &[u8], wrap: bool) -> bool {
+        let rows: Vec<u16> = sprite
+            .chunks(2)
+            .map(|row| u16::from_be_bytes([row[0], *row.get(1).unwrap_or(&0)]))
+            .collect();
+
+        self.draw_planes(x, y, &rows, wrap)
+    }
+
+    /**
+     * Splits the rows among the selected planes, and draws each part
+     */
+    fn draw_planes(&mut self, x: u8, y: u8, rows: &[u16], wrap: bool) -> bool {
+        let planes = self.selected_planes();
+
+        if planes == 0 || rows.len() < planes {
+            return false;
+        }
+
+        let plane_masks: Vec<u8> = (0..N_PLANES as u8)
+            .map(|plane| 1 << plane)
+            .filter(|mask| self.planes & mask != 0)
+            .collect();
+        let mut collision = false;
+
+        for (mask, rows) in plane_masks
+            .into_iter()
+            .zip(rows.chunks(rows.len() / planes))
+        {
+            collision |= self.draw_rows(x, y, rows, wrap, mask);
+        }
+
+        collision
+    }
+
+    /**
+     * XORs up to 16 pixels per row into a plane, starting from the most significant bit
+     */
+    fn draw_rows(&mut self, x: u8, y: u8, rows: &[u16], wrap: bool, plane: u8) -> bool {
+        let width = self.width();
+        let height = self.height();
+
+        let origin_x = x as usize % width;
+        let origin_y = y as usize % height;
+        let mut collision = false;
+
+        for (row, bits) in rows.iter().enumerate() {
+            let mut pos_y = origin_y + row;
+
+            if pos_y >= height {
+                if !wrap {
+                    break;
+                }
+                pos_y %= height;
+            }
+
+            for bit in 0..16 {
+                if (bits >> (15 - bit)) & 1 == 0 {
+                    continue;
+                }
+
+                let mut pos_x = origin_x + bit;
+
+                if pos_x >= width {
+                    if !wrap {
+                        break;
+                    }
+                    pos_x %= width;
+                }
+
+                let pixel = &mut self.pixels[pos_y * width + pos_x];
+
+                collision |= *pixel & plane != 0;
+                *pixel ^= plane;
+            }
+        }
+
+        collision
+    }
+
+    /**
+     * Scrolls the screen down `rows` pixels (**SCD N**)
+     */
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.scroll(0, rows as isize);
+    }
+
+    /**
+     * Scrolls the screen up `rows` pixels (**SCU N**, XO-CHIP)
+     */
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.scroll(0, -(rows as isize));
+    }
+
+    /**
+     * Scrolls the screen right 4 pixels (**SCR**)
+     */
+    pub fn scroll_right(&mut self) {
+        self.scroll(4, 0);
+    }
+
+    /**
+     * Scrolls the screen left 4 pixels (**SCL**)
+     */
+    pub fn scroll_left(&mut self) {
+        self.scroll(-4, 0);
+    }
+
+    /**
+     * Moves the selected planes (dx, dy) pixels. The pixels which leave
+     * the screen are lost, and the uncovered ones are turned off
+     */
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let width = self.width() as isize;
+        let height = self.height() as isize;
+        let planes = self.planes;
+        let source = self.pixels.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = (x - dx, y - dy);
+                let moved = if (0..width).contains(&from_x) && (0..height).contains(&from_y) {
+                    source[(from_y * width + from_x) as usize]
+                } else {
+                    0
+                };
+
+                let pixel = &mut self.pixels[(y * width + x) as usize];
+                *pixel = (*pixel & !planes) | (moved & planes);
+            }
+        }
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The colour of each pixel value: off, the first plane, the second plane,
+/// and both planes (XO-CHIP)
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [[u8; 3]; 4],
+}
+
+impl Palette {
+    /**
+     * Parses 2 or 4 colours in hexadecimal separated by commas
+     * (`000000,ffffff`), the `#` is optional. With only 2 colours, the
+     * ones of the second plane are the default ones
+     */
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut palette = Self::default();
+        let colors: Vec<&str> = text.split(',').map(str::trim).collect();
+
+        if colors.len() != 2 && colors.len() != 4 {
+            return None;
+        }
+
+        for (color, hex) in palette.colors.iter_mut().zip(colors) {
+            let hex = hex.strip_prefix('#').unwrap_or(hex);
+
+            if hex.len() != 6 {
+                return None;
+            }
+
+            let value = u32::from_str_radix(hex, 16).ok()?;
+            *color = [(value >> 16) as u8, (value >> 8) as u8, value as u8];
+        }
+
+        Some(palette)
+    }
+
+    /**
+     * Returns the colours one after the other (RGB), as the PNG and GIF palettes
+     */
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.colors.concat()
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            colors: [
+                [0x00, 0x00, 0x00],
+                [0xFF, 0xFF, 0xFF],
+                [0xAA, 0xAA, 0xAA],
+                [0x55, 0x55, 0x55],
+            ],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Framebuffer, Palette, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    #[test]
+    fn test_draw_sprite_clips_by_default() {
+        let mut fb = Framebuffer::new();
+
+        fb.draw_sprite(62, 31, &[0xFF, 0xFF], false);
+
+        assert!(fb.pixel(62, 31));
+        assert!(fb.pixel(63, 31));
+        assert!(!fb.pixel(0, 31));
+        assert!(!fb.pixel(62, 0));
+    }
+
+    #[test]
+    fn test_draw_sprite_wraps() {
+        let mut fb = Framebuffer::new();
+
+        fb.draw_sprite(62, 31, &[0xFF, 0xFF], true);
+
+        assert!(fb.pixel(63, 31));
+        assert!(fb.pixel(0, 31));
+        assert!(fb.pixel(5, 31));
+        assert!(fb.pixel(0, 0));
+    }
+
+    #[test]
+    fn test_draw_sprite_start_position_wraps() {
+        let mut fb = Framebuffer::new();
+
+        fb.draw_sprite(
+            SCREEN_WIDTH as u8 + 1,
+            SCREEN_HEIGHT as u8 + 2,
+            &[0x80],
+            false,
+        );
+
+        assert!(fb.pixel(1, 2));
+    }
+
+    #[test]
+    fn test_hires_draw_large_sprite() {
+        let mut fb = Framebuffer::new();
+
+        fb.set_hires(true, true);
+
+        assert_eq!((fb.width(), fb.height()), (128, 64));
+
+        fb.draw_large_sprite(120, 60, &[0x80, 0x01, 0xFF, 0xFF], false);
+
+        assert!(fb.pixel(120, 60));
+        assert!(fb.pixel(127, 61));
+        assert!(!fb.pixel(121, 60));
+        assert!(!fb.pixel(7, 60));
+    }
+
+    #[test]
+    fn test_resolution_switch_keeps_the_picture() {
+        let mut fb = Framebuffer::new();
+
+        fb.draw_sprite(3, 2, &[0x80], false);
+        fb.set_hires(true, false);
+
+        assert!(fb.pixel(6, 4) && fb.pixel(7, 5));
+        assert_eq!(fb.pixels().iter().filter(|pixel| **pixel != 0).count(), 4);
+
+        fb.set_hires(false, false);
+        assert!(fb.pixel(3, 2));
+
+        fb.set_hires(true, true);
+        assert!(fb.pixels().iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut fb = Framebuffer::new();
+
+        fb.draw_sprite(0, 0, &[0x80], false);
+
+        fb.scroll_down(3);
+        assert!(fb.pixel(0, 3));
+        assert!(!fb.pixel(0, 0));
+
+        fb.scroll_right();
+        assert!(fb.pixel(4, 3));
+
+        fb.scroll_left();
+        fb.scroll_left();
+        assert!(fb.pixels().iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut fb = Framebuffer::new();
+
+        // Both planes: the first row is drawn on the first plane,
+        // and the second one on the second plane
+        fb.set_planes(0b11);
+        assert!(!fb.draw_sprite(0, 0, &[0xC0, 0x80], false));
+
+        assert_eq!(fb.color(0, 0), 3);
+        assert_eq!(fb.color(1, 0), 1);
+
+        fb.set_planes(0b10);
+        assert!(fb.draw_sprite(0, 0, &[0x80], false));
+        assert_eq!(fb.color(0, 0), 1);
+
+        // Only the selected planes are cleared
+        fb.draw_sprite(4, 4, &[0x80], false);
+        fb.clear();
+        assert_eq!(fb.color(0, 0), 1);
+        assert_eq!(fb.color(4, 4), 0);
+
+        // Nothing is drawn without planes
+        fb.set_planes(0);
+        assert!(!fb.draw_sprite(0, 0, &[0x80], false));
+        assert_eq!(fb.color(0, 0), 1);
+    }
+
+    #[test]
+    fn test_palette() {
+        let palette = Palette::parse("#102030, ffffff").unwrap();
+        assert_eq!(palette.colors[0], [0x10, 0x20, 0x30]);
+        assert_eq!(palette.colors[2], Palette::default().colors[2]);
+        assert!(Palette::parse("000000").is_none());
+        assert!(Palette::parse("000000,fffff").is_none());
+    }
+}
@@ -0,0 +1,58 @@
+use std::fmt;
+
+/// What went wrong while executing an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    /// The opcode does not match any instruction
+    UnknownOpcode,
+    /// A call was made with the 16 stack entries already used
+    StackOverflow,
+    /// A return was made with an empty stack
+    StackUnderflow,
+    /// The instruction accessed an address out of the memory
+    MemoryOutOfRange(u16),
+    /// The program counter points out of the memory
+    PcOutOfRange,
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultKind::UnknownOpcode => write!(f, "unknown opcode"),
+            FaultKind::StackOverflow => write!(f, "stack overflow"),
+            FaultKind::StackUnderflow => write!(f, "stack underflow"),
+            FaultKind::MemoryOutOfRange(address) => {
+                write!(f, "memory address {address:#05X} out of range")
+            }
+            FaultKind::PcOutOfRange => write!(f, "program counter out of range"),
+        }
+    }
+}
+
+/// Fault raised by the CPU. It carries the address of the faulting
+/// instruction (`pc`) and its raw `opcode`, so it can be reported
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmulatorError {
+    pub pc: u16,
+    pub opcode: u16,
+    pub kind: FaultKind,
+}
+
+impl EmulatorError {
+    pub fn new(pc: u16, opcode: u16, kind: FaultKind) -> Self {
+        Self { pc, opcode, kind }
+    }
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:#05X} (opcode {:#06X})",
+            self.kind, self.pc, self.opcode
+        )
+    }
+}
+
+impl std::error::Error for EmulatorError {}
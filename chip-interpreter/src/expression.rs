@@ -0,0 +1,483 @@
+use std::fmt;
+
+use crate::cpu::CPU;
+
+/// A register of the CPU, as the debugger names it
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Dt,
+    St,
+    Sp,
+}
+
+impl Register {
+    /**
+     * Parses V0 - VF, I, PC, DT, ST or SP, in any case
+     */
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "I" => Some(Register::I),
+            "PC" => Some(Register::Pc),
+            "DT" => Some(Register::Dt),
+            "ST" => Some(Register::St),
+            "SP" => Some(Register::Sp),
+            name => {
+                let index = name.strip_prefix('V').filter(|index| index.len() == 1)?;
+
+                u8::from_str_radix(index, 16).ok().map(Register::V)
+            }
+        }
+    }
+
+    pub fn value(&self, cpu: &CPU) -> u32 {
+        match self {
+            Register::V(x) => cpu.registers[*x as usize] as u32,
+            Register::I => cpu.i_register as u32,
+            Register::Pc => cpu.program_counter() as u32,
+            Register::Dt => cpu.delay_timer as u32,
+            Register::St => cpu.sound_timer as u32,
+            Register::Sp => cpu.stack_pointer() as u32,
+        }
+    }
+
+    /**
+     * Writes the value, which must fit on the register.
+     * PC must stay in the memory, and SP on the stack
+     */
+    pub fn write(&self, cpu: &mut CPU, value: u32) -> Result<(), String> {
+        let byte = || u8::try_from(value).map_err(|_| format!("{value} does not fit on a byte"));
+        let address = || u16::try_from(value).map_err(|_| format!("{value:#X} is not an address"));
+
+        match self {
+            Register::V(x) => cpu.registers[*x as usize] = byte()?,
+            Register::I => cpu.i_register = address()?,
+            Register::Pc => match address() {
+                Ok(address) if (address as usize) < cpu.memory().size() => {
+                    cpu.set_program_counter(address)
+                }
+                _ => return Err(format!("{value:#X} is out of the memory")),
+            },
+            Register::Dt => cpu.delay_timer = byte()?,
+            Register::St => cpu.sound_timer = byte()?,
+            Register::Sp => cpu
+                .set_stack_pointer(address()?)
+                .map_err(|_| format!("{value} is deeper than the stack"))?,
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{x:X}"),
+            Register::I => write!(f, "I"),
+            Register::Pc => write!(f, "PC"),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+            Register::Sp => write!(f, "SP"),
+        }
+    }
+}
+
+/// Why an expression could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExpressionError {
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    /// Neither a register nor a symbol
+    UnknownName(String),
+}
+
+impl fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExpressionError::UnexpectedEnd => write!(f, "unexpected end of the expression"),
+            ExpressionError::UnexpectedToken(token) => write!(f, "unexpected <{token}>"),
+            ExpressionError::UnknownName(name) => write!(f, "unknown name <{name}>"),
+        }
+    }
+}
+
+impl std::error::Error for ExpressionError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u32),
+    Name(String),
+    Symbol(&'static str),
+}
+
+/// The operators, from the first to be tried to the last
+const SYMBOLS: [&str; 18] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "!", "(", ")", "[", "]",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOperator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+impl BinaryOperator {
+    fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "||" => Some(BinaryOperator::Or),
+            "&&" => Some(BinaryOperator::And),
+            "==" => Some(BinaryOperator::Equal),
+            "!=" => Some(BinaryOperator::NotEqual),
+            "<" => Some(BinaryOperator::Less),
+            "<=" => Some(BinaryOperator::LessOrEqual),
+            ">" => Some(BinaryOperator::Greater),
+            ">=" => Some(BinaryOperator::GreaterOrEqual),
+            "|" => Some(BinaryOperator::BitOr),
+            "^" => Some(BinaryOperator::BitXor),
+            "&" => Some(BinaryOperator::BitAnd),
+            "+" => Some(BinaryOperator::Add),
+            "-" => Some(BinaryOperator::Sub),
+            _ => None,
+        }
+    }
+
+    /**
+     * The same precedence Rust gives them: `V0 & 1 == 0` is `(V0 & 1) == 0`
+     */
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::Less
+            | BinaryOperator::LessOrEqual
+            | BinaryOperator::Greater
+            | BinaryOperator::GreaterOrEqual => 3,
+            BinaryOperator::BitOr => 4,
+            BinaryOperator::BitXor => 5,
+            BinaryOperator::BitAnd => 6,
+            BinaryOperator::Add | BinaryOperator::Sub => 7,
+        }
+    }
+
+    fn apply(&self, left: u32, right: u32) -> u32 {
+        match self {
+            BinaryOperator::Or => (left != 0 || right != 0) as u32,
+            BinaryOperator::And => (left != 0 && right != 0) as u32,
+            BinaryOperator::Equal => (left == right) as u32,
+            BinaryOperator::NotEqual => (left != right) as u32,
+            BinaryOperator::Less => (left < right) as u32,
+            BinaryOperator::LessOrEqual => (left <= right) as u32,
+            BinaryOperator::Greater => (left > right) as u32,
+            BinaryOperator::GreaterOrEqual => (left >= right) as u32,
+            BinaryOperator::BitOr => left | right,
+            BinaryOperator::BitXor => left ^ right,
+            BinaryOperator::BitAnd => left & right,
+            BinaryOperator::Add => left.wrapping_add(right),
+            BinaryOperator::Sub => left.wrapping_sub(right),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Number(u32),
+    Register(Register),
+    /// `[address]`, the byte on the address
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Binary(BinaryOperator, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn evaluate(&self, cpu: &CPU) -> u32 {
+        match self {
+            Node::Number(value) => *value,
+            Node::Register(register) => register.value(cpu),
+            Node::Memory(address) => {
+                let address = address.evaluate(cpu);
+
+                u16::try_from(address)
+                    .ok()
+                    .and_then(|address| cpu.memory().read_bytes(address, 1).ok())
+                    .map_or(0, |bytes| bytes[0] as u32)
+            }
+            Node::Not(node) => (node.evaluate(cpu) == 0) as u32,
+            Node::Binary(operator, left, right) => {
+                operator.apply(left.evaluate(cpu), right.evaluate(cpu))
+            }
+        }
+    }
+}
+
+/// A condition of the debugger, like `V3 == 0x10 && I > 0x300`.
+///
+/// The operands are numbers, registers (V0 - VF, I, PC, DT, ST, SP), bytes
+/// of the memory (`[I + 1]`) and the names the resolver knows, which are
+/// looked up once, while parsing. Any value other than 0 is true
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    /**
+     * Parses the expression. `resolve` gives the value of the names which
+     * are not registers, like the labels of a symbol table
+     */
+    pub fn parse(
+        source: &str,
+        resolve: impl Fn(&str) -> Option<u32>,
+    ) -> Result<Self, ExpressionError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            resolve: &resolve,
+        };
+
+        let root = parser.binary(0)?;
+
+        if let Some(token) = parser.next() {
+            return Err(ExpressionError::UnexpectedToken(token.to_string()));
+        }
+
+        Ok(Self {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+
+    pub fn evaluate(&self, cpu: &CPU) -> u32 {
+        self.root.evaluate(cpu)
+    }
+
+    pub fn is_true(&self, cpu: &CPU) -> bool {
+        self.evaluate(cpu) != 0
+    }
+
+    /**
+     * Returns the register if the whole expression is a single register
+     */
+    pub fn as_register(&self) -> Option<Register> {
+        match self.root {
+            Node::Register(register) => Some(register),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "{value}"),
+            Token::Name(name) => write!(f, "{name}"),
+            Token::Symbol(symbol) => write!(f, "{symbol}"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+
+    while let Some(first) = rest.chars().next() {
+        if first.is_ascii_alphanumeric() || first == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+
+            let token = if first.is_ascii_digit() {
+                let number = match word.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => match word.strip_prefix("0b") {
+                        Some(binary) => u32::from_str_radix(binary, 2),
+                        None => word.parse(),
+                    },
+                };
+
+                Token::Number(
+                    number.map_err(|_| ExpressionError::UnexpectedToken(word.to_string()))?,
+                )
+            } else {
+                Token::Name(word.to_string())
+            };
+
+            tokens.push(token);
+            rest = &rest[end..];
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+                .ok_or_else(|| ExpressionError::UnexpectedToken(first.to_string()))?;
+
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    resolve: &'a dyn Fn(&str) -> Option<u32>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), ExpressionError> {
+        match self.next() {
+            Some(Token::Symbol(found)) if found == symbol => Ok(()),
+            Some(token) => Err(ExpressionError::UnexpectedToken(token.to_string())),
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+
+    /**
+     * Parses the operators whose precedence is at least `min_precedence`
+     */
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, ExpressionError> {
+        let mut left = self.unary()?;
+
+        while let Some(Token::Symbol(symbol)) = self.peek() {
+            let Some(operator) = BinaryOperator::from_symbol(symbol) else {
+                break;
+            };
+
+            if operator.precedence() < min_precedence {
+                break;
+            }
+
+            self.position += 1;
+
+            let right = self.binary(operator.precedence() + 1)?;
+            left = Node::Binary(operator, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, ExpressionError> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Node::Number(value)),
+            Some(Token::Name(name)) => match Register::from_name(&name) {
+                Some(register) => Ok(Node::Register(register)),
+                None => (self.resolve)(&name)
+                    .map(Node::Number)
+                    .ok_or(ExpressionError::UnknownName(name)),
+            },
+            Some(Token::Symbol("!")) => Ok(Node::Not(Box::new(self.unary()?))),
+            Some(Token::Symbol("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(Token::Symbol("[")) => {
+                let node = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Memory(Box::new(node)))
+            }
+            Some(token) => Err(ExpressionError::UnexpectedToken(token.to_string())),
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expression, ExpressionError, Register};
+    use crate::{cpu::CPU, quirks::Quirks};
+
+    fn evaluate(source: &str, cpu: &CPU) -> u32 {
+        Expression::parse(source, |name| (name == "data").then_some(0x300))
+            .unwrap()
+            .evaluate(cpu)
+    }
+
+    #[test]
+    fn test_evaluate_expressions() {
+        let mut cpu = CPU::new(Quirks::default());
+
+        cpu.registers[3] = 0x10;
+        cpu.i_register = 0x301;
+        cpu.memory_mut().write_bytes(0x300, &[7, 9]).unwrap();
+
+        assert_eq!(evaluate("V3 == 0x10 && I > 0x300", &cpu), 1);
+        assert_eq!(evaluate("v3 == 16 && i > data + 1", &cpu), 0);
+        assert_eq!(evaluate("V3 & 0b11 == 0 || !(DT)", &cpu), 1);
+        assert_eq!(evaluate("[data] + [I]", &cpu), 16);
+        assert_eq!(evaluate("[0xFFFFF]", &cpu), 0);
+        assert_eq!(evaluate("1 + 2 - 4", &cpu), u32::MAX);
+        assert_eq!(evaluate("PC == 0x200 && SP == 0", &cpu), 1);
+
+        let expression = Expression::parse(" vA ", |_| None).unwrap();
+
+        assert_eq!(expression.as_register(), Some(Register::V(0xA)));
+        assert_eq!(expression.to_string(), "vA");
+    }
+
+    #[test]
+    fn test_expression_errors() {
+        let parse = |source| Expression::parse(source, |_| None);
+
+        assert_eq!(parse("V3 =="), Err(ExpressionError::UnexpectedEnd));
+        assert_eq!(
+            parse("V3 = 1"),
+            Err(ExpressionError::UnexpectedToken("=".to_string()))
+        );
+        assert_eq!(parse("(V3 + 1"), Err(ExpressionError::UnexpectedEnd));
+        assert_eq!(
+            parse("V3 V4"),
+            Err(ExpressionError::UnexpectedToken("V4".to_string()))
+        );
+        assert_eq!(
+            parse("score > 3"),
+            Err(ExpressionError::UnknownName("score".to_string()))
+        );
+        assert_eq!(
+            parse("0xZZ"),
+            Err(ExpressionError::UnexpectedToken("0xZZ".to_string()))
+        );
+        assert_eq!(
+            parse("VG"),
+            Err(ExpressionError::UnknownName("VG".to_string()))
+        );
+    }
+}
@@ -0,0 +1,54 @@
+use std::{fs, io, path::PathBuf};
+
+/// The HP-48 has 8 RPL user flags. XO-CHIP extends them to 16
+pub const N_RPL_FLAGS: usize = 16;
+
+/// Where the RPL user flags (**LD R, Vx** / **LD Vx, R**) are persisted.
+/// On the HP-48 they survive after the game exits, so the host decides
+/// where to keep them (a file, a database, the browser storage...)
+pub trait FlagStorage: std::fmt::Debug {
+    /**
+     * Returns the flags stored the last time
+     */
+    fn load(&mut self) -> [u8; N_RPL_FLAGS];
+
+    /**
+     * Persists the flags. It is called each time a game stores them.
+     * A failed save only loses the flags, so the game goes on and the
+     * error is kept for the host ([`crate::CPU::take_flag_storage_error`])
+     */
+    fn save(&mut self, flags: &[u8; N_RPL_FLAGS]) -> io::Result<()>;
+}
+
+/// Keeps the flags on a file, usually next to the ROM
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileFlagStorage {
+    path: PathBuf,
+}
+
+impl FileFlagStorage {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl FlagStorage for FileFlagStorage {
+    /**
+     * A missing or unreadable file means that nothing was stored yet
+     */
+    fn load(&mut self) -> [u8; N_RPL_FLAGS] {
+        let mut flags = [0x0; N_RPL_FLAGS];
+
+        if let Ok(data) = fs::read(&self.path) {
+            let size = data.len().min(N_RPL_FLAGS);
+            flags[..size].copy_from_slice(&data[..size]);
+        }
+
+        flags
+    }
+
+    fn save(&mut self, flags: &[u8; N_RPL_FLAGS]) -> io::Result<()> {
+        fs::write(&self.path, flags)
+    }
+}
@@ -0,0 +1,791 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    debugger::{Debugger, StopReason, WatchAccess, Watchpoint},
+    error::{EmulatorError, FaultKind},
+    expression::Register,
+    memory::XO_CHIP_MEMORY_SIZE,
+};
+
+/// Port `debug --gdb` listens on when none is given
+pub const DEFAULT_GDB_PORT: u16 = 3333;
+
+/// Instructions `c` executes between two checks of the interrupt (Ctrl-C)
+const CONTINUE_CHUNK: u32 = 10_000;
+
+/// Byte GDB sends to interrupt a `c`
+const INTERRUPT: u8 = 0x03;
+
+/// The registers in the order of `g` and `G`, with their size in bytes.
+/// Each register is sent in little endian
+const REGISTERS: [(Register, usize); 21] = [
+    (Register::V(0x0), 1),
+    (Register::V(0x1), 1),
+    (Register::V(0x2), 1),
+    (Register::V(0x3), 1),
+    (Register::V(0x4), 1),
+    (Register::V(0x5), 1),
+    (Register::V(0x6), 1),
+    (Register::V(0x7), 1),
+    (Register::V(0x8), 1),
+    (Register::V(0x9), 1),
+    (Register::V(0xA), 1),
+    (Register::V(0xB), 1),
+    (Register::V(0xC), 1),
+    (Register::V(0xD), 1),
+    (Register::V(0xE), 1),
+    (Register::V(0xF), 1),
+    (Register::I, 2),
+    (Register::Pc, 2),
+    (Register::Sp, 1),
+    (Register::Dt, 1),
+    (Register::St, 1),
+];
+
+/// Describes the registers, as there is no CHIP-8 architecture on GDB
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+/// What the client sent
+#[derive(Debug, PartialEq, Eq)]
+enum Incoming {
+    Packet(String),
+    Interrupt,
+}
+
+/// The framing of the protocol: `$data#checksum`, acknowledged with `+`
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    /// Set by `QStartNoAckMode`
+    no_ack: bool,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            no_ack: false,
+        })
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0x0];
+
+        match self.reader.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /**
+     * Returns the next packet or interrupt, or None once the client is gone.
+     * The packets with a wrong checksum are asked again (`-`)
+     */
+    fn receive(&mut self) -> io::Result<Option<Incoming>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Incoming::Interrupt)),
+                Some(b'$') => {}
+                // Acknowledgements and noise between the packets
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            self.reader.read_until(b'#', &mut data)?;
+
+            if data.pop() != Some(b'#') {
+                return Ok(None);
+            }
+
+            let mut checksum = [0x0; 2];
+            self.reader.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+
+            if !self.no_ack {
+                if expected != Some(checksum_of(&data)) {
+                    self.writer.write_all(b"-")?;
+                    continue;
+                }
+
+                self.writer.write_all(b"+")?;
+            }
+
+            return Ok(Some(Incoming::Packet(
+                String::from_utf8_lossy(&data).into_owned(),
+            )));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum_of(data.as_bytes()));
+
+        self.writer.write_all(packet.as_bytes())?;
+        self.writer.flush()
+    }
+
+    /**
+     * Returns true if the client sent an interrupt, without waiting for it
+     */
+    fn interrupted(&mut self) -> io::Result<bool> {
+        if self.reader.buffer().contains(&INTERRUPT) {
+            self.reader.consume(self.reader.buffer().len());
+            return Ok(true);
+        }
+
+        self.reader.get_ref().set_nonblocking(true)?;
+
+        let result = match self.reader.fill_buf() {
+            Ok(buffer) => Ok(buffer.contains(&INTERRUPT)),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        };
+
+        self.reader.get_ref().set_nonblocking(false)?;
+
+        if result? {
+            self.reader.consume(self.reader.buffer().len());
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/**
+ * Parses the `address,length` of `m`, `M`, `Z` and `z`. No length goes
+ * beyond the largest memory
+ */
+fn parse_range(text: &str) -> Option<(u16, usize)> {
+    let (address, length) = text.split_once(',')?;
+
+    Some((
+        u16::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(length, 16)
+            .ok()
+            .filter(|length| *length <= XO_CHIP_MEMORY_SIZE)?,
+    ))
+}
+
+/// Serves a [`Debugger`] to GDB, or to any front-end speaking its Remote
+/// Serial Protocol, over TCP.
+///
+/// It answers the register (`g`, `G`, `p`, `P`) and memory (`m`, `M`)
+/// packets, the breakpoints and watchpoints (`Z0` - `Z4`), `s` and `c`,
+/// and the `monitor` commands, which run on the debugger REPL
+/// (`monitor key 5` presses a key). The registers are described by
+/// `target.xml`: V0 - VF, I, PC, SP, DT and ST
+
+#[derive(Debug)]
+pub struct GdbStub {
+    debugger: Debugger,
+    /// The number of the watchpoint set by each `Z2` - `Z4` packet
+    watchpoints: BTreeMap<(char, u16, usize), usize>,
+}
+
+impl GdbStub {
+    pub fn new(debugger: Debugger) -> Self {
+        Self {
+            debugger,
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn into_debugger(self) -> Debugger {
+        self.debugger
+    }
+
+    /**
+     * Waits for a client on the address, and serves it until it detaches
+     */
+    pub fn listen(&mut self, address: impl ToSocketAddrs) -> io::Result<()> {
+        let listener = TcpListener::bind(address)?;
+        let (stream, _) = listener.accept()?;
+
+        self.serve(stream)
+    }
+
+    /**
+     * Answers the packets of the client until it detaches, kills the
+     * program or closes the connection
+     */
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nodelay(true)?;
+
+        let mut connection = Connection::new(stream)?;
+
+        while let Some(incoming) = connection.receive()? {
+            let packet = match incoming {
+                Incoming::Packet(packet) => packet,
+                // Nothing is running, the program is already stopped
+                Incoming::Interrupt => {
+                    connection.send("S02")?;
+                    continue;
+                }
+            };
+
+            match packet.as_str() {
+                "D" => {
+                    connection.send("OK")?;
+                    return Ok(());
+                }
+                "k" => return Ok(()),
+                "QStartNoAckMode" => {
+                    connection.send("OK")?;
+                    connection.no_ack = true;
+                }
+                "c" => {
+                    let reply = self.resume(&mut connection)?;
+                    connection.send(&reply)?;
+                }
+                _ => {
+                    for reply in self.reply(&packet) {
+                        connection.send(&reply)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Runs until a stop, checking for an interrupt between the chunks
+     */
+    fn resume(&mut self, connection: &mut Connection) -> io::Result<String> {
+        loop {
+            match self.debugger.resume(CONTINUE_CHUNK) {
+                Ok(StopReason::Limit(_)) => {
+                    if connection.interrupted()? {
+                        return Ok("S02".to_string());
+                    }
+                }
+                result => return Ok(self.stop_reply(result)),
+            }
+        }
+    }
+
+    /**
+     * Returns the replies to a packet which does not run the program for
+     * long. An empty reply tells the client the packet is not supported
+     */
+    fn reply(&mut self, packet: &str) -> Vec<String> {
+        // The command is the first character, which may not be ASCII on a
+        // broken or hostile client
+        let split = packet
+            .char_indices()
+            .nth(1)
+            .map_or(packet.len(), |(index, _)| index);
+        let (command, args) = packet.split_at(split);
+
+        let reply = match command {
+            "?" => "S05".to_string(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => self.read_register(args),
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "s" => {
+                let result = self.debugger.step_instructions(1);
+                self.stop_reply(result)
+            }
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "H" => "OK".to_string(),
+            "q" => return self.query(packet),
+            _ => String::new(),
+        };
+
+        vec![reply]
+    }
+
+    fn query(&mut self, packet: &str) -> Vec<String> {
+        let reply = match packet {
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ if packet.starts_with("qSupported") => {
+                "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+;swbreak+".to_string()
+            }
+            _ if packet.starts_with("qXfer:features:read:target.xml:") => {
+                let range = &packet["qXfer:features:read:target.xml:".len()..];
+
+                let range = parse_range(range).and_then(|(offset, length)| {
+                    let start = (offset as usize).min(TARGET_XML.len());
+                    let end = start.checked_add(length)?.min(TARGET_XML.len());
+
+                    Some((start, end))
+                });
+
+                match range {
+                    Some((start, end)) => {
+                        let more = if end < TARGET_XML.len() { "m" } else { "l" };
+
+                        format!("{more}{}", &TARGET_XML[start..end])
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            _ if packet.starts_with("qRcmd,") => return self.monitor(&packet["qRcmd,".len()..]),
+            _ => String::new(),
+        };
+
+        vec![reply]
+    }
+
+    /**
+     * Runs a `monitor` command on the debugger REPL, sending back its output
+     */
+    fn monitor(&mut self, hex: &str) -> Vec<String> {
+        let Some(command) = from_hex(hex).and_then(|bytes| String::from_utf8(bytes).ok()) else {
+            return vec!["E01".to_string()];
+        };
+
+        let mut output = Vec::new();
+
+        // Writing on a Vec never fails
+        let _ = self.debugger.execute(&command, &mut output);
+
+        match output.is_empty() {
+            true => vec!["OK".to_string()],
+            false => vec![format!("O{}", to_hex(&output)), "OK".to_string()],
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        let cpu = self.debugger.cpu();
+
+        REGISTERS
+            .iter()
+            .map(|(register, size)| to_hex(&register.value(cpu).to_le_bytes()[..*size]))
+            .collect()
+    }
+
+    fn write_registers(&mut self, hex: &str) -> String {
+        let Some(bytes) = from_hex(hex) else {
+            return "E01".to_string();
+        };
+
+        let mut bytes = bytes.as_slice();
+
+        for (register, size) in REGISTERS {
+            if bytes.len() < size {
+                return "E01".to_string();
+            }
+
+            let (value, rest) = bytes.split_at(size);
+
+            if self.write(register, value).is_err() {
+                return "E01".to_string();
+            }
+
+            bytes = rest;
+        }
+
+        "OK".to_string()
+    }
+
+    fn read_register(&self, hex: &str) -> String {
+        let register = usize::from_str_radix(hex, 16)
+            .ok()
+            .and_then(|number| REGISTERS.get(number));
+
+        match register {
+            Some((register, size)) => {
+                to_hex(&register.value(self.debugger.cpu()).to_le_bytes()[..*size])
+            }
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let parsed = args.split_once('=').and_then(|(number, hex)| {
+            let (register, size) = REGISTERS.get(usize::from_str_radix(number, 16).ok()?)?;
+            let bytes = from_hex(hex).filter(|bytes| bytes.len() == *size)?;
+
+            Some((*register, bytes))
+        });
+
+        match parsed {
+            Some((register, bytes)) if self.write(register, &bytes).is_ok() => "OK".to_string(),
+            _ => "E01".to_string(),
+        }
+    }
+
+    /**
+     * Writes the little endian value on the register
+     */
+    fn write(&mut self, register: Register, bytes: &[u8]) -> Result<(), String> {
+        let value = bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| value << 8 | *byte as u32);
+
+        register.write(self.debugger.cpu_mut(), value)
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let bytes = parse_range(args).and_then(|(address, length)| {
+            self.debugger
+                .cpu()
+                .memory()
+                .read_bytes(address, length)
+                .ok()
+        });
+
+        match bytes {
+            Some(bytes) => to_hex(bytes),
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let written = args.split_once(':').and_then(|(range, hex)| {
+            let (address, length) = parse_range(range)?;
+            let bytes = from_hex(hex).filter(|bytes| bytes.len() == length)?;
+
+            self.debugger
+                .cpu_mut()
+                .memory_mut()
+                .write_bytes(address, &bytes)
+                .ok()
+        });
+
+        match written {
+            Some(()) => "OK".to_string(),
+            None => "E01".to_string(),
+        }
+    }
+
+    /**
+     * Sets (`Z`) or clears (`z`) a breakpoint (0, 1) or a watchpoint
+     * (2 write, 3 read, 4 access): `Z0,addr,kind`
+     */
+    fn breakpoint(&mut self, set: bool, args: &str) -> String {
+        let Some((kind, range)) = args.split_once(',') else {
+            return "E01".to_string();
+        };
+        let Some((address, length)) = parse_range(range) else {
+            return "E01".to_string();
+        };
+
+        let access = match kind {
+            "0" | "1" => {
+                match set {
+                    true => self.debugger.add_breakpoint(address),
+                    false => self.debugger.remove_breakpoint(address),
+                };
+
+                return "OK".to_string();
+            }
+            "2" => WatchAccess::Write,
+            "3" => WatchAccess::Read,
+            "4" => WatchAccess::ReadWrite,
+            _ => return String::new(),
+        };
+
+        let key = (kind.chars().next().unwrap_or_default(), address, length);
+
+        if !set {
+            if let Some(number) = self.watchpoints.remove(&key) {
+                self.debugger.remove_watchpoint(number);
+            }
+
+            return "OK".to_string();
+        }
+
+        let end = (address as usize)
+            .checked_add(length.max(1) - 1)
+            .filter(|end| *end < self.debugger.cpu().memory().size());
+
+        let Some(end) = end else {
+            return "E01".to_string();
+        };
+
+        let number = self.debugger.add_watchpoint(Watchpoint::Memory {
+            start: address,
+            end: end as u16,
+            access,
+        });
+        self.watchpoints.insert(key, number);
+
+        "OK".to_string()
+    }
+
+    /**
+     * Tells the client why the program stopped: a signal (`S05` is a
+     * trap), a watchpoint (`T05watch:addr;`) or the end of the program (`W00`)
+     */
+    fn stop_reply(&self, result: Result<StopReason, EmulatorError>) -> String {
+        match result {
+            Ok(StopReason::Halted) => "W00".to_string(),
+            Ok(StopReason::Breakpoint(_)) => "T05swbreak:;".to_string(),
+            Ok(StopReason::MemoryAccess { watchpoint, access }) => {
+                let watch = self
+                    .watchpoints
+                    .iter()
+                    .find(|(_, number)| **number == watchpoint)
+                    .map(|((kind, start, _), _)| (*kind, (*start).max(access.address)));
+
+                match watch {
+                    Some(('3', address)) => format!("T05rwatch:{address:x};"),
+                    Some(('4', address)) => format!("T05awatch:{address:x};"),
+                    Some((_, address)) => format!("T05watch:{address:x};"),
+                    None => "S05".to_string(),
+                }
+            }
+            Ok(_) => "S05".to_string(),
+            // SIGILL and SIGSEGV
+            Err(err) if err.kind == FaultKind::UnknownOpcode => "S04".to_string(),
+            Err(_) => "S0b".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::{checksum_of, GdbStub};
+    use crate::{
+        asm::Assembler,
+        cpu::CPU,
+        debugger::Debugger,
+        quirks::Quirks,
+        rom::{Rom, DEFAULT_LOAD_ADDRESS},
+    };
+
+    /// A scripted client: sends each packet and reads its replies
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn read_packet(&mut self) -> String {
+            let mut byte = [0x0];
+            let mut packet = Vec::new();
+
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+
+                match byte[0] {
+                    b'$' => packet.clear(),
+                    b'#' => break,
+                    b'+' if packet.is_empty() => {}
+                    byte => packet.push(byte),
+                }
+            }
+
+            let mut checksum = [0x0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            assert_eq!(
+                u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16),
+                Ok(checksum_of(&packet))
+            );
+
+            self.stream.write_all(b"+").unwrap();
+
+            String::from_utf8(packet).unwrap()
+        }
+
+        fn send(&mut self, packet: &str) -> String {
+            let framed = format!("${packet}#{:02x}", checksum_of(packet.as_bytes()));
+
+            self.stream.write_all(framed.as_bytes()).unwrap();
+            self.read_packet()
+        }
+    }
+
+    fn serve(source: &str, script: impl FnOnce(&mut Client) + Send + 'static) -> Debugger {
+        let program = Assembler::new().assemble(source).unwrap();
+        let mut cpu = CPU::new(Quirks::default());
+
+        cpu.load_rom(&Rom::from_bytes(program, DEFAULT_LOAD_ADDRESS).unwrap())
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut client = Client {
+                stream: TcpStream::connect(address).unwrap(),
+            };
+
+            script(&mut client);
+            assert_eq!(client.send("D"), "OK");
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut stub = GdbStub::new(Debugger::new(cpu));
+
+        stub.serve(stream).unwrap();
+        client.join().unwrap();
+
+        stub.into_debugger()
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let debugger = serve("LD V1, 0xAB\nLD I, 0x321\nHALT", |client| {
+            assert!(client
+                .send("qSupported:multiprocess+")
+                .contains("qXfer:features:read+"));
+            assert_eq!(client.send("?"), "S05");
+            assert!(client
+                .send("qXfer:features:read:target.xml:0,20")
+                .starts_with("m<?xml"));
+            assert_eq!(
+                client.send("qXfer:features:read:target.xml:0,ffffffffffffffff"),
+                "E01"
+            );
+
+            assert_eq!(client.send("s"), "S05");
+            assert_eq!(client.send("s"), "S05");
+            // V0 - VF, I, PC, SP, DT and ST
+            assert_eq!(
+                client.send("g"),
+                format!("00ab{}21030402000000", "00".repeat(14))
+            );
+            assert_eq!(client.send("p11"), "0402");
+
+            assert_eq!(client.send("P3=7f"), "OK");
+            assert_eq!(client.send("P10=0003"), "OK");
+            assert_eq!(client.send("P12=11"), "E01");
+            assert_eq!(client.send("P15=00"), "E01");
+
+            assert_eq!(client.send("M300,3:010203"), "OK");
+            assert_eq!(client.send("m2ff,5"), "0001020300");
+            assert_eq!(client.send("mfff,2"), "E01");
+            assert_eq!(client.send("m200,ffffffffffffffff"), "E01");
+            assert_eq!(client.send("M200,ffffffffffffffff:00"), "E01");
+            assert_eq!(client.send("vMustReplyEmpty"), "");
+            assert_eq!(client.send("é"), "");
+            assert_eq!(client.send("ém0,1"), "");
+        });
+
+        let cpu = debugger.cpu();
+
+        assert_eq!(cpu.registers[3], 0x7F);
+        assert_eq!(cpu.i_register, 0x300);
+        assert_eq!(cpu.memory().read_bytes(0x300, 3), Ok(&[1, 2, 3][..]));
+    }
+
+    #[test]
+    fn test_breakpoints_and_continue() {
+        let source = "
+                LD V0, 5         ; 0x200
+                LD V1, 1         ; 0x202
+            loop:
+                LD I, 0x300      ; 0x204
+                LD [I], V0       ; 0x206
+                SUB V0, V1       ; 0x208
+                SE V0, 0         ; 0x20A
+                JP loop          ; 0x20C
+                HALT             ; 0x20E
+            ";
+
+        let debugger = serve(source, |client| {
+            assert_eq!(client.send("QStartNoAckMode"), "OK");
+
+            assert_eq!(client.send("Z0,20a,2"), "OK");
+            assert_eq!(client.send("c"), "T05swbreak:;");
+            assert_eq!(client.send("p11"), "0a02");
+            assert_eq!(client.send("p0"), "04");
+            assert_eq!(client.send("z0,20a,2"), "OK");
+
+            assert_eq!(client.send("Z2,300,1"), "OK");
+            assert_eq!(client.send("c"), "T05watch:300;");
+            assert_eq!(client.send("m300,1"), "04");
+            assert_eq!(client.send("z2,300,1"), "OK");
+            assert_eq!(client.send("Z2,300,ffffffffffffffff"), "E01");
+            assert_eq!(client.send("Z2,fff,2"), "E01");
+
+            // monitor registers
+            let output = client.send("qRcmd,726567697374657273");
+            assert!(output.starts_with('O'));
+            assert_eq!(client.read_packet(), "OK");
+
+            assert_eq!(client.send("c"), "W00");
+        });
+
+        assert!(debugger.cpu().is_halted());
+        assert!(debugger.watchpoints().is_empty());
+        assert!(debugger.breakpoints().is_empty());
+    }
+
+    #[test]
+    fn test_interrupt() {
+        serve("loop: JP loop", |client| {
+            let framed = format!("$c#{:02x}", checksum_of(b"c"));
+
+            client.stream.write_all(framed.as_bytes()).unwrap();
+            client.stream.write_all(&[0x03]).unwrap();
+
+            assert_eq!(client.read_packet(), "S02");
+            assert_eq!(client.send("p11"), "0002");
+        });
+    }
+}
@@ -0,0 +1,381 @@
+use std::{fmt, io};
+
+use crate::{
+    cpu::CPU,
+    display::{Framebuffer, Palette, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH},
+    error::EmulatorError,
+    keypad::N_KEYS,
+};
+
+/// Frames per second of the GIF recordings, as the frames run at 60 Hz
+const FRAMES_PER_SECOND: u64 = 60;
+
+/**
+ * Returns the pixel values of the framebuffer, each pixel repeated
+ * `scale` times on both axes
+ */
+pub fn scale_pixels(framebuffer: &Framebuffer, scale: usize) -> Vec<u8> {
+    let width = framebuffer.width();
+    let mut pixels = Vec::with_capacity(framebuffer.pixels().len() * scale * scale);
+
+    for row in framebuffer.pixels().chunks(width) {
+        let scaled: Vec<u8> = row
+            .iter()
+            .flat_map(|pixel| std::iter::repeat_n(*pixel, scale))
+            .collect();
+
+        for _ in 0..scale {
+            pixels.extend_from_slice(&scaled);
+        }
+    }
+
+    pixels
+}
+
+/**
+ * Writes the framebuffer as an indexed PNG, each pixel `scale` times bigger
+ */
+pub fn write_png<W: io::Write>(
+    framebuffer: &Framebuffer,
+    palette: &Palette,
+    scale: usize,
+    writer: W,
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(
+        writer,
+        (framebuffer.width() * scale) as u32,
+        (framebuffer.height() * scale) as u32,
+    );
+
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.to_rgb());
+
+    encoder
+        .write_header()?
+        .write_image_data(&scale_pixels(framebuffer, scale))?;
+
+    Ok(())
+}
+
+/// Keeps the frames of a run to write them as an animated GIF. The frames
+/// which do not change the screen only lengthen the previous one.
+///
+/// The GIF is as big as the largest resolution the run has used, so once a
+/// SUPER-CHIP game switches to high resolution, its low resolution frames
+/// are drawn twice as big to fill the same screen
+
+#[derive(Debug, Clone, Default)]
+pub struct GifRecording {
+    /// Each different frame, and for how many frames it was shown
+    frames: Vec<(Framebuffer, u64)>,
+}
+
+impl GifRecording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Adds the screen of a frame
+     */
+    pub fn push(&mut self, framebuffer: &Framebuffer) {
+        match self.frames.last_mut() {
+            Some((last, count)) if last == framebuffer => *count += 1,
+            _ => self.frames.push((framebuffer.clone(), 1)),
+        }
+    }
+
+    /**
+     * Returns how many frames have been added
+     */
+    pub fn len(&self) -> u64 {
+        self.frames.iter().map(|(_, count)| count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /**
+     * Writes the GIF, looping forever, with each pixel `scale` times bigger
+     */
+    pub fn write<W: io::Write>(
+        &self,
+        palette: &Palette,
+        scale: usize,
+        writer: W,
+    ) -> io::Result<()> {
+        let hires = self.frames.iter().any(|(frame, _)| frame.is_hires());
+        let (width, height) = match hires {
+            true => (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT),
+            false => (HIRES_SCREEN_WIDTH / 2, HIRES_SCREEN_HEIGHT / 2),
+        };
+        let (width, height) = ((width * scale) as u16, (height * scale) as u16);
+
+        let mut encoder = gif::Encoder::new(writer, width, height, &palette.to_rgb())
+            .map_err(io::Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(io::Error::other)?;
+
+        // The delays are in hundredths of a second, so they are rounded from
+        // the start of the run, to not drift
+        let centiseconds = |frames: u64| (frames * 100 + FRAMES_PER_SECOND / 2) / FRAMES_PER_SECOND;
+        let mut elapsed = 0;
+
+        for (framebuffer, count) in &self.frames {
+            let frame_scale = match hires && !framebuffer.is_hires() {
+                true => scale * 2,
+                false => scale,
+            };
+            let mut frame = gif::Frame::from_indexed_pixels(
+                width,
+                height,
+                scale_pixels(framebuffer, frame_scale),
+                None,
+            );
+
+            let mut delay = centiseconds(elapsed + count) - centiseconds(elapsed);
+            elapsed += count;
+
+            // A GIF delay is at most about 11 minutes, so a longer hold is
+            // written again until it has been shown for long enough
+            loop {
+                frame.delay = delay.min(u16::MAX as u64) as u16;
+                delay -= frame.delay as u64;
+
+                encoder.write_frame(&frame).map_err(io::Error::other)?;
+
+                if delay == 0 {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// The keys to hold on each frame of a headless run. Each line of the
+/// script has a frame and the keys held from that frame on, in hexadecimal,
+/// or `-` to release them all:
+///
+/// ```text
+/// # frame  keys
+/// 60       5
+/// 75       -
+/// 90       4 6
+/// ```
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    /// The frames on which the keys change, in order, and the keys (a bit each)
+    changes: Vec<(u64, u16)>,
+}
+
+impl InputScript {
+    pub fn parse(source: &str) -> Result<Self, ScriptError> {
+        let mut changes: Vec<(u64, u16)> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let error = |message: String| ScriptError {
+                line: index + 1,
+                message,
+            };
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = line.split_whitespace();
+
+            let Some(frame) = tokens.next() else {
+                continue;
+            };
+            let frame: u64 = frame
+                .parse()
+                .map_err(|_| error(format!("invalid frame <{frame}>")))?;
+
+            if changes.last().is_some_and(|(last, _)| *last >= frame) {
+                return Err(error(format!(
+                    "frame {frame} is not after the previous one"
+                )));
+            }
+
+            let mut keys = 0;
+            for token in tokens {
+                if token == "-" {
+                    continue;
+                }
+
+                match u8::from_str_radix(token, 16) {
+                    Ok(key) if (key as usize) < N_KEYS => keys |= 1 << key,
+                    _ => return Err(error(format!("invalid key <{token}>"))),
+                }
+            }
+
+            changes.push((frame, keys));
+        }
+
+        Ok(Self { changes })
+    }
+
+    /**
+     * Returns the keys held on the frame, a bit per key
+     */
+    pub fn keys_at(&self, frame: u64) -> u16 {
+        self.changes
+            .iter()
+            .take_while(|(start, _)| *start <= frame)
+            .last()
+            .map(|(_, keys)| *keys)
+            .unwrap_or_default()
+    }
+}
+
+/**
+ * Runs up to `frames` frames with the keys of the script, calling
+ * `on_frame` after each one. It stops before if the CPU halts.
+ * Returns how many frames have been run
+ */
+pub fn run_frames(
+    cpu: &mut CPU,
+    frames: u64,
+    input: &InputScript,
+    mut on_frame: impl FnMut(&CPU),
+) -> Result<u64, EmulatorError> {
+    for frame in 0..frames {
+        cpu.set_keys(input.keys_at(frame));
+
+        let running = cpu.run_frame()?;
+        on_frame(cpu);
+
+        if !running {
+            return Ok(frame + 1);
+        }
+    }
+
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_frames, scale_pixels, write_png, GifRecording, InputScript, Palette};
+    use crate::{
+        asm::Assembler,
+        cpu::CPU,
+        quirks::Quirks,
+        rom::{Rom, DEFAULT_LOAD_ADDRESS},
+    };
+
+    #[test]
+    fn test_input_script() {
+        let script = InputScript::parse("# frame keys\n10 5\n20 -\n30 4 f # both\n").unwrap();
+
+        assert_eq!(script.keys_at(0), 0);
+        assert_eq!(script.keys_at(10), 1 << 5);
+        assert_eq!(script.keys_at(19), 1 << 5);
+        assert_eq!(script.keys_at(20), 0);
+        assert_eq!(script.keys_at(500), 1 << 4 | 1 << 0xF);
+
+        assert_eq!(InputScript::parse("10 5\n5 4").unwrap_err().line, 2);
+        assert!(InputScript::parse("10 g").is_err());
+        assert!(InputScript::parse("ten 1").is_err());
+    }
+
+    #[test]
+    fn test_headless_run() {
+        // Draws the digit of the held key, and halts on the key F
+        let program = Assembler::new()
+            .assemble(
+                "
+                loop:
+                    LD V0, 0
+                check:
+                    SKP V0
+                    JP next
+                    CLS
+                    LD F, V0
+                    DRW V1, V1, 5
+                    SE V0, 0xF
+                    JP loop
+                    EXIT
+                next:
+                    ADD V0, 1
+                    SE V0, 16
+                    JP check
+                    JP loop
+                ",
+            )
+            .unwrap();
+        let mut cpu = CPU::with_seed(Quirks::super_chip(), 0);
+        cpu.load_rom(&Rom::from_bytes(program, DEFAULT_LOAD_ADDRESS).unwrap())
+            .unwrap();
+
+        let script = InputScript::parse("5 8\n20 f").unwrap();
+        let mut gif = GifRecording::new();
+        let frames = run_frames(&mut cpu, 100, &script, |cpu| gif.push(cpu.framebuffer())).unwrap();
+
+        assert!(cpu.is_halted());
+        assert!((20..40).contains(&frames));
+        assert_eq!(gif.len(), frames);
+
+        // The 8 and the F, and the empty screen before them
+        assert!(gif.frames.len() >= 3);
+
+        let mut bytes = Vec::new();
+        gif.write(&Palette::default(), 2, &mut bytes).unwrap();
+        assert!(bytes.starts_with(b"GIF89a"));
+
+        let mut bytes = Vec::new();
+        write_png(cpu.framebuffer(), &Palette::default(), 3, &mut bytes).unwrap();
+
+        let decoder = png::Decoder::new(bytes.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut image = vec![0x0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut image).unwrap();
+
+        assert_eq!((info.width, info.height), (64 * 3, 32 * 3));
+        assert_eq!(
+            reader.info().palette.as_deref(),
+            Some(&Palette::default().to_rgb()[..])
+        );
+        assert_eq!(image, scale_pixels(cpu.framebuffer(), 3));
+    }
+
+    #[test]
+    fn test_gif_long_hold() {
+        // 40000 frames are 66667 centiseconds, more than a GIF delay can be
+        let cpu = CPU::new(Quirks::default());
+        let mut gif = GifRecording::new();
+
+        for _ in 0..40000 {
+            gif.push(cpu.framebuffer());
+        }
+
+        let mut bytes = Vec::new();
+        gif.write(&Palette::default(), 1, &mut bytes).unwrap();
+
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(bytes.as_slice())
+            .unwrap();
+        let mut delays = Vec::new();
+
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+
+        assert_eq!(delays, [u16::MAX, 1132]);
+    }
+}
@@ -0,0 +1,509 @@
+use std::fmt;
+
+/// Nibbles of an opcode
+pub type Opcode = (u8, u8, u8, u8);
+
+/// First word of the 4 bytes long **LD I, NNNN** (XO-CHIP)
+pub const LONG_LOAD_OPCODE: u16 = 0xF000;
+
+/// A decoded instruction. The registers are given by their index (0x0 - 0xF).
+///
+/// Its `Display` uses the mnemonics of the README table, so
+/// `Instruction::decode(0x6A02, 0x0)` is shown as `LD VA, 0x02`
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0x0000
+    Halt,
+    /// 0x00CN (SUPER-CHIP)
+    ScrollDown(u8),
+    /// 0x00DN (XO-CHIP)
+    ScrollUp(u8),
+    /// 0x00E0
+    Cls,
+    /// 0x00EE
+    Ret,
+    /// 0x00FB (SUPER-CHIP)
+    ScrollRight,
+    /// 0x00FC (SUPER-CHIP)
+    ScrollLeft,
+    /// 0x00FD (SUPER-CHIP)
+    Exit,
+    /// 0x00FE (SUPER-CHIP)
+    Low,
+    /// 0x00FF (SUPER-CHIP)
+    High,
+    /// 0x1NNN
+    Jp(u16),
+    /// 0x2NNN
+    Call(u16),
+    /// 0x3XNN
+    SeByte(u8, u8),
+    /// 0x4XNN
+    SneByte(u8, u8),
+    /// 0x5XY0
+    SeRegister(u8, u8),
+    /// 0x5XY2 (XO-CHIP)
+    SaveRange(u8, u8),
+    /// 0x5XY3 (XO-CHIP)
+    LoadRange(u8, u8),
+    /// 0x6XNN
+    LdByte(u8, u8),
+    /// 0x7XNN
+    AddByte(u8, u8),
+    /// 0x8XY0
+    LdRegister(u8, u8),
+    /// 0x8XY1
+    Or(u8, u8),
+    /// 0x8XY2
+    And(u8, u8),
+    /// 0x8XY3
+    Xor(u8, u8),
+    /// 0x8XY4
+    AddRegister(u8, u8),
+    /// 0x8XY5
+    Sub(u8, u8),
+    /// 0x8XY6
+    Shr(u8, u8),
+    /// 0x8XY7
+    Subn(u8, u8),
+    /// 0x8XYE
+    Shl(u8, u8),
+    /// 0x9XY0
+    SneRegister(u8, u8),
+    /// 0xANNN
+    LdI(u16),
+    /// 0xBNNN
+    JpV0(u16),
+    /// 0xCXNN
+    Rnd(u8, u8),
+    /// 0xDXYN
+    Drw(u8, u8, u8),
+    /// 0xEX9E
+    Skp(u8),
+    /// 0xEXA1
+    Sknp(u8),
+    /// 0xF000 NNNN (XO-CHIP)
+    LdLongI(u16),
+    /// 0xFN01 (XO-CHIP)
+    Plane(u8),
+    /// 0xF002 (XO-CHIP)
+    Audio,
+    /// 0xFX07
+    LdFromDelayTimer(u8),
+    /// 0xFX0A
+    LdKey(u8),
+    /// 0xFX15
+    LdDelayTimer(u8),
+    /// 0xFX18
+    LdSoundTimer(u8),
+    /// 0xFX1E
+    AddI(u8),
+    /// 0xFX29
+    LdFont(u8),
+    /// 0xFX30 (SUPER-CHIP)
+    LdBigFont(u8),
+    /// 0xFX33
+    LdBcd(u8),
+    /// 0xFX3A (XO-CHIP)
+    Pitch(u8),
+    /// 0xFX55
+    StoreRegisters(u8),
+    /// 0xFX65
+    LoadRegisters(u8),
+    /// 0xFX75 (SUPER-CHIP)
+    StoreFlags(u8),
+    /// 0xFX85 (SUPER-CHIP)
+    LoadFlags(u8),
+    /// Any other opcode. It is shown as data (**DW NNNN**)
+    Unknown(u16),
+}
+
+/**
+ * Splits the given opcode into its 4 nibbles
+ */
+pub fn parse_opcode(opcode: u16) -> Opcode {
+    let c = ((opcode & 0xF000) >> 12) as u8;
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let d = (opcode & 0x000F) as u8;
+
+    (c, x, y, d)
+}
+
+/**
+ * Joins the 3 last nibbles into a 12 bits address (NNN)
+ */
+fn parse_12bit_address(opcode: Opcode) -> u16 {
+    let op1 = opcode.1 as u16;
+    let op2 = opcode.2 as u16;
+    let op3 = opcode.3 as u16;
+
+    op3 | op2 << 4 | op1 << 8
+}
+
+/**
+ * Parse 2 nibbles into a 1 byte hex value
+ */
+fn parse_8bit_address(nibble1: u8, nibble2: u8) -> u8 {
+    nibble1 << 4 | nibble2
+}
+
+/**
+ * Joins 4 nibbles into an opcode. Only the low 4 bits of each one are used
+ */
+fn join_nibbles(c: u8, x: u8, y: u8, d: u8) -> u16 {
+    (c as u16 & 0xF) << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | d as u16 & 0xF
+}
+
+impl Instruction {
+    /**
+     * Decodes an opcode. `next` is the word which follows it, only
+     * used by the 4 bytes long **LD I, NNNN**
+     */
+    pub fn decode(opcode: u16, next: u16) -> Self {
+        let opcodes = parse_opcode(opcode);
+
+        let x = opcodes.1;
+        let y = opcodes.2;
+        let n = opcodes.3;
+        let byte = parse_8bit_address(y, n);
+        let address = parse_12bit_address(opcodes);
+
+        match opcodes {
+            (0, 0, 0, 0) => Instruction::Halt,
+            (0, 0, 0xC, _) => Instruction::ScrollDown(n),
+            (0, 0, 0xD, _) => Instruction::ScrollUp(n),
+            (0, 0, 0xE, 0) => Instruction::Cls,
+            (0, 0, 0xE, 0xE) => Instruction::Ret,
+            (0, 0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0, 0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0, 0, 0xF, 0xD) => Instruction::Exit,
+            (0, 0, 0xF, 0xE) => Instruction::Low,
+            (0, 0, 0xF, 0xF) => Instruction::High,
+            (0x1, _, _, _) => Instruction::Jp(address),
+            (0x2, _, _, _) => Instruction::Call(address),
+            (0x3, _, _, _) => Instruction::SeByte(x, byte),
+            (0x4, _, _, _) => Instruction::SneByte(x, byte),
+            (0x5, _, _, 0x0) => Instruction::SeRegister(x, y),
+            (0x5, _, _, 0x2) => Instruction::SaveRange(x, y),
+            (0x5, _, _, 0x3) => Instruction::LoadRange(x, y),
+            (0x6, _, _, _) => Instruction::LdByte(x, byte),
+            (0x7, _, _, _) => Instruction::AddByte(x, byte),
+            (0x8, _, _, 0x0) => Instruction::LdRegister(x, y),
+            (0x8, _, _, 0x1) => Instruction::Or(x, y),
+            (0x8, _, _, 0x2) => Instruction::And(x, y),
+            (0x8, _, _, 0x3) => Instruction::Xor(x, y),
+            (0x8, _, _, 0x4) => Instruction::AddRegister(x, y),
+            (0x8, _, _, 0x5) => Instruction::Sub(x, y),
+            (0x8, _, _, 0x6) => Instruction::Shr(x, y),
+            (0x8, _, _, 0x7) => Instruction::Subn(x, y),
+            (0x8, _, _, 0xE) => Instruction::Shl(x, y),
+            (0x9, _, _, 0x0) => Instruction::SneRegister(x, y),
+            (0xA, _, _, _) => Instruction::LdI(address),
+            (0xB, _, _, _) => Instruction::JpV0(address),
+            (0xC, _, _, _) => Instruction::Rnd(x, byte),
+            (0xD, _, _, _) => Instruction::Drw(x, y, n),
+            (0xE, _, 0x9, 0xE) => Instruction::Skp(x),
+            (0xE, _, 0xA, 0x1) => Instruction::Sknp(x),
+            (0xF, 0, 0x0, 0x0) => Instruction::LdLongI(next),
+            (0xF, _, 0x0, 0x1) => Instruction::Plane(x),
+            (0xF, 0, 0x0, 0x2) => Instruction::Audio,
+            (0xF, _, 0x0, 0x7) => Instruction::LdFromDelayTimer(x),
+            (0xF, _, 0x0, 0xA) => Instruction::LdKey(x),
+            (0xF, _, 0x1, 0x5) => Instruction::LdDelayTimer(x),
+            (0xF, _, 0x1, 0x8) => Instruction::LdSoundTimer(x),
+            (0xF, _, 0x1, 0xE) => Instruction::AddI(x),
+            (0xF, _, 0x2, 0x9) => Instruction::LdFont(x),
+            (0xF, _, 0x3, 0x0) => Instruction::LdBigFont(x),
+            (0xF, _, 0x3, 0x3) => Instruction::LdBcd(x),
+            (0xF, _, 0x3, 0xA) => Instruction::Pitch(x),
+            (0xF, _, 0x5, 0x5) => Instruction::StoreRegisters(x),
+            (0xF, _, 0x6, 0x5) => Instruction::LoadRegisters(x),
+            (0xF, _, 0x7, 0x5) => Instruction::StoreFlags(x),
+            (0xF, _, 0x8, 0x5) => Instruction::LoadFlags(x),
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+
+    /**
+     * Returns the bytes of the instruction, the inverse of `decode`
+     */
+    pub fn encode(&self) -> Vec<u8> {
+        let opcode = match *self {
+            Instruction::Halt => 0x0000,
+            Instruction::ScrollDown(n) => join_nibbles(0x0, 0x0, 0xC, n),
+            Instruction::ScrollUp(n) => join_nibbles(0x0, 0x0, 0xD, n),
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Low => 0x00FE,
+            Instruction::High => 0x00FF,
+            Instruction::Jp(address) => 0x1000 | address & 0xFFF,
+            Instruction::Call(address) => 0x2000 | address & 0xFFF,
+            Instruction::SeByte(x, byte) => join_nibbles(0x3, x, byte >> 4, byte),
+            Instruction::SneByte(x, byte) => join_nibbles(0x4, x, byte >> 4, byte),
+            Instruction::SeRegister(x, y) => join_nibbles(0x5, x, y, 0x0),
+            Instruction::SaveRange(x, y) => join_nibbles(0x5, x, y, 0x2),
+            Instruction::LoadRange(x, y) => join_nibbles(0x5, x, y, 0x3),
+            Instruction::LdByte(x, byte) => join_nibbles(0x6, x, byte >> 4, byte),
+            Instruction::AddByte(x, byte) => join_nibbles(0x7, x, byte >> 4, byte),
+            Instruction::LdRegister(x, y) => join_nibbles(0x8, x, y, 0x0),
+            Instruction::Or(x, y) => join_nibbles(0x8, x, y, 0x1),
+            Instruction::And(x, y) => join_nibbles(0x8, x, y, 0x2),
+            Instruction::Xor(x, y) => join_nibbles(0x8, x, y, 0x3),
+            Instruction::AddRegister(x, y) => join_nibbles(0x8, x, y, 0x4),
+            Instruction::Sub(x, y) => join_nibbles(0x8, x, y, 0x5),
+            Instruction::Shr(x, y) => join_nibbles(0x8, x, y, 0x6),
+            Instruction::Subn(x, y) => join_nibbles(0x8, x, y, 0x7),
+            Instruction::Shl(x, y) => join_nibbles(0x8, x, y, 0xE),
+            Instruction::SneRegister(x, y) => join_nibbles(0x9, x, y, 0x0),
+            Instruction::LdI(address) => 0xA000 | address & 0xFFF,
+            Instruction::JpV0(address) => 0xB000 | address & 0xFFF,
+            Instruction::Rnd(x, byte) => join_nibbles(0xC, x, byte >> 4, byte),
+            Instruction::Drw(x, y, n) => join_nibbles(0xD, x, y, n),
+            Instruction::Skp(x) => join_nibbles(0xE, x, 0x9, 0xE),
+            Instruction::Sknp(x) => join_nibbles(0xE, x, 0xA, 0x1),
+            Instruction::LdLongI(_) => LONG_LOAD_OPCODE,
+            Instruction::Plane(n) => join_nibbles(0xF, n, 0x0, 0x1),
+            Instruction::Audio => 0xF002,
+            Instruction::LdFromDelayTimer(x) => join_nibbles(0xF, x, 0x0, 0x7),
+            Instruction::LdKey(x) => join_nibbles(0xF, x, 0x0, 0xA),
+            Instruction::LdDelayTimer(x) => join_nibbles(0xF, x, 0x1, 0x5),
+            Instruction::LdSoundTimer(x) => join_nibbles(0xF, x, 0x1, 0x8),
+            Instruction::AddI(x) => join_nibbles(0xF, x, 0x1, 0xE),
+            Instruction::LdFont(x) => join_nibbles(0xF, x, 0x2, 0x9),
+            Instruction::LdBigFont(x) => join_nibbles(0xF, x, 0x3, 0x0),
+            Instruction::LdBcd(x) => join_nibbles(0xF, x, 0x3, 0x3),
+            Instruction::Pitch(x) => join_nibbles(0xF, x, 0x3, 0xA),
+            Instruction::StoreRegisters(x) => join_nibbles(0xF, x, 0x5, 0x5),
+            Instruction::LoadRegisters(x) => join_nibbles(0xF, x, 0x6, 0x5),
+            Instruction::StoreFlags(x) => join_nibbles(0xF, x, 0x7, 0x5),
+            Instruction::LoadFlags(x) => join_nibbles(0xF, x, 0x8, 0x5),
+            Instruction::Unknown(opcode) => opcode,
+        };
+
+        let mut bytes = opcode.to_be_bytes().to_vec();
+
+        if let Instruction::LdLongI(address) = self {
+            bytes.extend(address.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    /**
+     * Returns how many bytes the instruction takes on memory
+     */
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LdLongI(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /**
+     * True if the instruction was added by SUPER-CHIP (XO-CHIP has it too)
+     */
+    pub fn is_super_chip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollDown(_)
+                | Instruction::ScrollRight
+                | Instruction::ScrollLeft
+                | Instruction::Exit
+                | Instruction::Low
+                | Instruction::High
+                | Instruction::Drw(_, _, 0)
+                | Instruction::LdBigFont(_)
+                | Instruction::StoreFlags(_)
+                | Instruction::LoadFlags(_)
+        )
+    }
+
+    /**
+     * True if the instruction only exists on XO-CHIP
+     */
+    pub fn is_xo_chip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ScrollUp(_)
+                | Instruction::SaveRange(..)
+                | Instruction::LoadRange(..)
+                | Instruction::LdLongI(_)
+                | Instruction::Plane(_)
+                | Instruction::Audio
+                | Instruction::Pitch(_)
+        )
+    }
+
+    /**
+     * Returns the address where a jump or a call goes
+     */
+    pub fn target(&self) -> Option<u16> {
+        match self {
+            Instruction::Jp(address) | Instruction::Call(address) | Instruction::JpV0(address) => {
+                Some(*address)
+            }
+            _ => None,
+        }
+    }
+
+    /**
+     * Shows the instruction with its jump or call target replaced by a label
+     */
+    pub fn with_label<'a>(&'a self, label: &'a str) -> Labeled<'a> {
+        Labeled {
+            instruction: self,
+            label,
+        }
+    }
+
+    /**
+     * Writes the mnemonic. The target is written as `label` when given
+     */
+    fn write(&self, f: &mut fmt::Formatter<'_>, label: Option<&str>) -> fmt::Result {
+        let target = |address: &u16| match label {
+            Some(label) => label.to_string(),
+            None => format!("{address:#05X}"),
+        };
+
+        match self {
+            Instruction::Halt => write!(f, "HALT"),
+            Instruction::ScrollDown(n) => write!(f, "SCD {n}"),
+            Instruction::ScrollUp(n) => write!(f, "SCU {n}"),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Low => write!(f, "LOW"),
+            Instruction::High => write!(f, "HIGH"),
+            Instruction::Jp(address) => write!(f, "JP {}", target(address)),
+            Instruction::Call(address) => write!(f, "CALL {}", target(address)),
+            Instruction::SeByte(x, byte) => write!(f, "SE V{x:X}, {byte:#04X}"),
+            Instruction::SneByte(x, byte) => write!(f, "SNE V{x:X}, {byte:#04X}"),
+            Instruction::SeRegister(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            Instruction::SaveRange(x, y) => write!(f, "SAVE V{x:X} - V{y:X}"),
+            Instruction::LoadRange(x, y) => write!(f, "LOAD V{x:X} - V{y:X}"),
+            Instruction::LdByte(x, byte) => write!(f, "LD V{x:X}, {byte:#04X}"),
+            Instruction::AddByte(x, byte) => write!(f, "ADD V{x:X}, {byte:#04X}"),
+            Instruction::LdRegister(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Instruction::Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            Instruction::And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Instruction::Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            Instruction::AddRegister(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Instruction::Sub(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Instruction::Shr(x, y) => write!(f, "SHR V{x:X}, V{y:X}"),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Instruction::Shl(x, y) => write!(f, "SHL V{x:X}, V{y:X}"),
+            Instruction::SneRegister(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            Instruction::LdI(address) => write!(f, "LD I, {address:#05X}"),
+            Instruction::JpV0(address) => write!(f, "JP V0, {}", target(address)),
+            Instruction::Rnd(x, byte) => write!(f, "RND V{x:X}, {byte:#04X}"),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            Instruction::Skp(x) => write!(f, "SKP V{x:X}"),
+            Instruction::Sknp(x) => write!(f, "SKNP V{x:X}"),
+            Instruction::LdLongI(address) => write!(f, "LD I, LONG {address:#06X}"),
+            Instruction::Plane(n) => write!(f, "PLANE {n}"),
+            Instruction::Audio => write!(f, "AUDIO"),
+            Instruction::LdFromDelayTimer(x) => write!(f, "LD V{x:X}, DT"),
+            Instruction::LdKey(x) => write!(f, "LD V{x:X}, K"),
+            Instruction::LdDelayTimer(x) => write!(f, "LD DT, V{x:X}"),
+            Instruction::LdSoundTimer(x) => write!(f, "LD ST, V{x:X}"),
+            Instruction::AddI(x) => write!(f, "ADD I, V{x:X}"),
+            Instruction::LdFont(x) => write!(f, "LD F, V{x:X}"),
+            Instruction::LdBigFont(x) => write!(f, "LD HF, V{x:X}"),
+            Instruction::LdBcd(x) => write!(f, "LD B, V{x:X}"),
+            Instruction::Pitch(x) => write!(f, "PITCH V{x:X}"),
+            Instruction::StoreRegisters(x) => write!(f, "LD [I], V{x:X}"),
+            Instruction::LoadRegisters(x) => write!(f, "LD V{x:X}, [I]"),
+            Instruction::StoreFlags(x) => write!(f, "LD R, V{x:X}"),
+            Instruction::LoadFlags(x) => write!(f, "LD V{x:X}, R"),
+            Instruction::Unknown(opcode) => write!(f, "DW {opcode:#06X}"),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+/// An instruction shown with a label instead of its target address
+
+#[derive(Debug, Clone, Copy)]
+pub struct Labeled<'a> {
+    instruction: &'a Instruction,
+    label: &'a str,
+}
+
+impl fmt::Display for Labeled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.instruction.write(f, Some(self.label))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction;
+
+    #[test]
+    fn test_decode_and_display() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x00C4, "SCD 4"),
+            (0x1228, "JP 0x228"),
+            (0x3A0F, "SE VA, 0x0F"),
+            (0x8AB6, "SHR VA, VB"),
+            (0xB300, "JP V0, 0x300"),
+            (0xD125, "DRW V1, V2, 5"),
+            (0xF30A, "LD V3, K"),
+            (0xF255, "LD [I], V2"),
+            (0x5122, "SAVE V1 - V2"),
+            (0x0123, "DW 0x0123"),
+        ];
+
+        for (opcode, mnemonic) in cases {
+            assert_eq!(Instruction::decode(opcode, 0x0).to_string(), mnemonic);
+        }
+    }
+
+    #[test]
+    fn test_encode_is_the_inverse_of_decode() {
+        for opcode in 0x0..=0xFFFF {
+            let instruction = Instruction::decode(opcode, 0xABCD);
+            let bytes = instruction.encode();
+
+            assert_eq!(bytes[0..2], opcode.to_be_bytes(), "{instruction}");
+        }
+
+        assert_eq!(
+            Instruction::LdLongI(0xABCD).encode(),
+            vec![0xF0, 0x00, 0xAB, 0xCD]
+        );
+    }
+
+    #[test]
+    fn test_decode_long_load() {
+        let instruction = Instruction::decode(0xF000, 0xABCD);
+
+        assert_eq!(instruction, Instruction::LdLongI(0xABCD));
+        assert_eq!(instruction.size(), 4);
+        assert_eq!(instruction.to_string(), "LD I, LONG 0xABCD");
+    }
+
+    #[test]
+    fn test_display_with_label() {
+        let instruction = Instruction::decode(0x2300, 0x0);
+
+        assert_eq!(instruction.target(), Some(0x300));
+        assert_eq!(
+            instruction.with_label("sub_300").to_string(),
+            "CALL sub_300"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+pub const N_KEYS: usize = 16;
+
+/// How the QWERTY keys are laid out on the hex keypad:
+///
+/// ```text
+/// 1 2 3 4      1 2 3 C
+/// Q W E R  =>  4 5 6 D
+/// A S D F      7 8 9 E
+/// Z X C V      A 0 B F
+/// ```
+pub const KEY_MAP: [(char, u8); N_KEYS] = [
+    ('1', 0x1),
+    ('2', 0x2),
+    ('3', 0x3),
+    ('4', 0xC),
+    ('q', 0x4),
+    ('w', 0x5),
+    ('e', 0x6),
+    ('r', 0xD),
+    ('a', 0x7),
+    ('s', 0x8),
+    ('d', 0x9),
+    ('f', 0xE),
+    ('z', 0xA),
+    ('x', 0x0),
+    ('c', 0xB),
+    ('v', 0xF),
+];
+
+/**
+ * Returns the keypad key of a QWERTY key
+ */
+pub fn keypad_key(key: char) -> Option<u8> {
+    let key = key.to_ascii_lowercase();
+
+    KEY_MAP
+        .iter()
+        .find(|(qwerty, _)| *qwerty == key)
+        .map(|(_, keypad)| *keypad)
+}
+
+/// Hexadecimal keypad (0x0 - 0xF)
+///
+/// |1|2|3|C|
+/// |4|5|6|D|
+/// |7|8|9|E|
+/// |A|0|B|F|
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keypad {
+    keys: [bool; N_KEYS],
+}
+
+#[allow(dead_code)]
+impl Keypad {
+    pub fn new() -> Self {
+        Self {
+            keys: [false; N_KEYS],
+        }
+    }
+
+    /**
+     * Marks a key as pressed. Only the low nibble of the key is used
+     */
+    pub fn press(&mut self, key: u8) {
+        self.keys[(key & 0xF) as usize] = true;
+    }
+
+    /**
+     * Marks a key as released. Only the low nibble of the key is used
+     */
+    pub fn release(&mut self, key: u8) {
+        self.keys[(key & 0xF) as usize] = false;
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.keys[(key & 0xF) as usize]
+    }
+
+    /**
+     * Returns the state of every key, indexed by its value
+     */
+    pub fn keys(&self) -> &[bool; N_KEYS] {
+        &self.keys
+    }
+
+    /**
+     * Returns the state of every key as a mask: bit N is set while the key N is pressed
+     */
+    pub fn bits(&self) -> u16 {
+        self.keys
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (key, pressed)| bits | (*pressed as u16) << key)
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::keypad_key;
+
+    #[test]
+    fn test_keypad_keys() {
+        assert_eq!(keypad_key('1'), Some(0x1));
+        assert_eq!(keypad_key('4'), Some(0xC));
+        assert_eq!(keypad_key('X'), Some(0x0));
+        assert_eq!(keypad_key('v'), Some(0xF));
+        assert_eq!(keypad_key('p'), None);
+    }
+}
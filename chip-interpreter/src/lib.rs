@@ -0,0 +1,66 @@
+//! CHIP-8 interpreter core.
+//!
+//! The [`CPU`] owns the whole machine: [`Memory`], [`Stack`], the
+//! [`Framebuffer`], the [`Keypad`] and the timers. Frontends drive it with
+//! [`CPU::step`] or [`CPU::run_frame`], forward the keys with
+//! [`CPU::press_key`] / [`CPU::release_key`] and read the pixels from
+//! [`CPU::framebuffer`].
+//!
+//! The terminal frontend (`tui`) and the headless runs with their PNG and
+//! GIF output (`headless`) are behind the features of the same name, so a
+//! frontend which only embeds the core does not build their dependencies.
+
+pub mod asm;
+pub mod audio;
+pub mod cpu;
+pub mod debugger;
+pub mod disasm;
+pub mod display;
+pub mod error;
+pub mod expression;
+pub mod flags;
+pub mod gdb;
+#[cfg(feature = "headless")]
+pub mod headless;
+pub mod instruction;
+pub mod keypad;
+pub mod memory;
+pub mod movie;
+pub mod octo;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod rom;
+pub mod savestate;
+#[cfg(feature = "tui")]
+pub mod tui;
+
+pub use asm::{Assembler, AssemblyError, AssemblyErrorKind};
+pub use audio::{AudioPattern, AUDIO_PATTERN_SIZE, DEFAULT_PITCH};
+pub use cpu::{StepEvent, CORE_VERSION, CPU, INSTRUCTIONS_PER_TICK};
+pub use debugger::{Debugger, StopReason, WatchAccess, Watchpoint, DEFAULT_RUN_LIMIT};
+pub use disasm::{DisassembledInstruction, Disassembly};
+pub use display::{
+    Framebuffer, Palette, HIRES_SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, N_PLANES, SCREEN_HEIGHT,
+    SCREEN_WIDTH,
+};
+pub use error::{EmulatorError, FaultKind};
+pub use expression::{Expression, ExpressionError, Register};
+pub use flags::{FileFlagStorage, FlagStorage, N_RPL_FLAGS};
+pub use gdb::{GdbStub, DEFAULT_GDB_PORT};
+#[cfg(feature = "headless")]
+pub use headless::{run_frames, write_png, GifRecording, InputScript, ScriptError};
+pub use instruction::{Instruction, Opcode};
+pub use keypad::{keypad_key, Keypad, KEY_MAP, N_KEYS};
+pub use memory::{
+    AccessKind, Memory, MemoryAccess, Stack, MAX_STACK_SIZE, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE,
+};
+pub use movie::{Movie, MovieError, MAX_MOVIE_FRAMES, MOVIE_VERSION};
+pub use octo::{CompiledProgram, InstructionSet, OctoCompiler, SymbolTable};
+pub use quirks::Quirks;
+pub use rewind::{Rewind, DEFAULT_REWIND_CAPACITY, DEFAULT_SNAPSHOT_INTERVAL};
+pub use rng::{Rng, SeededRng};
+pub use rom::{Rom, RomError, DEFAULT_LOAD_ADDRESS, ETI660_LOAD_ADDRESS};
+pub use savestate::{slot_path, SaveState, SaveStateError, SAVE_STATE_VERSION};
+#[cfg(feature = "tui")]
+pub use tui::{run_tui, CellRenderer, TuiMode, TuiOptions};
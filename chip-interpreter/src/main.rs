@@ -1,45 +1,671 @@
-mod cpu;
-mod memory;
-
 use std::{
     env,
-    fs::File,
-    io::{BufReader, Read, Result},
+    fmt::Display,
+    fs,
+    io::{self, Result},
+    path::Path,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
 };
 
-use cpu::CPU;
+use chip8_emulator::{
+    run_frames, run_tui, slot_path, write_png, Assembler, Debugger, Disassembly, FileFlagStorage,
+    GdbStub, GifRecording, InputScript, InstructionSet, Movie, OctoCompiler, Palette, Quirks, Rom,
+    SaveState, SeededRng, SymbolTable, TuiMode, TuiOptions, CPU, DEFAULT_GDB_PORT,
+    DEFAULT_LOAD_ADDRESS,
+};
 
-fn main() -> Result<()> {
-    let path_to_rom = match env::args().nth(1) {
+const USAGE: &str = "Usage: cargo run <my_file.ch8> [--quirks vip|chip48|schip|xochip|modern] [--load-address 0x200] [--flags <file>] [--load-state <file>] [--save-state <file>] [--slot 0-9] [--record <file> [--input <script>] [--frames 600] | --replay <file>]
+       cargo run disasm <my_file.ch8> [--labels] [--load-address 0x200]
+       cargo run assemble <my_file.asm> [-o my_file.ch8] [--load-address 0x200]
+       cargo run octo <my_file.8o> [-o my_file.ch8] [--target chip8|schip|xochip] [--symbols my_file.sym]
+       cargo run debug <my_file.ch8> [--quirks vip|chip48|schip|xochip|modern] [--load-address 0x200] [--symbols my_file.sym] [--gdb [--port 3333]]
+       cargo run tui <my_file.ch8> [--braille] [--quirks vip|chip48|schip|xochip|modern] [--load-address 0x200] [--flags <file> | --record <file>] [--frames N]
+       cargo run headless <my_file.ch8> [--frames 600] [--input <script>] [--png out.png] [--gif out.gif] [--scale 4] [--palette 000000,ffffff] [--seed 0] [--quirks ...] [--load-address 0x200]";
+
+/// Frames a headless run lasts by default: 10 seconds
+const DEFAULT_HEADLESS_FRAMES: u64 = 600;
+/// Size of each pixel on the images of a headless run by default
+const DEFAULT_HEADLESS_SCALE: usize = 4;
+
+/**
+ * Prints the error in red and exits
+ */
+fn exit_with_error(message: impl Display) -> ! {
+    eprintln!("\u{001b}[31mError: {message}\u{001b}[0m");
+    std::process::exit(1);
+}
+
+/**
+ * Parses an address written in decimal or in hexadecimal (0x600)
+ */
+fn parse_address(value: &str) -> Option<u16> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/**
+ * Parses the value of `--load-address`
+ */
+fn parse_load_address(value: Option<String>) -> u16 {
+    let value = value.unwrap_or_default();
+
+    parse_address(&value)
+        .unwrap_or_else(|| exit_with_error(format!("invalid load address <{value}>")))
+}
+
+/**
+ * Parses the value of `--quirks`
+ */
+fn parse_quirks(value: Option<String>) -> Quirks {
+    let name = value.unwrap_or_default();
+
+    Quirks::from_name(&name).unwrap_or_else(|| {
+        exit_with_error(format!(
+            "unknown quirks <{name}> (vip, chip48, schip, xochip or modern)"
+        ))
+    })
+}
+
+/**
+ * Parses the number given to a flag
+ */
+fn parse_number<T: FromStr>(flag: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_default();
+
+    value
+        .parse()
+        .unwrap_or_else(|_| exit_with_error(format!("invalid {flag} <{value}>")))
+}
+
+/**
+ * Exits showing the usage if the path argument is missing
+ */
+fn require_path(path: Option<String>) -> String {
+    match path {
         Some(path) => path,
         None => {
-            eprintln!("\n\u{001b}[31mError (Missing argument) => path\n\u{001b}[32mUsage: cargo run <my_file.ch8>\u{001b}[0m");
+            eprintln!(
+                "\n\u{001b}[31mError (Missing argument) => path\n\u{001b}[32m{USAGE}\u{001b}[0m"
+            );
             std::process::exit(1);
         }
+    }
+}
+
+/**
+ * Warns if the RPL flags of `--flags` could not be saved
+ */
+fn warn_unsaved_flags(cpu: &mut CPU) {
+    if let Some(err) = cpu.take_flag_storage_error() {
+        eprintln!("\u{001b}[33mWarning: the RPL flags could not be saved: {err}\u{001b}[0m");
+    }
+}
+
+/**
+ * Reads the script of `--input`
+ */
+fn read_input_script(value: Option<String>) -> InputScript {
+    let path = require_path(value);
+    let text = fs::read_to_string(&path).unwrap_or_else(|err| exit_with_error(err));
+
+    InputScript::parse(&text).unwrap_or_else(|err| exit_with_error(format!("{path}: {err}")))
+}
+
+/**
+ * Reads the ROM, warning about an odd length
+ */
+fn read_rom(path_to_rom: Option<String>, load_address: u16) -> Rom {
+    let path_to_rom = require_path(path_to_rom);
+
+    let rom = Rom::from_file(path_to_rom, load_address).unwrap_or_else(|err| exit_with_error(err));
+
+    if rom.has_odd_length() {
+        eprintln!(
+            "\u{001b}[33mWarning: the ROM has an odd length ({} bytes), it may be truncated\u{001b}[0m",
+            rom.len()
+        );
+    }
+
+    rom
+}
+
+/**
+ * Writes the movie, and then reports the error which stopped the recording
+ */
+fn save_movie<E: Display>(movie: &Movie, path: &str, result: std::result::Result<(), E>) {
+    movie
+        .save_to_file(path)
+        .unwrap_or_else(|err| exit_with_error(format!("{err} ({path})")));
+
+    println!("Recorded {} frames on {path}", movie.frames.len());
+
+    if let Err(err) = result {
+        exit_with_error(err);
+    }
+}
+
+/**
+ * Runs up to `frames` frames of the ROM from a fresh machine with the keys
+ * of the script, recording a movie. It stops before if the CPU halts or on
+ * Ctrl+C, and the movie is written even if the CPU faults, so it can
+ * reproduce it
+ */
+fn record_movie(rom: &Rom, quirks: Quirks, path: &str, input: &InputScript, frames: u64) -> CPU {
+    let mut movie = Movie::new(rom, quirks, SeededRng::from_time().next_u64());
+    let mut cpu = movie.start(rom).unwrap_or_else(|err| exit_with_error(err));
+
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler = {
+        let interrupted = interrupted.clone();
+        move || interrupted.store(true, Ordering::Relaxed)
     };
+    ctrlc::set_handler(handler).unwrap_or_else(|err| exit_with_error(err));
 
-    let mut file = BufReader::new(match File::open(path_to_rom) {
-        Ok(f) => f,
-        Err(err) => {
-            eprintln!("\u{001b}[31mError: {err}\u{001b}[0m");
-            std::process::exit(1);
+    let mut result = Ok(());
+
+    for frame in 0..frames {
+        if interrupted.load(Ordering::Relaxed) {
+            break;
         }
-    });
 
-    let mut buf = CPU::create_buffer();
-    let mut cpu = CPU::new();
+        cpu.set_keys(input.keys_at(frame));
 
-    match file.read(&mut buf) {
-        Ok(_) => {}
-        Err(err) => {
-            eprintln!("\u{001b}[31mError: {err}\u{001b}[0m");
-            std::process::exit(1);
+        match movie.record_frame(&mut cpu) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+
+    save_movie(&movie, path, result);
+
+    cpu
+}
+
+/**
+ * Replays a movie, failing if it was recorded on another ROM
+ * or another version of the emulator
+ */
+fn replay_movie(path_to_rom: Option<String>, path: &str) -> CPU {
+    let movie =
+        Movie::from_file(path).unwrap_or_else(|err| exit_with_error(format!("{err} ({path})")));
+    let rom = read_rom(path_to_rom, movie.load_address);
+
+    let cpu = movie
+        .replay(&rom)
+        .unwrap_or_else(|err| exit_with_error(format!("{err} ({path})")));
+
+    println!("Replayed {} frames of {path}", movie.frames.len());
+
+    cpu
+}
+
+/**
+ * Runs a ROM (the default command)
+ */
+fn run_command(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut path_to_rom = None;
+    let mut quirks = Quirks::default();
+    let mut load_address = DEFAULT_LOAD_ADDRESS;
+    let mut flags_path = None;
+    let mut load_state = None;
+    let mut save_state = None;
+    let mut record = None;
+    let mut replay = None;
+    let mut input = None;
+    let mut frames = None;
+    let mut slot = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => quirks = parse_quirks(args.next()),
+            "--load-address" => load_address = parse_load_address(args.next()),
+            "--flags" => flags_path = args.next(),
+            "--load-state" => load_state = args.next(),
+            "--save-state" => save_state = args.next(),
+            "--slot" => slot = Some(parse_number::<u8>("slot", args.next())),
+            "--record" => record = args.next(),
+            "--replay" => replay = args.next(),
+            "--input" => input = Some(read_input_script(args.next())),
+            "--frames" => frames = Some(parse_number("number of frames", args.next())),
+            _ => path_to_rom = Some(arg),
         }
+    }
+
+    // A movie starts from a fresh machine, without the RPL flags file
+    if (record.is_some() || replay.is_some()) && (load_state.is_some() || flags_path.is_some())
+        || record.is_some() && replay.is_some()
+    {
+        exit_with_error(
+            "--record and --replay can not be used with --load-state or --flags, nor together",
+        );
+    }
+
+    if record.is_none() && (input.is_some() || frames.is_some()) {
+        exit_with_error("--input and --frames are only used with --record");
+    }
+
+    // Starts from the slot when it has been saved, and saves it at the end
+    if let Some(slot) = slot {
+        if load_state.is_some() || save_state.is_some() || record.is_some() || replay.is_some() {
+            exit_with_error(
+                "--slot can not be used with --load-state, --save-state, --record or --replay",
+            );
+        }
+
+        if slot > 9 {
+            exit_with_error(format!("invalid slot <{slot}> (0 - 9)"));
+        }
+
+        let path = slot_path(require_path(path_to_rom.clone()), slot);
+
+        if path.exists() {
+            load_state = Some(path.display().to_string());
+        }
+        save_state = Some(path.display().to_string());
+    }
+
+    let cpu = match (record, replay) {
+        (Some(path), _) => record_movie(
+            &read_rom(path_to_rom, load_address),
+            quirks,
+            &path,
+            &input.unwrap_or_default(),
+            frames.unwrap_or(DEFAULT_HEADLESS_FRAMES),
+        ),
+        (_, Some(path)) => replay_movie(path_to_rom, &path),
+        _ => run_rom(path_to_rom, quirks, load_address, flags_path, load_state),
     };
 
-    cpu.load_rom(&buf);
+    if let Some(path) = save_state {
+        cpu.save_state()
+            .save_to_file(&path)
+            .unwrap_or_else(|err| exit_with_error(format!("{err} ({path})")));
+    }
 
-    cpu.run();
+    Ok(())
+}
+
+/**
+ * Runs the ROM until it halts or waits for a key
+ */
+fn run_rom(
+    path_to_rom: Option<String>,
+    quirks: Quirks,
+    load_address: u16,
+    flags_path: Option<String>,
+    load_state: Option<String>,
+) -> CPU {
+    let rom = read_rom(path_to_rom, load_address);
+    let mut cpu = CPU::new(quirks);
+
+    if let Some(path) = flags_path {
+        cpu.set_flag_storage(Box::new(FileFlagStorage::new(path)));
+    }
+
+    if let Err(err) = cpu.load_rom(&rom) {
+        exit_with_error(err);
+    }
+
+    // The state replaces the whole machine, the ROM included
+    if let Some(path) = load_state {
+        let state = SaveState::from_file(&path)
+            .unwrap_or_else(|err| exit_with_error(format!("{err} ({path})")));
+
+        cpu.restore_state(&state);
+    }
+
+    let result = cpu.run();
+    warn_unsaved_flags(&mut cpu);
+
+    if let Err(err) = result {
+        exit_with_error(err);
+    }
+
+    cpu
+}
+
+/**
+ * Prints the instructions of a ROM (`disasm`)
+ */
+fn disasm_command(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut path_to_rom = None;
+    let mut load_address = DEFAULT_LOAD_ADDRESS;
+    let mut labels = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--labels" => labels = true,
+            "--load-address" => load_address = parse_load_address(args.next()),
+            _ => path_to_rom = Some(arg),
+        }
+    }
+
+    let mut disassembly = Disassembly::new(&read_rom(path_to_rom, load_address));
+
+    if labels {
+        disassembly = disassembly.with_labels();
+    }
+
+    print!("{disassembly}");
 
     Ok(())
 }
+
+/**
+ * Assembles a source file into a ROM (`assemble`)
+ */
+fn assemble_command(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut path_to_source = None;
+    let mut output = None;
+    let mut load_address = DEFAULT_LOAD_ADDRESS;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = args.next(),
+            "--load-address" => load_address = parse_load_address(args.next()),
+            _ if path_to_source.is_some() => {
+                exit_with_error(format!("unexpected argument <{arg}>"))
+            }
+            _ => path_to_source = Some(arg),
+        }
+    }
+
+    let path_to_source = require_path(path_to_source);
+    let source = fs::read_to_string(&path_to_source)
+        .unwrap_or_else(|err| exit_with_error(format!("{err} ({path_to_source})")));
+
+    // my_file.asm -> my_file.ch8
+    let output = output.unwrap_or_else(|| {
+        Path::new(&path_to_source)
+            .with_extension("ch8")
+            .display()
+            .to_string()
+    });
+
+    let bytes = Assembler::with_load_address(load_address)
+        .assemble_source(&source, Path::new(&path_to_source))
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    // The same checks the interpreter does before running it
+    let rom = Rom::from_bytes(bytes, load_address).unwrap_or_else(|err| exit_with_error(err));
+
+    fs::write(&output, rom.data())?;
+
+    println!("{} bytes written on {output}", rom.len());
+
+    Ok(())
+}
+
+/**
+ * Compiles an Octo source file into a ROM (`octo`)
+ */
+fn octo_command(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut path_to_source = None;
+    let mut output = None;
+    let mut symbols_path = None;
+    let mut instruction_set = InstructionSet::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = args.next(),
+            "--symbols" => symbols_path = args.next(),
+            "--target" => {
+                let name = args.next().unwrap_or_default();
+
+                instruction_set = InstructionSet::from_name(&name).unwrap_or_else(|| {
+                    exit_with_error(format!("unknown target <{name}> (chip8, schip or xochip)"))
+                });
+            }
+            _ => path_to_source = Some(arg),
+        }
+    }
+
+    let path_to_source = require_path(path_to_source);
+
+    let output = output.unwrap_or_else(|| {
+        Path::new(&path_to_source)
+            .with_extension("ch8")
+            .display()
+            .to_string()
+    });
+
+    let program = OctoCompiler::new(instruction_set)
+        .compile_file(&path_to_source)
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    let rom = Rom::from_bytes(program.bytes, DEFAULT_LOAD_ADDRESS)
+        .unwrap_or_else(|err| exit_with_error(err));
+
+    fs::write(&output, rom.data())?;
+
+    if let Some(path) = symbols_path {
+        fs::write(path, program.symbols.to_string())?;
+    }
+
+    println!("{} bytes written on {output}", rom.len());
+
+    Ok(())
+}
+
+/**
+ * Runs a ROM step by step on the terminal, or under GDB (`debug`)
+ */
+fn debug_command(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut path_to_rom = None;
+    let mut quirks = Quirks::default();
+    let mut load_address = DEFAULT_LOAD_ADDRESS;
+    let mut symbols_path = None;
+    let mut gdb = false;
+    let mut port = DEFAULT_GDB_PORT;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => quirks = parse_quirks(args.next()),
+            "--load-address" => load_address = parse_load_address(args.next()),
+            "--symbols" => symbols_path = args.next(),
+            "--gdb" => gdb = true,
+            "--port" => {
+                let value = args.next().unwrap_or_default();
+
+                port = value
+                    .parse()
+                    .unwrap_or_else(|_| exit_with_error(format!("invalid port <{value}>")));
+            }
+            _ => path_to_rom = Some(arg),
+        }
+    }
+
+    let path_to_rom = require_path(path_to_rom);
+    let rom = read_rom(Some(path_to_rom.clone()), load_address);
+    let mut cpu = CPU::new(quirks);
+
+    if let Err(err) = cpu.load_rom(&rom) {
+        exit_with_error(err);
+    }
+
+    let mut debugger = Debugger::new(cpu).with_rom_path(path_to_rom);
+
+    if let Some(path) = symbols_path {
+        let text = fs::read_to_string(&path).unwrap_or_else(|err| exit_with_error(err));
+        let symbols = SymbolTable::parse(&text)
+            .unwrap_or_else(|| exit_with_error(format!("invalid symbol table <{path}>")));
+
+        debugger = debugger.with_symbols(symbols);
+    }
+
+    if gdb {
+        println!("Waiting for GDB on 127.0.0.1:{port} (target remote :{port})");
+
+        return GdbStub::new(debugger).listen(("127.0.0.1", port));
+    }
+
+    debugger.repl(io::stdin().lock(), io::stdout())
+}
+
+/**
+ * Plays a ROM on the terminal (`tui`)
+ */
+fn tui_command(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut path_to_rom = None;
+    let mut quirks = Quirks::default();
+    let mut load_address = DEFAULT_LOAD_ADDRESS;
+    let mut flags_path = None;
+    let mut record = None;
+    let mut options = TuiOptions::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => quirks = parse_quirks(args.next()),
+            "--load-address" => load_address = parse_load_address(args.next()),
+            "--flags" => flags_path = args.next(),
+            "--record" => record = args.next(),
+            "--frames" => options.frames = Some(parse_number("number of frames", args.next())),
+            "--braille" => options.mode = TuiMode::Braille,
+            _ => path_to_rom = Some(arg),
+        }
+    }
+
+    let path_to_rom = require_path(path_to_rom);
+    let rom = read_rom(Some(path_to_rom.clone()), load_address);
+
+    // Recorded until Esc, Ctrl+C, a fault or the end of --frames. Loading
+    // a quick-save slot would break the movie, so they are only used below
+    if let Some(path) = record {
+        if flags_path.is_some() {
+            exit_with_error("--record can not be used with --flags");
+        }
+
+        let mut movie = Movie::new(&rom, quirks, SeededRng::from_time().next_u64());
+        let mut cpu = movie.start(&rom).unwrap_or_else(|err| exit_with_error(err));
+
+        options.movie = Some(&mut movie);
+        let result = run_tui(&mut cpu, options);
+
+        save_movie(&movie, &path, result);
+
+        return Ok(());
+    }
+
+    let mut cpu = CPU::new(quirks);
+
+    if let Some(path) = flags_path {
+        cpu.set_flag_storage(Box::new(FileFlagStorage::new(path)));
+    }
+
+    if let Err(err) = cpu.load_rom(&rom) {
+        exit_with_error(err);
+    }
+
+    options.rom_path = Some(Path::new(&path_to_rom));
+
+    // The terminal is already restored here
+    let result = run_tui(&mut cpu, options);
+    warn_unsaved_flags(&mut cpu);
+
+    if let Err(err) = result {
+        exit_with_error(err);
+    }
+
+    Ok(())
+}
+
+/**
+ * Runs a ROM without a display for a number of frames, with the keys of a
+ * script, and writes the last screen (`--png`) or every frame (`--gif`)
+ */
+fn headless_command(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut path_to_rom = None;
+    let mut quirks = Quirks::default();
+    let mut load_address = DEFAULT_LOAD_ADDRESS;
+    let mut frames = DEFAULT_HEADLESS_FRAMES;
+    let mut input = InputScript::default();
+    let mut png_path = None;
+    let mut gif_path = None;
+    let mut scale = DEFAULT_HEADLESS_SCALE;
+    let mut palette = Palette::default();
+    let mut seed = 0;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => quirks = parse_quirks(args.next()),
+            "--load-address" => load_address = parse_load_address(args.next()),
+            "--frames" => frames = parse_number("number of frames", args.next()),
+            "--seed" => seed = parse_number("seed", args.next()),
+            "--png" => png_path = args.next(),
+            "--gif" => gif_path = args.next(),
+            "--scale" => scale = parse_number("scale", args.next()),
+            "--palette" => {
+                let value = args.next().unwrap_or_default();
+
+                palette = Palette::parse(&value).unwrap_or_else(|| {
+                    exit_with_error(format!(
+                        "invalid palette <{value}> (2 or 4 colours like 000000,ffffff)"
+                    ))
+                });
+            }
+            "--input" => input = read_input_script(args.next()),
+            _ => path_to_rom = Some(arg),
+        }
+    }
+
+    if !(1..=64).contains(&scale) {
+        exit_with_error(format!("invalid scale <{scale}> (1 - 64)"));
+    }
+
+    let rom = read_rom(path_to_rom, load_address);
+    let mut cpu = CPU::with_seed(quirks, seed);
+
+    if let Err(err) = cpu.load_rom(&rom) {
+        exit_with_error(err);
+    }
+
+    let mut gif = GifRecording::new();
+    let record = gif_path.is_some();
+
+    let ran = run_frames(&mut cpu, frames, &input, |cpu| {
+        if record {
+            gif.push(cpu.framebuffer());
+        }
+    })
+    .unwrap_or_else(|err| exit_with_error(err));
+
+    println!("Ran {ran} frames");
+
+    if let Some(path) = png_path {
+        let file = fs::File::create(&path).unwrap_or_else(|err| exit_with_error(err));
+
+        write_png(cpu.framebuffer(), &palette, scale, io::BufWriter::new(file))?;
+    }
+
+    if let Some(path) = gif_path {
+        let file = fs::File::create(&path).unwrap_or_else(|err| exit_with_error(err));
+
+        gif.write(&palette, scale, io::BufWriter::new(file))?;
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1).peekable();
+
+    match args.peek().map(String::as_str) {
+        Some("disasm") => disasm_command(args.skip(1)),
+        Some("assemble") => assemble_command(args.skip(1)),
+        Some("octo") => octo_command(args.skip(1)),
+        Some("debug") => debug_command(args.skip(1)),
+        Some("tui") => tui_command(args.skip(1)),
+        Some("headless") => headless_command(args.skip(1)),
+        _ => run_command(args),
+    }
+}
@@ -1,12 +1,67 @@
 use std::vec;
 
-#[allow(dead_code)]
-const MAX_MEMORY_SIZE: usize = 4096;
-const MAX_STACK_SIZE: usize = 16;
+use crate::error::FaultKind;
+
+/// Memory of the CHIP-8 and SUPER-CHIP (4 KiB)
+pub const MEMORY_SIZE: usize = 4096;
+/// Memory of the XO-CHIP (64 KiB), the whole 16 bits address space
+pub const XO_CHIP_MEMORY_SIZE: usize = 0x10000;
+/// The stack keeps up to 16 return addresses
+pub const MAX_STACK_SIZE: usize = 16;
+
+/// Address where the hexadecimal font is loaded
+pub const FONT_ADDRESS: u16 = 0x50;
+/// Each font glyph is 5 bytes tall
+pub const FONT_GLYPH_SIZE: u16 = 5;
+
+/// Address where the SUPER-CHIP large hexadecimal font is loaded
+pub const BIG_FONT_ADDRESS: u16 = 0xA0;
+/// Each large font glyph is 10 bytes tall
+pub const BIG_FONT_GLYPH_SIZE: u16 = 10;
 
-/// Max size is 4096 bytes (4kb)
-/// That means that usize size in chip8
-/// is 12 bits, so 212 * 12 = 4096
+/// Built-in 4x5 hexadecimal font (0 - F)
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// Built-in 8x10 hexadecimal font (0 - F) of the SUPER-CHIP
+const BIG_FONT: [u8; 160] = [
+    0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+    0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+    0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+    0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+    0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+    0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+    0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+    0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    0x18, 0x3C, 0x66, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFE, 0xC3, 0xC3, 0xFE, 0xFE, 0xC3, 0xC3, 0xFE, 0xFC, // B
+    0x3C, 0x7E, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0x7E, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// The CHIP-8 memory is 4096 bytes (4kb), so the
+/// addresses of the instructions are 12 bits long.
+/// The XO-CHIP reaches 64 KiB through **LD I, NNNN**
 
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -21,14 +76,79 @@ impl Stack {
         }
     }
 
-    pub fn push(&mut self, address: u16, sp: u16) {
-        self.stack[sp as usize] = address;
+    pub fn push(&mut self, address: u16, sp: u16) -> Result<(), FaultKind> {
+        let entry = self
+            .stack
+            .get_mut(sp as usize)
+            .ok_or(FaultKind::StackOverflow)?;
+
+        *entry = address;
+
+        Ok(())
+    }
+
+    /**
+     * Creates a stack with the given entries, as returned by `entries`
+     */
+    pub fn from_entries(entries: [u16; MAX_STACK_SIZE]) -> Self {
+        Self {
+            stack: entries.to_vec(),
+        }
+    }
+
+    /**
+     * Returns every entry of the stack, used or not
+     */
+    pub fn entries(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn pop(&mut self, sp: u16) -> Result<u16, FaultKind> {
+        let entry = self
+            .stack
+            .get_mut(sp as usize)
+            .ok_or(FaultKind::StackUnderflow)?;
+
+        let addr = *entry;
+        *entry = 0x0;
+
+        Ok(addr)
+    }
+}
+
+impl Default for Stack {
+    fn default() -> Self {
+        Self::new()
     }
+}
+
+/// What the program did with the memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    /// The opcode of an instruction
+    Fetch,
+    Read,
+    Write,
+}
+
+/// A read or a write of `len` bytes, recorded while the access log is enabled
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: AccessKind,
+    pub address: u16,
+    pub len: usize,
+}
+
+impl MemoryAccess {
+    /**
+     * Returns true if any of the accessed bytes is between `start` and `end` (included)
+     */
+    pub fn overlaps(&self, start: u16, end: u16) -> bool {
+        let first = self.address as usize;
+        let last = first + self.len.max(1) - 1;
 
-    pub fn pop(&mut self, sp: u16) -> u16 {
-        let addr = self.stack[sp as usize];
-        self.stack[sp as usize] = 0x0;
-        addr
+        first <= end as usize && start as usize <= last
     }
 }
 
@@ -38,79 +158,306 @@ pub struct Memory {
     memory: Vec<u8>,
     pc: u16,
     pub read_pc: u16,
+    /// None unless a debugger asked for the accesses, so a normal run only pays a branch
+    accesses: Option<Vec<MemoryAccess>>,
 }
 
 #[allow(dead_code)]
 impl Memory {
     pub fn new() -> Self {
+        Self::with_size(MEMORY_SIZE)
+    }
+
+    /**
+     * Creates a memory of the given size, with the fonts preloaded
+     */
+    pub fn with_size(size: usize) -> Self {
+        let mut memory = vec![0x0; size];
+
+        let font_start = FONT_ADDRESS as usize;
+        memory[font_start..font_start + FONT.len()].copy_from_slice(&FONT);
+
+        let big_font_start = BIG_FONT_ADDRESS as usize;
+        memory[big_font_start..big_font_start + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
+
         Self {
-            memory: vec![0x0; MAX_MEMORY_SIZE],
+            memory,
             pc: 0x200,
             read_pc: 0x200,
+            accesses: None,
         }
     }
 
     /**
-     * Writes an 16bits opcode into memory
+     * Starts or stops recording every fetch, read and write
+     */
+    pub fn set_access_log(&mut self, enabled: bool) {
+        self.accesses = match enabled {
+            true => Some(self.accesses.take().unwrap_or_default()),
+            false => None,
+        };
+    }
+
+    pub fn is_logging_accesses(&self) -> bool {
+        self.accesses.is_some()
+    }
+
+    /**
+     * Returns the accesses recorded since the last call, emptying the log
      */
-    pub fn write(&mut self, data: u16) {
-        for i in data.to_be_bytes() {
-            self.memory[self.pc as usize] = i;
-            self.pc += 1;
+    pub fn take_accesses(&mut self) -> Vec<MemoryAccess> {
+        self.accesses
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    #[inline]
+    fn log_access(&mut self, kind: AccessKind, address: u16, len: usize) {
+        if let Some(accesses) = &mut self.accesses {
+            accesses.push(MemoryAccess { kind, address, len });
         }
     }
 
     /**
-     * Copy a ROM into memory
+     * Writes an 16bits opcode into memory, after the last one written
+     */
+    pub fn write(&mut self, data: u16) -> Result<(), FaultKind> {
+        self.write_bytes(self.pc, &data.to_be_bytes())?;
+        self.pc = self.pc.wrapping_add(2);
+
+        Ok(())
+    }
+
+    /**
+     * Copy a ROM into memory, starting from the given address
+     */
+    pub fn memcpy(&mut self, address: u16, mem: &[u8]) -> Result<(), FaultKind> {
+        self.write_bytes(address, mem)
+    }
+
+    /**
+     * Returns how many bytes the memory has
+     */
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    /**
+     * Returns the whole memory
      */
-    pub fn memcpy(&mut self, mem: &[u8]) {
-        self.memory[0x200..MAX_MEMORY_SIZE].clone_from_slice(mem);
+    pub fn bytes(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /**
+     * Replaces the whole memory, size included
+     */
+    pub fn set_bytes(&mut self, bytes: &[u8]) {
+        self.memory = bytes.to_vec();
+    }
+
+    /**
+     * Grows or shrinks the memory, keeping the bytes which still fit
+     */
+    pub fn resize(&mut self, size: usize) {
+        self.memory.resize(size, 0x0);
     }
 
     /**
      * Writes an 16 bits opcode on a given memory address
      */
-    pub fn write_into(&mut self, data: u16, address: u16) {
-        if address as usize > MAX_MEMORY_SIZE {
-            panic!("Address out of bound!");
-        }
+    pub fn write_into(&mut self, data: u16, address: u16) -> Result<(), FaultKind> {
+        self.write_bytes(address, &data.to_be_bytes())
+    }
 
-        let mut address_to_write = address;
+    /**
+     * Returns `size` bytes starting from the given address
+     */
+    pub fn read_bytes(&self, address: u16, size: usize) -> Result<&[u8], FaultKind> {
+        let start = address as usize;
 
-        for i in data.to_be_bytes() {
-            self.memory[address_to_write as usize] = i;
-            address_to_write += 1;
-        }
+        start
+            .checked_add(size)
+            .and_then(|end| self.memory.get(start..end))
+            .ok_or(FaultKind::MemoryOutOfRange(address))
+    }
+
+    /**
+     * Returns `size` bytes starting from the given address, like `read_bytes`,
+     * but the read is recorded on the access log. Used by the instructions
+     */
+    pub fn load_bytes(&mut self, address: u16, size: usize) -> Result<&[u8], FaultKind> {
+        self.log_access(AccessKind::Read, address, size);
+
+        self.read_bytes(address, size)
+    }
+
+    /**
+     * Writes the given bytes starting from the given address
+     */
+    pub fn write_bytes(&mut self, address: u16, data: &[u8]) -> Result<(), FaultKind> {
+        let start = address as usize;
+
+        self.log_access(AccessKind::Write, address, data.len());
+
+        start
+            .checked_add(data.len())
+            .and_then(|end| self.memory.get_mut(start..end))
+            .ok_or(FaultKind::MemoryOutOfRange(address))?
+            .copy_from_slice(data);
+
+        Ok(())
     }
 
     /**
      * Read x bytes from memory and returns it in a 16 bits format
      */
-    pub fn read(&mut self, size: u8) -> u16 {
+    pub fn read(&mut self, size: u8) -> Result<u16, FaultKind> {
+        if self.read_pc as usize + size as usize > self.memory.len() {
+            return Err(FaultKind::PcOutOfRange);
+        }
+
+        self.log_access(AccessKind::Fetch, self.read_pc, size as usize);
+
         let mut bytes_readed: [u8; 2] = [0, 0];
 
         for i in 0..size {
             bytes_readed[i as usize] = self.memory[self.read_pc as usize];
-            self.read_pc += 1;
+            self.read_pc = self.read_pc.wrapping_add(1);
         }
 
         let byte_1 = bytes_readed[0] as u16;
         let byte_2 = bytes_readed[1] as u16;
 
-        byte_1 << 8 | byte_2
+        Ok(byte_1 << 8 | byte_2)
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Memory;
+    use super::{AccessKind, Memory, MemoryAccess, Stack, XO_CHIP_MEMORY_SIZE};
+    use crate::error::FaultKind;
 
     #[test]
     fn test_write_into_memory() {
         let mut mem: Memory = Memory::new();
         // 0x0ff7 = 4087
-        mem.write(0x0ff7);
+        mem.write(0x0ff7).unwrap();
+
+        assert_eq!(mem.read(2), Ok(0x0ff7));
+    }
+
+    #[test]
+    fn test_write_into_out_of_bound() {
+        let mut mem: Memory = Memory::new();
+
+        assert_eq!(
+            mem.write_into(0x1234, 0xFFF),
+            Err(FaultKind::MemoryOutOfRange(0xFFF))
+        );
+        assert_eq!(
+            mem.write_into(0x1234, 0x1000),
+            Err(FaultKind::MemoryOutOfRange(0x1000))
+        );
+        assert_eq!(
+            mem.read_bytes(0x200, usize::MAX),
+            Err(FaultKind::MemoryOutOfRange(0x200))
+        );
+
+        mem.pc = 0xFFF;
+        assert_eq!(mem.write(0x1234), Err(FaultKind::MemoryOutOfRange(0xFFF)));
+    }
+
+    #[test]
+    fn test_read_pc_out_of_bound() {
+        let mut mem: Memory = Memory::new();
+
+        mem.read_pc = 0xFFF;
+
+        assert_eq!(mem.read(2), Err(FaultKind::PcOutOfRange));
+    }
+
+    #[test]
+    fn test_xo_chip_memory() {
+        let mut mem = Memory::with_size(XO_CHIP_MEMORY_SIZE);
+
+        assert_eq!(mem.write_into(0x1234, 0xFFFE), Ok(()));
+
+        mem.read_pc = 0xFFFE;
+
+        assert_eq!(mem.read(2), Ok(0x1234));
+        assert_eq!(mem.read_pc, 0x0);
+    }
+
+    #[test]
+    fn test_access_log() {
+        let mut mem = Memory::new();
+
+        mem.write_into(0x1234, 0x300).unwrap();
+        assert_eq!(mem.take_accesses(), vec![]);
+
+        mem.set_access_log(true);
+        mem.write_into(0x1234, 0x300).unwrap();
+        mem.load_bytes(0x301, 1).unwrap();
+        mem.read_bytes(0x300, 2).unwrap();
+        mem.read(2).unwrap();
+
+        let accesses = mem.take_accesses();
+
+        assert_eq!(
+            accesses,
+            vec![
+                MemoryAccess {
+                    kind: AccessKind::Write,
+                    address: 0x300,
+                    len: 2
+                },
+                MemoryAccess {
+                    kind: AccessKind::Read,
+                    address: 0x301,
+                    len: 1
+                },
+                MemoryAccess {
+                    kind: AccessKind::Fetch,
+                    address: 0x200,
+                    len: 2
+                },
+            ]
+        );
+        assert!(accesses[0].overlaps(0x301, 0x310));
+        assert!(!accesses[0].overlaps(0x302, 0x310));
+        assert_eq!(mem.take_accesses(), vec![]);
+
+        mem.set_access_log(false);
+        mem.load_bytes(0x300, 2).unwrap();
+        assert!(!mem.is_logging_accesses());
+        assert_eq!(mem.take_accesses(), vec![]);
+    }
+
+    #[test]
+    fn test_stack_overflow_and_underflow() {
+        let mut stack = Stack::new();
+
+        assert_eq!(stack.push(0x200, 15), Ok(()));
+        assert_eq!(stack.push(0x200, 16), Err(FaultKind::StackOverflow));
+        assert_eq!(stack.pop(16), Err(FaultKind::StackUnderflow));
+    }
+
+    #[test]
+    fn test_font_is_preloaded() {
+        let mem: Memory = Memory::new();
 
-        assert_eq!(mem.read(2), 0x0ff7);
+        // Glyph of the digit 0
+        assert_eq!(
+            mem.read_bytes(super::FONT_ADDRESS, 5),
+            Ok(&[0xF0, 0x90, 0x90, 0x90, 0xF0][..])
+        );
     }
 }
@@ -0,0 +1,402 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::{
+    cpu::{CORE_VERSION, CPU, INSTRUCTIONS_PER_TICK},
+    error::EmulatorError,
+    quirks::Quirks,
+    rom::Rom,
+};
+
+/// The first bytes of every movie file
+pub const MOVIE_MAGIC: [u8; 4] = *b"C8MV";
+/// Version of the format `to_bytes` writes. A movie only replays on the
+/// emulator it was recorded with, so the older versions are not read
+pub const MOVIE_VERSION: u16 = 2;
+/// Longest movie read, 4 hours at 60 frames per second. The frames are
+/// stored as runs, so a few bytes could ask for gigabytes otherwise
+pub const MAX_MOVIE_FRAMES: usize = 4 * 60 * 60 * 60;
+
+#[derive(Debug)]
+pub enum MovieError {
+    Io(io::Error),
+    /// The bytes do not start with [`MOVIE_MAGIC`]
+    NotAMovie,
+    /// Written by another version of the emulator
+    UnsupportedVersion(u16),
+    /// The bytes end before the whole movie is read
+    Truncated,
+    /// A value no movie can have
+    Invalid(&'static str),
+    /// The movie was recorded on another ROM
+    RomMismatch {
+        recorded: u64,
+        found: u64,
+    },
+    /// The movie was recorded on another [`CORE_VERSION`]
+    EmulatorMismatch {
+        recorded: u32,
+    },
+    /// The ROM does not load with the recorded quirks
+    Rom(String),
+    /// The CPU faulted while replaying the movie
+    Emulator(EmulatorError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Io(err) => write!(f, "{err}"),
+            MovieError::NotAMovie => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => write!(
+                f,
+                "movie version {version} is not supported (this one is {MOVIE_VERSION})"
+            ),
+            MovieError::Truncated => write!(f, "the movie is truncated"),
+            MovieError::Invalid(what) => write!(f, "invalid movie: {what}"),
+            MovieError::RomMismatch { recorded, found } => write!(
+                f,
+                "the movie was recorded on another ROM (hash {recorded:016x}, this one is {found:016x})"
+            ),
+            MovieError::EmulatorMismatch { recorded } => write!(
+                f,
+                "the movie was recorded on version {recorded} of the emulator core (this one is {CORE_VERSION})"
+            ),
+            MovieError::Rom(err) => write!(f, "{err}"),
+            MovieError::Emulator(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl From<io::Error> for MovieError {
+    fn from(err: io::Error) -> Self {
+        MovieError::Io(err)
+    }
+}
+
+impl From<EmulatorError> for MovieError {
+    fn from(err: EmulatorError) -> Self {
+        MovieError::Emulator(err)
+    }
+}
+
+/// Reads the values in little endian
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], MovieError> {
+        if self.bytes.len() < size {
+            return Err(MovieError::Truncated);
+        }
+
+        let (taken, rest) = self.bytes.split_at(size);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, MovieError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, MovieError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, MovieError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// A recording of a run: how the machine was started and the keypad of
+/// every frame. Replaying it on the same ROM and the same [`CORE_VERSION`]
+/// runs every frame exactly as it was recorded, which is what a bug
+/// report needs.
+///
+/// The movie starts from a fresh machine, so it can not start from a save
+/// state, and the random generator is seeded from [`Movie::seed`]
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub core_version: u32,
+    /// [`Rom::hash`] of the recorded ROM
+    pub rom_hash: u64,
+    pub load_address: u16,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub instructions_per_tick: u32,
+    /// The keypad of each frame, a bit per key (see [`CPU::set_keys`])
+    pub frames: Vec<u16>,
+}
+
+impl Movie {
+    /**
+     * Starts an empty movie of the ROM
+     */
+    pub fn new(rom: &Rom, quirks: Quirks, seed: u64) -> Self {
+        Self {
+            core_version: CORE_VERSION,
+            rom_hash: rom.hash(),
+            load_address: rom.load_address(),
+            quirks,
+            seed,
+            instructions_per_tick: INSTRUCTIONS_PER_TICK,
+            frames: Vec::new(),
+        }
+    }
+
+    /**
+     * Fails if the movie was not recorded on this ROM and this version of
+     * the emulator core
+     */
+    pub fn check(&self, rom: &Rom) -> Result<(), MovieError> {
+        if self.core_version != CORE_VERSION {
+            return Err(MovieError::EmulatorMismatch {
+                recorded: self.core_version,
+            });
+        }
+
+        if self.rom_hash != rom.hash() {
+            return Err(MovieError::RomMismatch {
+                recorded: self.rom_hash,
+                found: rom.hash(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Returns the machine the movie starts on, with the ROM loaded. The ROM
+     * is loaded on the recorded address, whatever the one of `rom` is
+     */
+    pub fn start(&self, rom: &Rom) -> Result<CPU, MovieError> {
+        self.check(rom)?;
+
+        let rom = Rom::from_bytes(rom.data().to_vec(), self.load_address)
+            .map_err(|err| MovieError::Rom(err.to_string()))?;
+        let mut cpu = CPU::with_seed(self.quirks, self.seed);
+
+        cpu.set_instructions_per_tick(self.instructions_per_tick);
+        cpu.load_rom(&rom)
+            .map_err(|err| MovieError::Rom(err.to_string()))?;
+
+        Ok(cpu)
+    }
+
+    /**
+     * Records the keys the frontend has set and runs the frame
+     * (`CPU::run_frame`). The CPU must come from [`Movie::start`]
+     */
+    pub fn record_frame(&mut self, cpu: &mut CPU) -> Result<bool, EmulatorError> {
+        self.frames.push(cpu.keypad().bits());
+
+        cpu.run_frame()
+    }
+
+    /**
+     * Runs the whole movie, and returns the machine as it was when the
+     * recording stopped
+     */
+    pub fn replay(&self, rom: &Rom) -> Result<CPU, MovieError> {
+        let mut cpu = self.start(rom)?;
+
+        for keys in &self.frames {
+            cpu.set_keys(*keys);
+            cpu.run_frame()?;
+        }
+
+        Ok(cpu)
+    }
+
+    /**
+     * Encodes the movie: the magic, the version (u16), the core version (u32),
+     * how the machine starts and then the frames, as runs of
+     * `count (u32), keys (u16)`, as the keys rarely change every frame
+     */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MOVIE_MAGIC.to_vec();
+
+        bytes.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.core_version.to_le_bytes());
+        bytes.extend_from_slice(&self.rom_hash.to_le_bytes());
+        bytes.extend_from_slice(&self.load_address.to_le_bytes());
+        bytes.extend_from_slice(&self.quirks.to_bits().to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.instructions_per_tick.to_le_bytes());
+
+        let mut runs: Vec<(u32, u16)> = Vec::new();
+        for keys in &self.frames {
+            match runs.last_mut() {
+                Some((count, last)) if last == keys => *count += 1,
+                _ => runs.push((1, *keys)),
+            }
+        }
+
+        bytes.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+        for (count, keys) in runs {
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.extend_from_slice(&keys.to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        let mut reader = Reader { bytes };
+
+        if reader.take(MOVIE_MAGIC.len()).ok() != Some(&MOVIE_MAGIC[..]) {
+            return Err(MovieError::NotAMovie);
+        }
+
+        let version = reader.u16()?;
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let core_version = reader.u32()?;
+        let rom_hash = reader.u64()?;
+        let load_address = reader.u16()?;
+        let quirks = Quirks::from_bits(reader.u16()?);
+        let seed = reader.u64()?;
+        let instructions_per_tick = reader.u32()?;
+
+        let mut frames = Vec::new();
+        for _ in 0..reader.u32()? {
+            let count = reader.u32()? as usize;
+            let keys = reader.u16()?;
+
+            frames
+                .len()
+                .checked_add(count)
+                .filter(|total| *total <= MAX_MOVIE_FRAMES)
+                .ok_or(MovieError::Invalid("too many frames"))?;
+            frames.extend(std::iter::repeat_n(keys, count));
+        }
+
+        if !reader.bytes.is_empty() {
+            return Err(MovieError::Invalid("trailing bytes after the frames"));
+        }
+
+        Ok(Self {
+            core_version,
+            rom_hash,
+            load_address,
+            quirks,
+            seed,
+            instructions_per_tick,
+            frames,
+        })
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), MovieError> {
+        fs::write(path, self.to_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, MovieError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Movie, MovieError};
+    use crate::{
+        asm::Assembler,
+        quirks::Quirks,
+        rom::{Rom, DEFAULT_LOAD_ADDRESS},
+    };
+
+    fn rom() -> Rom {
+        let program = Assembler::new()
+            .assemble(
+                "
+                    LD V2, 7
+                loop:
+                    RND V0, 0x3F
+                    SKNP V2
+                    ADD V3, V0
+                    LD F, V3
+                    DRW V0, V3, 5
+                    JP loop
+                ",
+            )
+            .unwrap();
+
+        Rom::from_bytes(program, DEFAULT_LOAD_ADDRESS).unwrap()
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let rom = rom();
+        let mut movie = Movie::new(&rom, Quirks::cosmac_vip(), 42);
+        let mut cpu = movie.start(&rom).unwrap();
+
+        for frame in 0..200 {
+            cpu.set_keys(if frame % 50 < 20 { 1 << 7 } else { 0 });
+            movie.record_frame(&mut cpu).unwrap();
+        }
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(movie.frames.len(), 200);
+        assert_eq!(movie.quirks, Quirks::cosmac_vip());
+
+        let replayed = movie.replay(&rom).unwrap();
+        assert_eq!(replayed.save_state(), cpu.save_state());
+
+        // Another seed is another run
+        let other = Movie { seed: 43, ..movie };
+        assert_ne!(other.replay(&rom).unwrap().save_state(), cpu.save_state());
+    }
+
+    #[test]
+    fn test_rejected_replays() {
+        let rom = rom();
+        let movie = Movie::new(&rom, Quirks::default(), 1);
+
+        let mut data = rom.data().to_vec();
+        data[1] ^= 1;
+        let other_rom = Rom::from_bytes(data, DEFAULT_LOAD_ADDRESS).unwrap();
+        assert!(matches!(
+            movie.replay(&other_rom),
+            Err(MovieError::RomMismatch { .. })
+        ));
+
+        let old = Movie {
+            core_version: 0,
+            ..movie.clone()
+        };
+        assert!(matches!(
+            old.replay(&rom),
+            Err(MovieError::EmulatorMismatch { .. })
+        ));
+
+        let bytes = movie.to_bytes();
+        assert!(matches!(
+            Movie::from_bytes(b"C8SS"),
+            Err(MovieError::NotAMovie)
+        ));
+        assert!(matches!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(MovieError::Truncated)
+        ));
+
+        // Two runs of 3 billion frames, in 12 bytes
+        let mut huge = bytes.clone();
+        huge.truncate(huge.len() - 4);
+        huge.extend_from_slice(&2u32.to_le_bytes());
+        for _ in 0..2 {
+            huge.extend_from_slice(&3_000_000_000u32.to_le_bytes());
+            huge.extend_from_slice(&0u16.to_le_bytes());
+        }
+        assert!(matches!(
+            Movie::from_bytes(&huge),
+            Err(MovieError::Invalid("too many frames"))
+        ));
+    }
+}
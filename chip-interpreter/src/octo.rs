@@ -0,0 +1,1790 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    f64::consts,
+    fmt, fs,
+    path::Path,
+};
+
+use crate::{
+    asm::{AssemblyError, AssemblyErrorKind, INPUT_NAME},
+    instruction::Instruction,
+    memory::{MEMORY_SIZE, XO_CHIP_MEMORY_SIZE},
+    rom::DEFAULT_LOAD_ADDRESS,
+};
+
+/// Stops a macro which expands itself forever
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+/// Instructions a program is allowed to use. Each set includes the previous one
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InstructionSet {
+    Chip8,
+    SuperChip,
+    #[default]
+    XoChip,
+}
+
+impl InstructionSet {
+    /**
+     * Returns the set with the given name (chip8, schip or xochip)
+     */
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(InstructionSet::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(InstructionSet::SuperChip),
+            "xochip" | "xo-chip" => Some(InstructionSet::XoChip),
+            _ => None,
+        }
+    }
+
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        match self {
+            InstructionSet::Chip8 => !instruction.is_super_chip() && !instruction.is_xo_chip(),
+            InstructionSet::SuperChip => !instruction.is_xo_chip(),
+            InstructionSet::XoChip => true,
+        }
+    }
+
+    /**
+     * Bytes of memory of the machines which run this set
+     */
+    pub fn memory_size(&self) -> usize {
+        match self {
+            InstructionSet::XoChip => XO_CHIP_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
+        }
+    }
+
+    /**
+     * Returns the smallest set which has the instruction
+     */
+    fn required_by(instruction: &Instruction) -> Self {
+        if instruction.is_xo_chip() {
+            InstructionSet::XoChip
+        } else if instruction.is_super_chip() {
+            InstructionSet::SuperChip
+        } else {
+            InstructionSet::Chip8
+        }
+    }
+}
+
+impl fmt::Display for InstructionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstructionSet::Chip8 => write!(f, "CHIP-8"),
+            InstructionSet::SuperChip => write!(f, "SUPER-CHIP"),
+            InstructionSet::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+/// Names of a compiled program, for the debugging tools. Its `Display`
+/// writes one symbol per line, the labels sorted by address:
+///
+/// ```text
+/// label main 0x0200
+/// breakpoint collision 0x0214
+/// const SPEED 3
+/// ```
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    labels: BTreeMap<String, u16>,
+    breakpoints: BTreeMap<u16, String>,
+    constants: BTreeMap<String, i64>,
+}
+
+impl SymbolTable {
+    pub fn labels(&self) -> &BTreeMap<String, u16> {
+        &self.labels
+    }
+
+    /**
+     * Returns the `:breakpoint` names by address
+     */
+    pub fn breakpoints(&self) -> &BTreeMap<u16, String> {
+        &self.breakpoints
+    }
+
+    /**
+     * Returns the `:const` and `:calc` values, rounded down
+     */
+    pub fn constants(&self) -> &BTreeMap<String, i64> {
+        &self.constants
+    }
+
+    /**
+     * Reads a table written by its `Display`. Returns None if a line is malformed
+     */
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut table = Self::default();
+
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let [kind, name, value] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+                return None;
+            };
+            let value = parse_number(value)? as i64;
+
+            match kind {
+                "label" => {
+                    table
+                        .labels
+                        .insert(name.to_string(), u16::try_from(value).ok()?);
+                }
+                "breakpoint" => {
+                    table
+                        .breakpoints
+                        .insert(u16::try_from(value).ok()?, name.to_string());
+                }
+                "const" => {
+                    table.constants.insert(name.to_string(), value);
+                }
+                _ => return None,
+            }
+        }
+
+        Some(table)
+    }
+
+    /**
+     * Returns the first label (by name) on the address, if any
+     */
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(_, label)| **label == address)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+impl fmt::Display for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort_by_key(|(name, address)| (**address, *name));
+
+        for (name, address) in labels {
+            writeln!(f, "label {name} {address:#06X}")?;
+        }
+
+        for (address, name) in &self.breakpoints {
+            writeln!(f, "breakpoint {name} {address:#06X}")?;
+        }
+
+        for (name, value) in &self.constants {
+            writeln!(f, "const {name} {value}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Bytes of a compiled program, to be loaded on 0x200, and its symbols
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledProgram {
+    pub bytes: Vec<u8>,
+    pub symbols: SymbolTable,
+}
+
+/// Octo separates the tokens with whitespace only, so `:=` or `{` are tokens too
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    /// Written between quotes
+    is_string: bool,
+}
+
+/**
+ * Splits the source into tokens, skipping the `#` comments
+ */
+fn tokenize(source: &str, file: &str) -> Result<Vec<Token>, AssemblyError> {
+    let mut tokens = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let mut chars = line.char_indices().peekable();
+
+        while let Some((start, c)) = chars.next() {
+            let column = start + 1;
+
+            if c.is_whitespace() {
+                continue;
+            }
+
+            if c == '#' {
+                break;
+            }
+
+            if c == '"' {
+                let mut text = String::new();
+                let mut closed = false;
+
+                for (_, c) in chars.by_ref() {
+                    if c == '"' {
+                        closed = true;
+                        break;
+                    }
+                    text.push(c);
+                }
+
+                if !closed {
+                    return Err(AssemblyError {
+                        file: file.to_string(),
+                        line: index + 1,
+                        column,
+                        kind: AssemblyErrorKind::UnterminatedString,
+                    });
+                }
+
+                tokens.push(Token {
+                    text,
+                    line: index + 1,
+                    column,
+                    is_string: true,
+                });
+                continue;
+            }
+
+            let mut text = c.to_string();
+
+            while let Some(&(_, c)) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                text.push(c);
+                chars.next();
+            }
+
+            tokens.push(Token {
+                text,
+                line: index + 1,
+                column,
+                is_string: false,
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+/**
+ * Shifts the integer part of `a` by `b` bits. None if `b` is not in 0 - 63
+ */
+fn shift(a: f64, b: f64, operation: fn(i64, u32) -> Option<i64>) -> Option<f64> {
+    let bits = u32::try_from(b as i64).ok()?;
+
+    operation(a as i64, bits).map(|value| value as f64)
+}
+
+/**
+ * Parses a decimal, 0x hexadecimal or 0b binary number, which may be negative
+ */
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/**
+ * Parses v0 - vF
+ */
+fn parse_register(text: &str) -> Option<u8> {
+    let index = text.strip_prefix(['v', 'V'])?;
+
+    match index.len() {
+        1 => u8::from_str_radix(index, 16).ok(),
+        _ => None,
+    }
+}
+
+/**
+ * Label, constant and macro names start with a letter or `_`
+ */
+fn is_identifier(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_alphabetic() || c == '_') && parse_register(text).is_none()
+}
+
+/// `v0 := hi` and `v1 := lo` of `:unpack`
+fn unpack(nibble: Option<u8>, address: u16) -> (u8, u8) {
+    (
+        (nibble.unwrap_or(0) << 4) | (address >> 8) as u8,
+        address as u8,
+    )
+}
+
+/// Where the address of a label goes, when it is used before being defined
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FixupKind {
+    /// The NNN of `jump`, `call`, `i :=` ...
+    Address,
+    /// The word after `i := long`
+    Long,
+    /// The word of `:pointer`
+    Pointer,
+    /// The bytes of the two `vx := NN` of `:unpack`
+    Unpack(Option<u8>),
+}
+
+impl FixupKind {
+    fn max(&self) -> u32 {
+        match self {
+            FixupKind::Address | FixupKind::Unpack(Some(_)) => 0xFFF,
+            _ => 0xFFFF,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Fixup {
+    address: u32,
+    kind: FixupKind,
+    label: Token,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// A block which is waiting for its closing keyword
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Block {
+    /// `if … begin`, with the jump to patch on `else` or `end`
+    If {
+        token: Token,
+        jump: u32,
+        has_else: bool,
+    },
+    /// `loop`, with the jumps of its `while`s, patched on `again`
+    Loop {
+        token: Token,
+        start: u32,
+        breaks: Vec<u32>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessOrEqual,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn from_operator(operator: &str) -> Option<Self> {
+        match operator {
+            "==" => Some(Comparison::Equal),
+            "!=" => Some(Comparison::NotEqual),
+            "<" => Some(Comparison::Less),
+            ">" => Some(Comparison::Greater),
+            "<=" => Some(Comparison::LessOrEqual),
+            ">=" => Some(Comparison::GreaterOrEqual),
+            _ => None,
+        }
+    }
+
+    fn negate(self) -> Self {
+        match self {
+            Comparison::Equal => Comparison::NotEqual,
+            Comparison::NotEqual => Comparison::Equal,
+            Comparison::Less => Comparison::GreaterOrEqual,
+            Comparison::Greater => Comparison::LessOrEqual,
+            Comparison::LessOrEqual => Comparison::Greater,
+            Comparison::GreaterOrEqual => Comparison::Less,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+/// The condition of `if` and `while`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Condition {
+    /// `vx key` or `vx -key`
+    Key { x: u8, pressed: bool },
+    Compare {
+        x: u8,
+        comparison: Comparison,
+        operand: Operand,
+    },
+}
+
+impl Condition {
+    fn negate(self) -> Self {
+        match self {
+            Condition::Key { x, pressed } => Condition::Key {
+                x,
+                pressed: !pressed,
+            },
+            Condition::Compare {
+                x,
+                comparison,
+                operand,
+            } => Condition::Compare {
+                x,
+                comparison: comparison.negate(),
+                operand,
+            },
+        }
+    }
+}
+
+type Compiled<T> = Result<T, AssemblyError>;
+
+/// Compiles [Octo](https://johnearnest.github.io/Octo/docs/Manual.html) into
+/// bytes to load on 0x200.
+///
+/// ```text
+/// :const SPEED 2
+/// :alias x v1
+///
+/// : main
+///     loop
+///         x += SPEED
+///         if x > 60 then x := 0
+///         i := ball
+///         sprite x v2 1
+///     again
+///
+/// : ball 0x80
+/// ```
+///
+/// Octo jumps to the `main` label from 0x200, unless `main` is the first
+/// thing of the program. The numbers in place of a statement are bytes, and
+/// the names which are not anything else are calls to labels, which can be
+/// defined later. `:calc` expressions are evaluated from right to left,
+/// without precedence between the operators.
+///
+/// `:stringmode` is not supported
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OctoCompiler {
+    instruction_set: InstructionSet,
+    file: String,
+    tokens: VecDeque<Token>,
+    rom: Vec<u8>,
+    written: Vec<bool>,
+    here: u32,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    breakpoints: BTreeMap<u16, String>,
+    blocks: Vec<Block>,
+    jumps_to_main: bool,
+    expansions: usize,
+}
+
+impl OctoCompiler {
+    pub fn new(instruction_set: InstructionSet) -> Self {
+        Self {
+            instruction_set,
+            file: INPUT_NAME.to_string(),
+            tokens: VecDeque::new(),
+            rom: Vec::new(),
+            written: Vec::new(),
+            here: DEFAULT_LOAD_ADDRESS as u32,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            breakpoints: BTreeMap::new(),
+            blocks: Vec::new(),
+            jumps_to_main: true,
+            expansions: 0,
+        }
+    }
+
+    pub fn compile(self, source: &str) -> Result<CompiledProgram, AssemblyError> {
+        self.compile_source(source, INPUT_NAME)
+    }
+
+    pub fn compile_file<P: AsRef<Path>>(self, path: P) -> Result<CompiledProgram, AssemblyError> {
+        let path = path.as_ref();
+        let name = path.display().to_string();
+
+        let source = fs::read_to_string(path).map_err(|err| AssemblyError {
+            file: name.clone(),
+            line: 0,
+            column: 0,
+            kind: AssemblyErrorKind::Include(err.to_string()),
+        })?;
+
+        self.compile_source(&source, &name)
+    }
+
+    fn compile_source(
+        mut self,
+        source: &str,
+        file: &str,
+    ) -> Result<CompiledProgram, AssemblyError> {
+        self.file = file.to_string();
+        self.tokens = tokenize(source, file)?.into();
+
+        // Room for `jump main`
+        self.rom = vec![0x12, 0x00];
+        self.written = vec![true; 2];
+        self.here += 2;
+
+        while let Some(token) = self.tokens.pop_front() {
+            self.statement(token)?;
+        }
+
+        self.finish()
+    }
+
+    fn error(&self, token: &Token, kind: AssemblyErrorKind) -> AssemblyError {
+        AssemblyError {
+            file: self.file.clone(),
+            line: token.line,
+            column: token.column,
+            kind,
+        }
+    }
+
+    fn syntax_error(&self, token: &Token, message: &str) -> AssemblyError {
+        self.error(token, AssemblyErrorKind::Syntax(message.to_string()))
+    }
+
+    /**
+     * Takes the next token. `after` locates the error when there are no more
+     */
+    fn expect(&mut self, after: &Token) -> Compiled<Token> {
+        self.tokens.pop_front().ok_or_else(|| {
+            self.syntax_error(after, &format!("unexpected end after <{}>", after.text))
+        })
+    }
+
+    /**
+     * Returns the register of a `vx` token or of an `:alias`
+     */
+    fn register(&self, token: &Token) -> Option<u8> {
+        if token.is_string {
+            return None;
+        }
+
+        self.aliases
+            .get(&token.text)
+            .copied()
+            .or_else(|| parse_register(&token.text))
+    }
+
+    fn expect_register(&mut self, after: &Token) -> Compiled<u8> {
+        let token = self.expect(after)?;
+
+        self.register(&token).ok_or_else(|| {
+            self.syntax_error(
+                &token,
+                &format!("expected a register, found <{}>", token.text),
+            )
+        })
+    }
+
+    fn statement(&mut self, token: Token) -> Compiled<()> {
+        if token.is_string {
+            return Err(self.syntax_error(&token, "unexpected string"));
+        }
+
+        if let Some(x) = self.register(&token) {
+            return self.register_statement(token, x);
+        }
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.expect(&token)?;
+                self.define_label(&name, self.here)
+            }
+            ":const" => {
+                let name = self.expect(&token)?;
+                let value = self.expect(&name)?;
+                let value = self.value(&value)?;
+                self.define_constant(&name, value, false)
+            }
+            ":calc" => {
+                let name = self.expect(&token)?;
+                let open = self.expect(&name)?;
+                let value = self.calc(&open)?;
+                self.define_constant(&name, value, true)
+            }
+            ":alias" => {
+                let name = self.expect(&token)?;
+                let register = self.expect(&name)?;
+                let x = match self.register(&register) {
+                    Some(x) => x,
+                    None => self.nibble(&register)?,
+                };
+
+                if !is_identifier(&name.text) {
+                    return Err(self.syntax_error(&name, "invalid alias name"));
+                }
+
+                self.aliases.insert(name.text, x);
+                Ok(())
+            }
+            ":byte" => {
+                let value = self.expect(&token)?;
+                let byte = self.byte(&value)?;
+                self.emit(&token, &[byte])
+            }
+            ":pointer" => {
+                let label = self.expect(&token)?;
+                let address = self.target(label, FixupKind::Pointer)?;
+                self.emit(&token, &address.to_be_bytes())
+            }
+            ":org" => {
+                let value = self.expect(&token)?;
+                let address = self.value(&value)?;
+                let address = self.in_range(&value, address, 0xFFFF)?;
+
+                if address < DEFAULT_LOAD_ADDRESS as u32 {
+                    return Err(self.error(
+                        &value,
+                        AssemblyErrorKind::OrgBeforeLoadAddress(address as u16),
+                    ));
+                }
+
+                self.here = address;
+                Ok(())
+            }
+            ":unpack" => {
+                let high = self.expect(&token)?;
+                let nibble = match high.text.as_str() {
+                    "long" => None,
+                    _ => Some(self.nibble(&high)?),
+                };
+                let label = self.expect(&high)?;
+                let (v0, v1) = unpack(nibble, self.target(label, FixupKind::Unpack(nibble))?);
+
+                self.instruction(&token, Instruction::LdByte(0x0, v0))?;
+                self.instruction(&token, Instruction::LdByte(0x1, v1))
+            }
+            ":next" => {
+                // The label points to the second byte of the next instruction
+                let name = self.expect(&token)?;
+                self.define_label(&name, self.here + 1)
+            }
+            ":breakpoint" => {
+                let name = self.expect(&token)?;
+                self.breakpoints.insert(self.here as u16, name.text);
+                Ok(())
+            }
+            ":monitor" => {
+                // Only useful on the Octo IDE
+                let address = self.expect(&token)?;
+                self.expect(&address)?;
+                Ok(())
+            }
+            ":assert" => {
+                let mut open = self.expect(&token)?;
+                let mut message = String::from("the expression is zero");
+
+                if open.is_string {
+                    message = open.text.clone();
+                    open = self.expect(&open)?;
+                }
+
+                if self.calc(&open)? == 0.0 {
+                    return Err(self.error(&token, AssemblyErrorKind::AssertionFailed(message)));
+                }
+
+                Ok(())
+            }
+            ":call" => {
+                let label = self.expect(&token)?;
+                let address = self.target(label, FixupKind::Address)?;
+                self.instruction(&token, Instruction::Call(address))
+            }
+            ":macro" => self.define_macro(&token),
+            ":proto" => {
+                // Forward declarations are not needed
+                self.expect(&token)?;
+                Ok(())
+            }
+            "clear" => self.instruction(&token, Instruction::Cls),
+            "return" | ";" => self.instruction(&token, Instruction::Ret),
+            "exit" => self.instruction(&token, Instruction::Exit),
+            "hires" => self.instruction(&token, Instruction::High),
+            "lores" => self.instruction(&token, Instruction::Low),
+            "scroll-right" => self.instruction(&token, Instruction::ScrollRight),
+            "scroll-left" => self.instruction(&token, Instruction::ScrollLeft),
+            "audio" => self.instruction(&token, Instruction::Audio),
+            "scroll-down" | "scroll-up" | "plane" => {
+                let value = self.expect(&token)?;
+                let n = self.nibble(&value)?;
+
+                let instruction = match token.text.as_str() {
+                    "scroll-down" => Instruction::ScrollDown(n),
+                    "scroll-up" => Instruction::ScrollUp(n),
+                    _ => Instruction::Plane(n),
+                };
+
+                self.instruction(&token, instruction)
+            }
+            "bcd" | "saveflags" | "loadflags" => {
+                let x = self.expect_register(&token)?;
+
+                let instruction = match token.text.as_str() {
+                    "bcd" => Instruction::LdBcd(x),
+                    "saveflags" => Instruction::StoreFlags(x),
+                    _ => Instruction::LoadFlags(x),
+                };
+
+                self.instruction(&token, instruction)
+            }
+            "save" | "load" => {
+                let x = self.expect_register(&token)?;
+                let is_range = self.tokens.front().is_some_and(|next| next.text == "-");
+                let save = token.text == "save";
+
+                let instruction = if is_range {
+                    let dash = self.expect(&token)?;
+                    let y = self.expect_register(&dash)?;
+
+                    match save {
+                        true => Instruction::SaveRange(x, y),
+                        false => Instruction::LoadRange(x, y),
+                    }
+                } else {
+                    match save {
+                        true => Instruction::StoreRegisters(x),
+                        false => Instruction::LoadRegisters(x),
+                    }
+                };
+
+                self.instruction(&token, instruction)
+            }
+            "sprite" => {
+                let x = self.expect_register(&token)?;
+                let y = self.expect_register(&token)?;
+                let height = self.expect(&token)?;
+                let n = self.nibble(&height)?;
+
+                self.instruction(&token, Instruction::Drw(x, y, n))
+            }
+            "jump" | "jump0" => {
+                let label = self.expect(&token)?;
+                let address = self.target(label, FixupKind::Address)?;
+
+                let instruction = match token.text.as_str() {
+                    "jump" => Instruction::Jp(address),
+                    _ => Instruction::JpV0(address),
+                };
+
+                self.instruction(&token, instruction)
+            }
+            "native" => {
+                // 0NNN, a machine language routine of the original interpreter
+                let label = self.expect(&token)?;
+                let address = self.target(label, FixupKind::Address)?;
+                self.emit(&token, &address.to_be_bytes())
+            }
+            "delay" | "buzzer" | "pitch" => {
+                let operator = self.expect(&token)?;
+
+                if operator.text != ":=" {
+                    return Err(self.error(
+                        &operator,
+                        AssemblyErrorKind::InvalidOperands(token.text.clone()),
+                    ));
+                }
+
+                let x = self.expect_register(&operator)?;
+
+                let instruction = match token.text.as_str() {
+                    "delay" => Instruction::LdDelayTimer(x),
+                    "buzzer" => Instruction::LdSoundTimer(x),
+                    _ => Instruction::Pitch(x),
+                };
+
+                self.instruction(&token, instruction)
+            }
+            "i" => self.i_statement(token),
+            "if" => {
+                let condition = self.condition(&token)?;
+                let keyword = self.expect(&token)?;
+
+                match keyword.text.as_str() {
+                    "then" => self.skip_unless(&keyword, condition),
+                    "begin" => {
+                        self.skip_unless(&keyword, condition.negate())?;
+
+                        let jump = self.here;
+                        self.instruction(&keyword, Instruction::Jp(0))?;
+                        self.blocks.push(Block::If {
+                            token,
+                            jump,
+                            has_else: false,
+                        });
+                        Ok(())
+                    }
+                    _ => Err(self.syntax_error(&keyword, "expected `then` or `begin`")),
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If {
+                    token: begin,
+                    jump,
+                    has_else: false,
+                }) => {
+                    let end_jump = self.here;
+                    self.instruction(&token, Instruction::Jp(0))?;
+                    self.patch_jump(&token, jump)?;
+
+                    self.blocks.push(Block::If {
+                        token: begin,
+                        jump: end_jump,
+                        has_else: true,
+                    });
+                    Ok(())
+                }
+                _ => Err(self.syntax_error(&token, "`else` without `if … begin`")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) => self.patch_jump(&token, jump),
+                _ => Err(self.syntax_error(&token, "`end` without `if … begin`")),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop {
+                    token,
+                    start: self.here,
+                    breaks: Vec::new(),
+                });
+                Ok(())
+            }
+            "while" => {
+                let condition = self.condition(&token)?;
+
+                if !self
+                    .blocks
+                    .iter()
+                    .any(|block| matches!(block, Block::Loop { .. }))
+                {
+                    return Err(self.syntax_error(&token, "`while` outside of a `loop`"));
+                }
+
+                // Leaves the loop unless the condition holds
+                self.skip_unless(&token, condition.negate())?;
+                let jump = self.here;
+                self.instruction(&token, Instruction::Jp(0))?;
+
+                if let Some(Block::Loop { breaks, .. }) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    breaks.push(jump);
+                }
+
+                Ok(())
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks, .. }) => {
+                    let start = self.in_range(&token, start as f64, 0xFFF)?;
+                    self.instruction(&token, Instruction::Jp(start as u16))?;
+
+                    for jump in breaks {
+                        self.patch_jump(&token, jump)?;
+                    }
+
+                    Ok(())
+                }
+                _ => Err(self.syntax_error(&token, "`again` without `loop`")),
+            },
+            directive if directive.starts_with(':') => Err(self.error(
+                &token,
+                AssemblyErrorKind::UnknownDirective(directive.to_string()),
+            )),
+            name => {
+                if let Some(definition) = self.macros.get(name).cloned() {
+                    return self.expand(&token, definition);
+                }
+
+                // A number on its own is a byte, a name is a call
+                if parse_number(name).is_some() || self.constants.contains_key(name) {
+                    let byte = self.byte(&token)?;
+                    return self.emit(&token, &[byte]);
+                }
+
+                let address = self.target(token.clone(), FixupKind::Address)?;
+                self.instruction(&token, Instruction::Call(address))
+            }
+        }
+    }
+
+    /**
+     * `vx := …`, `vx += …` and the other operators of a register
+     */
+    fn register_statement(&mut self, token: Token, x: u8) -> Compiled<()> {
+        let operator = self.expect(&token)?;
+        let operand = self.expect(&operator)?;
+        let y = self.register(&operand);
+
+        let instruction = match (operator.text.as_str(), y) {
+            (":=", Some(y)) => Instruction::LdRegister(x, y),
+            (":=", None) => match operand.text.as_str() {
+                "key" => Instruction::LdKey(x),
+                "delay" => Instruction::LdFromDelayTimer(x),
+                "random" => {
+                    let mask = self.expect(&operand)?;
+                    Instruction::Rnd(x, self.byte(&mask)?)
+                }
+                _ => Instruction::LdByte(x, self.byte(&operand)?),
+            },
+            ("+=", Some(y)) => Instruction::AddRegister(x, y),
+            ("+=", None) => Instruction::AddByte(x, self.byte(&operand)?),
+            ("-=", Some(y)) => Instruction::Sub(x, y),
+            ("-=", None) => Instruction::AddByte(x, self.byte(&operand)?.wrapping_neg()),
+            ("=-", Some(y)) => Instruction::Subn(x, y),
+            ("|=", Some(y)) => Instruction::Or(x, y),
+            ("&=", Some(y)) => Instruction::And(x, y),
+            ("^=", Some(y)) => Instruction::Xor(x, y),
+            (">>=", Some(y)) => Instruction::Shr(x, y),
+            ("<<=", Some(y)) => Instruction::Shl(x, y),
+            _ => {
+                return Err(self.error(
+                    &operator,
+                    AssemblyErrorKind::InvalidOperands(operator.text.clone()),
+                ))
+            }
+        };
+
+        self.instruction(&token, instruction)
+    }
+
+    /**
+     * `i := label`, `i := long label`, `i := hex vx`, `i := bighex vx` and `i += vx`
+     */
+    fn i_statement(&mut self, token: Token) -> Compiled<()> {
+        let operator = self.expect(&token)?;
+        let operand = self.expect(&operator)?;
+
+        let instruction = match (operator.text.as_str(), operand.text.as_str()) {
+            ("+=", _) => match self.register(&operand) {
+                Some(x) => Instruction::AddI(x),
+                None => {
+                    return Err(self.syntax_error(
+                        &operand,
+                        &format!("expected a register, found <{}>", operand.text),
+                    ))
+                }
+            },
+            (":=", "hex") => Instruction::LdFont(self.expect_register(&operand)?),
+            (":=", "bighex") => Instruction::LdBigFont(self.expect_register(&operand)?),
+            (":=", "long") => {
+                let label = self.expect(&operand)?;
+                Instruction::LdLongI(self.target(label, FixupKind::Long)?)
+            }
+            (":=", _) => Instruction::LdI(self.target(operand, FixupKind::Address)?),
+            _ => {
+                return Err(self.error(
+                    &operator,
+                    AssemblyErrorKind::InvalidOperands("i".to_string()),
+                ))
+            }
+        };
+
+        self.instruction(&token, instruction)
+    }
+
+    /**
+     * Parses `vx == value`, `vx key` and the other conditions of `if` and `while`
+     */
+    fn condition(&mut self, keyword: &Token) -> Compiled<Condition> {
+        let x = self.expect_register(keyword)?;
+        let operator = self.expect(keyword)?;
+
+        if operator.text == "key" || operator.text == "-key" {
+            return Ok(Condition::Key {
+                x,
+                pressed: operator.text == "key",
+            });
+        }
+
+        let comparison = Comparison::from_operator(&operator.text).ok_or_else(|| {
+            self.syntax_error(
+                &operator,
+                &format!("unknown comparison <{}>", operator.text),
+            )
+        })?;
+
+        let value = self.expect(&operator)?;
+        let operand = match self.register(&value) {
+            Some(y) => Operand::Register(y),
+            None => Operand::Byte(self.byte(&value)?),
+        };
+
+        Ok(Condition::Compare {
+            x,
+            comparison,
+            operand,
+        })
+    }
+
+    /**
+     * Emits the instructions which skip the next one when the condition is false
+     */
+    fn skip_unless(&mut self, token: &Token, condition: Condition) -> Compiled<()> {
+        use Comparison::*;
+        use Instruction::*;
+
+        let instructions = match condition {
+            Condition::Key { x, pressed: true } => vec![Sknp(x)],
+            Condition::Key { x, pressed: false } => vec![Skp(x)],
+            Condition::Compare {
+                x,
+                comparison,
+                operand,
+            } => match (comparison, operand) {
+                (Equal, Operand::Register(y)) => vec![SneRegister(x, y)],
+                (Equal, Operand::Byte(nn)) => vec![SneByte(x, nn)],
+                (NotEqual, Operand::Register(y)) => vec![SeRegister(x, y)],
+                (NotEqual, Operand::Byte(nn)) => vec![SeByte(x, nn)],
+                (_, operand) => {
+                    // VF is 1 when x >= y for < and >=, and when y >= x for > and <=
+                    let subtraction = match (comparison, operand) {
+                        (Less | GreaterOrEqual, Operand::Register(y)) => {
+                            [LdRegister(0xF, x), Sub(0xF, y)]
+                        }
+                        (Less | GreaterOrEqual, Operand::Byte(nn)) => {
+                            [LdByte(0xF, nn), Subn(0xF, x)]
+                        }
+                        (_, Operand::Register(y)) => [LdRegister(0xF, y), Sub(0xF, x)],
+                        (_, Operand::Byte(nn)) => [LdByte(0xF, nn), Sub(0xF, x)],
+                    };
+
+                    // < and > hold when VF is 0
+                    let skip = match comparison {
+                        Less | Greater => SneByte(0xF, 0),
+                        _ => SeByte(0xF, 0),
+                    };
+
+                    vec![subtraction[0], subtraction[1], skip]
+                }
+            },
+        };
+
+        for instruction in instructions {
+            self.instruction(token, instruction)?;
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Resolves a number, a constant, a label or a `{ calc }` expression.
+     * Returns `None` for a name which may be a label defined later
+     */
+    fn resolve(&mut self, token: &Token) -> Compiled<Option<f64>> {
+        if token.text == "{" && !token.is_string {
+            return self.calc(token).map(Some);
+        }
+
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(Some(value));
+        }
+
+        if let Some(value) = self.constants.get(&token.text) {
+            return Ok(Some(*value));
+        }
+
+        if let Some(address) = self.labels.get(&token.text) {
+            return Ok(Some(*address as f64));
+        }
+
+        if token.is_string || !is_identifier(&token.text) {
+            return Err(
+                self.syntax_error(token, &format!("expected a value, found <{}>", token.text))
+            );
+        }
+
+        Ok(None)
+    }
+
+    fn value(&mut self, token: &Token) -> Compiled<f64> {
+        match self.resolve(token)? {
+            Some(value) => Ok(value),
+            None => Err(self.error(token, AssemblyErrorKind::UnknownSymbol(token.text.clone()))),
+        }
+    }
+
+    /**
+     * Checks that the value fits on `max`. The negative values are written in
+     * two's complement, down to half the range
+     */
+    fn in_range(&self, token: &Token, value: f64, max: u32) -> Compiled<u32> {
+        let value = value.floor() as i64;
+        let min = -((max as i64 + 1) / 2);
+
+        if value < min || value > max as i64 {
+            return Err(self.error(token, AssemblyErrorKind::OutOfRange { value, max }));
+        }
+
+        Ok((value & max as i64) as u32)
+    }
+
+    fn nibble(&mut self, token: &Token) -> Compiled<u8> {
+        let value = self.value(token)?;
+        self.in_range(token, value, 0xF).map(|value| value as u8)
+    }
+
+    fn byte(&mut self, token: &Token) -> Compiled<u8> {
+        let value = self.value(token)?;
+        self.in_range(token, value, 0xFF).map(|value| value as u8)
+    }
+
+    /**
+     * Returns the address a jump, a call or a load points to. An unknown
+     * label is left as 0, to be patched once the whole program is compiled
+     */
+    fn target(&mut self, label: Token, kind: FixupKind) -> Compiled<u16> {
+        match self.resolve(&label)? {
+            Some(value) => self
+                .in_range(&label, value, kind.max())
+                .map(|address| address as u16),
+            None => {
+                self.fixups.push(Fixup {
+                    address: self.here,
+                    kind,
+                    label,
+                });
+                Ok(0)
+            }
+        }
+    }
+
+    fn define_label(&mut self, name: &Token, address: u32) -> Compiled<()> {
+        if !is_identifier(&name.text) || name.is_string {
+            return Err(self.syntax_error(name, &format!("invalid label name <{}>", name.text)));
+        }
+
+        if self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            return Err(self.error(name, AssemblyErrorKind::DuplicateSymbol(name.text.clone())));
+        }
+
+        let mut address = address;
+
+        // Nothing but the jump before main, so the jump is not needed
+        if name.text == "main" && self.here == address && self.rom.len() == 2 && self.here == 0x202
+        {
+            self.rom.clear();
+            self.written.clear();
+            self.here = DEFAULT_LOAD_ADDRESS as u32;
+            self.jumps_to_main = false;
+            address = self.here;
+        }
+
+        self.labels.insert(name.text.clone(), address as u16);
+        Ok(())
+    }
+
+    /**
+     * Defines a `:const`, or a `:calc` which may redefine a previous one
+     */
+    fn define_constant(&mut self, name: &Token, value: f64, redefine: bool) -> Compiled<()> {
+        if !is_identifier(&name.text) || name.is_string {
+            return Err(self.syntax_error(name, &format!("invalid constant name <{}>", name.text)));
+        }
+
+        let defined = self.labels.contains_key(&name.text)
+            || (self.constants.contains_key(&name.text) && !redefine);
+
+        if defined {
+            return Err(self.error(name, AssemblyErrorKind::DuplicateSymbol(name.text.clone())));
+        }
+
+        self.constants.insert(name.text.clone(), value);
+        Ok(())
+    }
+
+    /**
+     * `:macro name parameters { body }`
+     */
+    fn define_macro(&mut self, token: &Token) -> Compiled<()> {
+        let name = self.expect(token)?;
+        let mut parameters = Vec::new();
+
+        let body = loop {
+            let parameter = self.expect(&name)?;
+
+            if parameter.text == "{" {
+                break self.block_tokens(&parameter)?;
+            }
+
+            parameters.push(parameter.text);
+        };
+
+        if self.macros.contains_key(&name.text) {
+            return Err(self.error(&name, AssemblyErrorKind::DuplicateSymbol(name.text.clone())));
+        }
+
+        self.macros.insert(name.text, Macro { parameters, body });
+        Ok(())
+    }
+
+    /**
+     * Puts the body of the macro in place of its call, with the parameters replaced
+     */
+    fn expand(&mut self, token: &Token, definition: Macro) -> Compiled<()> {
+        self.expansions += 1;
+
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(self.syntax_error(token, "too many macro expansions"));
+        }
+
+        let mut arguments = Vec::new();
+        for _ in &definition.parameters {
+            arguments.push(self.expect(token)?);
+        }
+
+        for body_token in definition.body.into_iter().rev() {
+            let expanded = match definition
+                .parameters
+                .iter()
+                .position(|parameter| *parameter == body_token.text)
+            {
+                Some(index) => arguments[index].clone(),
+                None => body_token,
+            };
+
+            self.tokens.push_front(expanded);
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Takes the tokens until the `}` which closes `open`
+     */
+    fn block_tokens(&mut self, open: &Token) -> Compiled<Vec<Token>> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| self.syntax_error(open, "missing `}`"))?;
+
+            match token.text.as_str() {
+                "{" if !token.is_string => depth += 1,
+                "}" if !token.is_string && depth == 0 => return Ok(tokens),
+                "}" if !token.is_string => depth -= 1,
+                _ => {}
+            }
+
+            tokens.push(token);
+        }
+    }
+
+    /**
+     * Evaluates a `{ expression }`
+     */
+    fn calc(&mut self, open: &Token) -> Compiled<f64> {
+        if open.text != "{" || open.is_string {
+            return Err(self.syntax_error(open, "expected `{`"));
+        }
+
+        let tokens = self.block_tokens(open)?;
+        let mut position = 0;
+        let value = self.calc_expression(&tokens, &mut position, open)?;
+
+        match tokens.get(position) {
+            Some(token) => Err(self.syntax_error(
+                token,
+                &format!("unexpected <{}> in the expression", token.text),
+            )),
+            None => Ok(value),
+        }
+    }
+
+    /**
+     * A term, optionally followed by an operator and the rest of the expression
+     */
+    fn calc_expression(
+        &self,
+        tokens: &[Token],
+        position: &mut usize,
+        open: &Token,
+    ) -> Compiled<f64> {
+        let left = self.calc_term(tokens, position, open)?;
+
+        let Some(operator) = tokens.get(*position) else {
+            return Ok(left);
+        };
+
+        // None for a division by zero or a shift out of range
+        let apply: fn(f64, f64) -> Option<f64> = match operator.text.as_str() {
+            "+" => |a, b| Some(a + b),
+            "-" => |a, b| Some(a - b),
+            "*" => |a, b| Some(a * b),
+            "/" => |a, b| (b != 0.0).then(|| a / b),
+            "%" => |a, b| (b != 0.0).then(|| a % b),
+            "&" => |a, b| Some((a as i64 & b as i64) as f64),
+            "|" => |a, b| Some((a as i64 | b as i64) as f64),
+            "^" => |a, b| Some((a as i64 ^ b as i64) as f64),
+            "<<" => |a, b| shift(a, b, i64::checked_shl),
+            ">>" => |a, b| shift(a, b, i64::checked_shr),
+            "pow" => |a, b| Some(a.powf(b)),
+            "min" => |a, b| Some(a.min(b)),
+            "max" => |a, b| Some(a.max(b)),
+            "<" => |a, b| Some((a < b) as u8 as f64),
+            "<=" => |a, b| Some((a <= b) as u8 as f64),
+            ">" => |a, b| Some((a > b) as u8 as f64),
+            ">=" => |a, b| Some((a >= b) as u8 as f64),
+            "==" => |a, b| Some((a == b) as u8 as f64),
+            "!=" => |a, b| Some((a != b) as u8 as f64),
+            _ => return Ok(left),
+        };
+
+        *position += 1;
+        let right = self.calc_expression(tokens, position, open)?;
+
+        apply(left, right).ok_or_else(|| match operator.text.as_str() {
+            "/" | "%" => self.syntax_error(operator, "division by zero"),
+            _ => self.syntax_error(operator, "shift out of range (0 - 63)"),
+        })
+    }
+
+    fn calc_term(&self, tokens: &[Token], position: &mut usize, open: &Token) -> Compiled<f64> {
+        let token = tokens
+            .get(*position)
+            .ok_or_else(|| self.syntax_error(open, "incomplete expression"))?;
+        *position += 1;
+
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value);
+        }
+
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|a| -a),
+            "~" => Some(|a| !(a as i64) as f64),
+            "!" => Some(|a| (a == 0.0) as u8 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+
+        if let Some(apply) = unary {
+            return Ok(apply(self.calc_term(tokens, position, open)?));
+        }
+
+        match token.text.as_str() {
+            "(" => {
+                let value = self.calc_expression(tokens, position, open)?;
+
+                match tokens.get(*position) {
+                    Some(close) if close.text == ")" => {
+                        *position += 1;
+                        Ok(value)
+                    }
+                    _ => Err(self.syntax_error(token, "missing `)`")),
+                }
+            }
+            "@" => {
+                // The byte already compiled on the address
+                let address = self.calc_term(tokens, position, open)?;
+                let offset = address as i64 - DEFAULT_LOAD_ADDRESS as i64;
+
+                Ok(usize::try_from(offset)
+                    .ok()
+                    .and_then(|offset| self.rom.get(offset))
+                    .map_or(0.0, |byte| *byte as f64))
+            }
+            "HERE" => Ok(self.here as f64),
+            "PI" => Ok(consts::PI),
+            "E" => Ok(consts::E),
+            name => {
+                if let Some(value) = self.constants.get(name) {
+                    return Ok(*value);
+                }
+
+                match self.labels.get(name) {
+                    Some(address) => Ok(*address as f64),
+                    None => {
+                        Err(self.error(token, AssemblyErrorKind::UnknownSymbol(name.to_string())))
+                    }
+                }
+            }
+        }
+    }
+
+    /**
+     * Emits the instruction, if the instruction set has it
+     */
+    fn instruction(&mut self, token: &Token, instruction: Instruction) -> Compiled<()> {
+        if !self.instruction_set.supports(&instruction) {
+            return Err(self.error(
+                token,
+                AssemblyErrorKind::Unsupported {
+                    statement: token.text.clone(),
+                    instruction_set: InstructionSet::required_by(&instruction).to_string(),
+                },
+            ));
+        }
+
+        self.emit(token, &instruction.encode())
+    }
+
+    fn emit(&mut self, token: &Token, bytes: &[u8]) -> Compiled<()> {
+        let memory_size = self.instruction_set.memory_size();
+
+        if self.here as usize + bytes.len() > memory_size {
+            return Err(self.error(token, AssemblyErrorKind::OutOfMemory(memory_size)));
+        }
+
+        let offset = (self.here - DEFAULT_LOAD_ADDRESS as u32) as usize;
+        let end = offset + bytes.len();
+
+        if self.rom.len() < end {
+            self.rom.resize(end, 0x0);
+            self.written.resize(end, false);
+        }
+
+        for (index, byte) in bytes.iter().enumerate() {
+            if self.written[offset + index] {
+                let address = (self.here as usize + index) as u16;
+                return Err(self.error(token, AssemblyErrorKind::Overlap(address)));
+            }
+
+            self.rom[offset + index] = *byte;
+            self.written[offset + index] = true;
+        }
+
+        self.here += bytes.len() as u32;
+
+        Ok(())
+    }
+
+    /**
+     * Points the `JP 0` compiled on `at` to the current address
+     */
+    fn patch_jump(&mut self, token: &Token, at: u32) -> Compiled<()> {
+        let target = self.in_range(token, self.here as f64, 0xFFF)? as u16;
+        let offset = (at - DEFAULT_LOAD_ADDRESS as u32) as usize;
+
+        self.rom[offset..offset + 2].copy_from_slice(&Instruction::Jp(target).encode());
+
+        Ok(())
+    }
+
+    /**
+     * Patches the labels used before being defined, and the jump to main
+     */
+    fn finish(mut self) -> Result<CompiledProgram, AssemblyError> {
+        if let Some(block) = self.blocks.last() {
+            return Err(match block {
+                Block::If { token, .. } => self.syntax_error(token, "missing `end`"),
+                Block::Loop { token, .. } => self.syntax_error(token, "missing `again`"),
+            });
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let Some(&address) = self.labels.get(&fixup.label.text) else {
+                return Err(self.error(
+                    &fixup.label,
+                    AssemblyErrorKind::UnknownSymbol(fixup.label.text.clone()),
+                ));
+            };
+
+            self.in_range(&fixup.label, address as f64, fixup.kind.max())?;
+
+            let offset = (fixup.address - DEFAULT_LOAD_ADDRESS as u32) as usize;
+            let rom = &mut self.rom;
+
+            match fixup.kind {
+                FixupKind::Address => {
+                    rom[offset] = (rom[offset] & 0xF0) | (address >> 8) as u8;
+                    rom[offset + 1] = address as u8;
+                }
+                FixupKind::Long => {
+                    rom[offset + 2..offset + 4].copy_from_slice(&address.to_be_bytes())
+                }
+                FixupKind::Pointer => {
+                    rom[offset..offset + 2].copy_from_slice(&address.to_be_bytes())
+                }
+                FixupKind::Unpack(nibble) => {
+                    let (v0, v1) = unpack(nibble, address);
+                    rom[offset + 1] = v0;
+                    rom[offset + 3] = v1;
+                }
+            }
+        }
+
+        if self.jumps_to_main {
+            let Some(&main) = self.labels.get("main") else {
+                return Err(AssemblyError {
+                    file: self.file.clone(),
+                    line: 1,
+                    column: 1,
+                    kind: AssemblyErrorKind::UnknownSymbol("main".to_string()),
+                });
+            };
+
+            let main_token = Token {
+                text: "main".to_string(),
+                line: 1,
+                column: 1,
+                is_string: false,
+            };
+            let main = self.in_range(&main_token, main as f64, 0xFFF)? as u16;
+
+            self.rom[0..2].copy_from_slice(&Instruction::Jp(main).encode());
+        }
+
+        let symbols = SymbolTable {
+            labels: self.labels.into_iter().collect(),
+            breakpoints: self.breakpoints,
+            constants: self
+                .constants
+                .into_iter()
+                .map(|(name, value)| (name, value.floor() as i64))
+                .collect(),
+        };
+
+        Ok(CompiledProgram {
+            bytes: self.rom,
+            symbols,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InstructionSet, OctoCompiler, SymbolTable};
+    use crate::{
+        asm::AssemblyErrorKind,
+        cpu::CPU,
+        quirks::Quirks,
+        rom::{Rom, DEFAULT_LOAD_ADDRESS},
+    };
+
+    fn compile(source: &str) -> Vec<u8> {
+        OctoCompiler::new(InstructionSet::XoChip)
+            .compile(source)
+            .unwrap()
+            .bytes
+    }
+
+    #[test]
+    fn test_compile_statements() {
+        let bytes = compile(
+            "
+            : main
+                clear
+                v0 := 5          # comment
+                v1 += v0
+                v2 -= 1
+                i := sprite
+                sprite v0 v1 1
+                i := long sprite
+                jump main
+            : sprite 0x80 0b01000000
+            ",
+        );
+
+        assert_eq!(
+            bytes,
+            vec![
+                0x00, 0xE0, 0x60, 0x05, 0x81, 0x04, 0x72, 0xFF, 0xA2, 0x12, 0xD0, 0x11, 0xF0, 0x00,
+                0x02, 0x12, 0x12, 0x00, 0x80, 0x40
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_jump_to_main() {
+        let bytes = compile(
+            "
+            : draw return
+            : main draw
+            ",
+        );
+
+        // jump main ; return ; call draw
+        assert_eq!(bytes, vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+        assert_eq!(
+            OctoCompiler::new(InstructionSet::XoChip)
+                .compile(": draw return")
+                .unwrap_err()
+                .kind,
+            AssemblyErrorKind::UnknownSymbol("main".to_string())
+        );
+    }
+
+    #[test]
+    fn test_compile_control_flow() {
+        // v0 counts to 10 (loop and while), then v1 is 1 if v0 > 5 (if … else)
+        let program = OctoCompiler::new(InstructionSet::XoChip)
+            .compile(
+                "
+                : main
+                    loop
+                        while v0 != 10
+                        v0 += 1
+                    again
+                    if v0 > 5 begin
+                        v1 := 1
+                    else
+                        v1 := 2
+                    end
+                    if v0 key then v2 := 1
+                    i := result
+                    save v2
+                : halt
+                    0 0
+                : result
+                    0 0 0
+                ",
+            )
+            .unwrap();
+
+        let mut cpu = CPU::new(Quirks::xo_chip());
+        cpu.load_rom(&Rom::from_bytes(program.bytes, DEFAULT_LOAD_ADDRESS).unwrap())
+            .unwrap();
+        cpu.run().unwrap();
+
+        let result = program.symbols.labels()["result"];
+        assert_eq!(cpu.memory().read_bytes(result, 3).unwrap(), &[10, 1, 0]);
+    }
+
+    #[test]
+    fn test_compile_macros_and_calc() {
+        let program = OctoCompiler::new(InstructionSet::XoChip)
+            .compile(
+                "
+                :alias counter v3
+                :const SPEED 3
+                :calc STEP { SPEED * 2 + 1 }
+                :macro add-twice register amount { register += amount register += amount }
+                : main
+                    add-twice counter STEP
+                    :breakpoint check
+                    :unpack 0xA data
+                    :byte { 10 - 2 - 1 }
+                : data
+                    :pointer main
+                ",
+            )
+            .unwrap();
+
+        assert_eq!(
+            program.bytes,
+            vec![0x73, 0x09, 0x73, 0x09, 0x60, 0xA2, 0x61, 0x09, 0x09, 0x02, 0x00]
+        );
+        assert_eq!(program.symbols.labels()["data"], 0x209);
+        // Right to left: SPEED * (2 + 1)
+        assert_eq!(program.symbols.constants()["STEP"], 9);
+        assert_eq!(program.symbols.label_at(0x200), Some("main"));
+        assert_eq!(
+            program.symbols.to_string(),
+            "label main 0x0200\n\
+             label data 0x0209\n\
+             breakpoint check 0x0204\n\
+             const SPEED 3\n\
+             const STEP 9\n"
+        );
+        assert_eq!(
+            SymbolTable::parse(&program.symbols.to_string()),
+            Some(program.symbols)
+        );
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let error = |set, source: &str| OctoCompiler::new(set).compile(source).unwrap_err();
+
+        let unsupported = error(InstructionSet::Chip8, ": main\n  hires");
+        assert_eq!((unsupported.line, unsupported.column), (2, 3));
+        assert_eq!(
+            unsupported.kind,
+            AssemblyErrorKind::Unsupported {
+                statement: "hires".to_string(),
+                instruction_set: "SUPER-CHIP".to_string(),
+            }
+        );
+        assert_eq!(
+            error(InstructionSet::SuperChip, ": main i := long main").kind,
+            AssemblyErrorKind::Unsupported {
+                statement: "i".to_string(),
+                instruction_set: "XO-CHIP".to_string(),
+            }
+        );
+        assert_eq!(
+            error(InstructionSet::XoChip, ": main v0 := 256").kind,
+            AssemblyErrorKind::OutOfRange {
+                value: 256,
+                max: 0xFF
+            }
+        );
+        assert_eq!(
+            error(InstructionSet::XoChip, ": main jump nowhere").kind,
+            AssemblyErrorKind::UnknownSymbol("nowhere".to_string())
+        );
+        assert_eq!(
+            error(InstructionSet::XoChip, ": main loop").kind,
+            AssemblyErrorKind::Syntax("missing `again`".to_string())
+        );
+        assert_eq!(
+            error(
+                InstructionSet::XoChip,
+                ": main :assert \"too big\" { 1 > 2 }"
+            )
+            .kind,
+            AssemblyErrorKind::AssertionFailed("too big".to_string())
+        );
+        assert_eq!(
+            error(InstructionSet::Chip8, ": main :org 0xFFF v0 := 1").kind,
+            AssemblyErrorKind::OutOfMemory(4096)
+        );
+
+        let division = error(InstructionSet::XoChip, ": main :calc X { 1 / 0 }");
+        assert_eq!((division.line, division.column), (1, 20));
+        assert_eq!(
+            division.kind,
+            AssemblyErrorKind::Syntax("division by zero".to_string())
+        );
+        assert_eq!(
+            error(InstructionSet::XoChip, ": main :calc X { 1 << 64 }").kind,
+            AssemblyErrorKind::Syntax("shift out of range (0 - 63)".to_string())
+        );
+        assert_eq!(
+            error(InstructionSet::XoChip, ": main :calc X { 8 >> -1 }").kind,
+            AssemblyErrorKind::Syntax("shift out of range (0 - 63)".to_string())
+        );
+    }
+}
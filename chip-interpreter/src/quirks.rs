@@ -0,0 +1,207 @@
+/// Behaviours which differ among the CHIP-8 platforms.
+/// Each game must be run with the quirks of the platform it was written for.
+///
+/// | Quirk                     | COSMAC VIP | CHIP-48 | SUPER-CHIP | XO-CHIP | Modern |
+/// | ------------------------- | ---------- | ------- | ---------- | ------- | ------ |
+/// | `shift_uses_vy`           | yes        | no      | no         | yes     | no     |
+/// | `logic_resets_vf`         | yes        | no      | no         | no      | no     |
+/// | `load_store_increments_i` | yes        | yes     | no         | yes     | no     |
+/// | `jump_with_vx`            | no         | yes     | yes        | no      | no     |
+/// | `wrap_sprites`            | no         | no      | no         | yes     | no     |
+/// | `display_wait`            | yes        | no      | no         | no      | no     |
+/// | `xo_chip`                 | no         | no      | no         | yes     | no     |
+/// | `large_sprites`           | no         | no      | yes        | yes     | yes    |
+/// | `hires_clears`            | no         | no      | no         | yes     | yes    |
+/// | `load_store_i_on_last`    | no         | yes     | no         | no      | no     |
+/// | `super_chip`              | no         | no      | yes        | yes     | yes    |
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// **SHR/SHL Vx, Vy** shift Vy and store it on Vx, instead of shifting Vx
+    pub shift_uses_vy: bool,
+    /// **OR/AND/XOR Vx, Vy** set VF to 0
+    pub logic_resets_vf: bool,
+    /// **LD [I], Vx** and **LD Vx, [I]** leave I pointing after the last register
+    pub load_store_increments_i: bool,
+    /// **JP V0, NNN** becomes **JP Vx, XNN**
+    pub jump_with_vx: bool,
+    /// The sprites wrap around the screen instead of being clipped
+    pub wrap_sprites: bool,
+    /// Only one sprite can be drawn per frame, as the COSMAC VIP
+    /// waits for the vertical blank interrupt before drawing
+    pub display_wait: bool,
+    /// Enables the XO-CHIP extensions: 64 KiB of memory, 2 bit planes,
+    /// audio patterns and the 4 bytes long **LD I, NNNN**
+    pub xo_chip: bool,
+    /// **DRW Vx, Vy, 0** draws a 16x16 sprite, instead of nothing
+    pub large_sprites: bool,
+    /// **LOW** and **HIGH** clear the screen, instead of scaling the picture
+    /// to the new resolution
+    pub hires_clears: bool,
+    /// With `load_store_increments_i`, I is left pointing on the last
+    /// register instead of after it (I + X, not I + X + 1)
+    pub load_store_i_on_last: bool,
+    /// Enables the SUPER-CHIP instructions: **LOW**, **HIGH**, the scrolls,
+    /// **EXIT**, **DRW Vx, Vy, 0**, the big font and the RPL flags
+    pub super_chip: bool,
+}
+
+impl Quirks {
+    /**
+     * The original CHIP-8 interpreter of the COSMAC VIP
+     */
+    pub const fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            logic_resets_vf: true,
+            load_store_increments_i: true,
+            jump_with_vx: false,
+            wrap_sprites: false,
+            display_wait: true,
+            xo_chip: false,
+            large_sprites: false,
+            hires_clears: false,
+            load_store_i_on_last: false,
+            super_chip: false,
+        }
+    }
+
+    /**
+     * CHIP-48, for the HP-48 calculators. Unlike SUPER-CHIP 1.1, it has none
+     * of its instructions and **LD [I], Vx** moves I to the last register
+     */
+    pub const fn chip48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            logic_resets_vf: false,
+            load_store_increments_i: true,
+            jump_with_vx: true,
+            wrap_sprites: false,
+            display_wait: false,
+            xo_chip: false,
+            large_sprites: false,
+            hires_clears: false,
+            load_store_i_on_last: true,
+            super_chip: false,
+        }
+    }
+
+    /**
+     * SUPER-CHIP 1.1, for the HP-48 calculators
+     */
+    pub const fn super_chip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            logic_resets_vf: false,
+            load_store_increments_i: false,
+            jump_with_vx: true,
+            wrap_sprites: false,
+            display_wait: false,
+            xo_chip: false,
+            large_sprites: true,
+            hires_clears: false,
+            load_store_i_on_last: false,
+            super_chip: true,
+        }
+    }
+
+    /**
+     * XO-CHIP, as Octo runs it
+     */
+    pub const fn xo_chip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            logic_resets_vf: false,
+            load_store_increments_i: true,
+            jump_with_vx: false,
+            wrap_sprites: true,
+            display_wait: false,
+            xo_chip: true,
+            large_sprites: true,
+            hires_clears: true,
+            load_store_i_on_last: false,
+            super_chip: true,
+        }
+    }
+
+    /**
+     * What most of the modern interpreters do
+     */
+    pub const fn modern() -> Self {
+        Self {
+            shift_uses_vy: false,
+            logic_resets_vf: false,
+            load_store_increments_i: false,
+            jump_with_vx: false,
+            wrap_sprites: false,
+            display_wait: false,
+            xo_chip: false,
+            large_sprites: true,
+            hires_clears: true,
+            load_store_i_on_last: false,
+            super_chip: true,
+        }
+    }
+
+    /**
+     * Returns the preset with the given name
+     * (`vip`, `chip48`, `schip`, `xochip` or `modern`)
+     */
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "vip" | "cosmac-vip" | "chip8" => Some(Self::cosmac_vip()),
+            "chip48" | "chip-48" => Some(Self::chip48()),
+            "schip" | "superchip" | "super-chip" => Some(Self::super_chip()),
+            "xochip" | "xo-chip" => Some(Self::xo_chip()),
+            "modern" => Some(Self::modern()),
+            _ => None,
+        }
+    }
+
+    /**
+     * Packs the quirks one bit each, in the order of the struct, as the
+     * save states and the movies store them
+     */
+    pub fn to_bits(&self) -> u16 {
+        [
+            self.shift_uses_vy,
+            self.logic_resets_vf,
+            self.load_store_increments_i,
+            self.jump_with_vx,
+            self.wrap_sprites,
+            self.display_wait,
+            self.xo_chip,
+            self.large_sprites,
+            self.hires_clears,
+            self.load_store_i_on_last,
+            self.super_chip,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |bits, (bit, set)| bits | (*set as u16) << bit)
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        let bit = |n: u16| bits & (1 << n) != 0;
+
+        Self {
+            shift_uses_vy: bit(0),
+            logic_resets_vf: bit(1),
+            load_store_increments_i: bit(2),
+            jump_with_vx: bit(3),
+            wrap_sprites: bit(4),
+            display_wait: bit(5),
+            xo_chip: bit(6),
+            large_sprites: bit(7),
+            hires_clears: bit(8),
+            load_store_i_on_last: bit(9),
+            super_chip: bit(10),
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::modern()
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::VecDeque;
+
+use crate::{cpu::CPU, error::EmulatorError, savestate::SaveState};
+
+/// Frames between two snapshots: two per second at 60 Hz
+pub const DEFAULT_SNAPSHOT_INTERVAL: u32 = 30;
+/// Snapshots kept: five minutes of history with the default interval
+pub const DEFAULT_REWIND_CAPACITY: usize = 600;
+
+/// A save state, compressed. The oldest one is whole (a keyframe), and each
+/// of the others only keeps the bytes which changed since the previous one
+#[derive(Debug, Clone)]
+struct Snapshot {
+    /// The frame which was about to run
+    frame: u64,
+    keyframe: bool,
+    data: Vec<u8>,
+}
+
+/// Writes a number 7 bits at a time, the last byte without the high bit
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+
+    while let Some(byte) = data.get(*position) {
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    value
+}
+
+/**
+ * Packs the runs of zeros, which are most of a delta and of an empty
+ * memory: each run is written as `zeros, literals, the literal bytes`
+ */
+fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let zeros = bytes[position..]
+            .iter()
+            .take_while(|byte| **byte == 0)
+            .count();
+        position += zeros;
+
+        // A lonely zero is cheaper as a literal than as a new run
+        let mut literals = 0;
+        while position + literals < bytes.len() {
+            let rest = &bytes[position + literals..];
+
+            if rest.len() > 1 && rest[0] == 0 && rest[1] == 0 {
+                break;
+            }
+
+            literals += 1;
+        }
+
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, literals);
+        out.extend_from_slice(&bytes[position..position + literals]);
+        position += literals;
+    }
+
+    out
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut position = 0;
+
+    while position < data.len() {
+        let zeros = read_varint(data, &mut position);
+        bytes.resize(bytes.len() + zeros, 0x0);
+
+        let literals = read_varint(data, &mut position);
+        let end = (position + literals).min(data.len());
+        bytes.extend_from_slice(&data[position..end]);
+        position = end;
+    }
+
+    bytes
+}
+
+fn xor(left: &[u8], right: &[u8]) -> Vec<u8> {
+    left.iter().zip(right).map(|(a, b)| a ^ b).collect()
+}
+
+/// Lets a frontend go back in time: it runs the frames through
+/// [`Rewind::run_frame`], which takes a snapshot of the machine every
+/// `interval` frames and remembers the keypad of every frame.
+///
+/// Going back to a frame restores the snapshot before it and runs the
+/// frames in between again with the same keys, so any frame of the history
+/// can be reached, not only the snapshots. It needs a deterministic random
+/// generator, like the one of [`CPU::with_seed`].
+///
+/// The snapshots are delta encoded against the previous one and the runs of
+/// zeros are packed, so a frame which only changed a few bytes of the memory
+/// costs a few bytes
+
+#[derive(Debug, Clone)]
+pub struct Rewind {
+    interval: u32,
+    capacity: usize,
+    snapshots: VecDeque<Snapshot>,
+    /// The bytes of the newest snapshot, which the next delta is taken against
+    latest: Vec<u8>,
+    /// The keypad of each frame since the oldest snapshot
+    inputs: VecDeque<u16>,
+    /// The frame which runs next
+    frame: u64,
+}
+
+impl Rewind {
+    /**
+     * Takes a snapshot every `interval` frames, and keeps up to `capacity` of them
+     */
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            snapshots: VecDeque::new(),
+            latest: Vec::new(),
+            inputs: VecDeque::new(),
+            frame: 0,
+        }
+    }
+
+    /**
+     * Returns the number of the frame which runs next
+     */
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /**
+     * Returns the first frame which can still be reached
+     */
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.snapshots.front().map(|snapshot| snapshot.frame)
+    }
+
+    /**
+     * Returns how many snapshots are kept
+     */
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /**
+     * Returns how many bytes the compressed snapshots take
+     */
+    pub fn stored_bytes(&self) -> usize {
+        self.snapshots
+            .iter()
+            .map(|snapshot| snapshot.data.len())
+            .sum()
+    }
+
+    /**
+     * Forgets the whole history, like after loading another ROM
+     */
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.inputs.clear();
+        self.latest.clear();
+        self.frame = 0;
+    }
+
+    /**
+     * Runs a frame (`CPU::run_frame`) with the keys the frontend has set,
+     * taking a snapshot first when it is its turn
+     */
+    pub fn run_frame(&mut self, cpu: &mut CPU) -> Result<bool, EmulatorError> {
+        let last_snapshot = self.snapshots.back().map(|snapshot| snapshot.frame);
+
+        if self.frame.is_multiple_of(self.interval as u64) && last_snapshot != Some(self.frame) {
+            self.push(cpu.save_state().to_bytes());
+        }
+
+        self.inputs.push_back(cpu.keypad().bits());
+        self.frame += 1;
+
+        cpu.run_frame()
+    }
+
+    /**
+     * Goes back a single frame. Returns false if it is not on the history
+     */
+    pub fn step_back(&mut self, cpu: &mut CPU) -> Result<bool, EmulatorError> {
+        match self.frame.checked_sub(1) {
+            Some(frame) => self.seek(cpu, frame),
+            None => Ok(false),
+        }
+    }
+
+    /**
+     * Puts the machine back on the start of the given frame, with the keys it
+     * was run with, and forgets the frames after it. Returns false if the
+     * frame is not on the history
+     */
+    pub fn seek(&mut self, cpu: &mut CPU, frame: u64) -> Result<bool, EmulatorError> {
+        let Some(oldest) = self.oldest_frame() else {
+            return Ok(false);
+        };
+
+        if frame < oldest || frame > self.frame {
+            return Ok(false);
+        }
+
+        // The last snapshot taken before the frame
+        let index = self
+            .snapshots
+            .iter()
+            .rposition(|snapshot| snapshot.frame <= frame)
+            .unwrap_or_default();
+        let bytes = self.decode(index);
+        let state = SaveState::from_bytes(&bytes).expect("the snapshots are valid save states");
+
+        // Replayed on another machine, so a fault leaves `cpu` as it was
+        let mut scratch = CPU::with_seed(state.quirks, 0);
+        scratch.restore_state(&state);
+
+        let start = self.snapshots[index].frame;
+        for replayed in start..frame {
+            scratch.set_keys(self.inputs[(replayed - oldest) as usize]);
+            scratch.run_frame()?;
+        }
+
+        // And the keys the frame was run with
+        if let Some(keys) = self.inputs.get((frame - oldest) as usize) {
+            scratch.set_keys(*keys);
+        }
+
+        cpu.restore_state(&scratch.save_state());
+
+        self.snapshots.truncate(index + 1);
+        self.inputs.truncate((frame - oldest) as usize);
+        self.latest = bytes;
+        self.frame = frame;
+
+        Ok(true)
+    }
+
+    /**
+     * Returns the bytes of the snapshot, applying the deltas from the keyframe
+     */
+    fn decode(&self, index: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        for snapshot in self.snapshots.range(..=index) {
+            let data = decompress(&snapshot.data);
+
+            bytes = match snapshot.keyframe {
+                true => data,
+                false => xor(&bytes, &data),
+            };
+        }
+
+        bytes
+    }
+
+    fn push(&mut self, bytes: Vec<u8>) {
+        // Switching to or from XO-CHIP changes the size of the memory
+        let keyframe = self.snapshots.is_empty() || bytes.len() != self.latest.len();
+
+        let data = match keyframe {
+            true => compress(&bytes),
+            false => compress(&xor(&self.latest, &bytes)),
+        };
+
+        self.snapshots.push_back(Snapshot {
+            frame: self.frame,
+            keyframe,
+            data,
+        });
+        self.latest = bytes;
+
+        if self.snapshots.len() > self.capacity {
+            // The next snapshot becomes the keyframe
+            let bytes = self.decode(1);
+            let oldest = self.snapshots[0].frame;
+
+            self.snapshots.pop_front();
+            self.snapshots[0].keyframe = true;
+            self.snapshots[0].data = compress(&bytes);
+
+            self.inputs
+                .drain(..(self.snapshots[0].frame - oldest) as usize);
+        }
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_SNAPSHOT_INTERVAL, DEFAULT_REWIND_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress, Rewind};
+    use crate::{
+        asm::Assembler,
+        cpu::CPU,
+        quirks::Quirks,
+        rom::{Rom, DEFAULT_LOAD_ADDRESS},
+        savestate::SaveState,
+    };
+
+    fn cpu() -> CPU {
+        let program = Assembler::new()
+            .assemble(
+                "
+                    LD V2, 5
+                    LD I, 0x300
+                loop:
+                    RND V0, 0x3F
+                    ADD V1, 1
+                    SKNP V2
+                    ADD V3, 1
+                    LD [I], V3
+                    LD F, V0
+                    DRW V0, V1, 5
+                    JP loop
+                ",
+            )
+            .unwrap();
+        let mut cpu = CPU::with_seed(Quirks::default(), 3);
+
+        cpu.load_rom(&Rom::from_bytes(program, DEFAULT_LOAD_ADDRESS).unwrap())
+            .unwrap();
+
+        cpu
+    }
+
+    /**
+     * Runs the frames with the key 5 pressed on some of them, and returns
+     * the state at the start of each frame
+     */
+    fn run(rewind: &mut Rewind, cpu: &mut CPU, frames: u64) -> Vec<SaveState> {
+        let mut states = Vec::new();
+
+        for frame in 0..frames {
+            cpu.set_keys(if frame % 7 < 3 { 1 << 5 } else { 0 });
+            states.push(cpu.save_state());
+            rewind.run_frame(cpu).unwrap();
+        }
+
+        states
+    }
+
+    #[test]
+    fn test_compression() {
+        let mut bytes = vec![0x0; 5000];
+        bytes[10] = 1;
+        bytes[12] = 2;
+        bytes[4000..4100].fill(7);
+
+        let compressed = compress(&bytes);
+
+        assert!(compressed.len() < 120);
+        assert_eq!(decompress(&compressed), bytes);
+        assert_eq!(decompress(&compress(&[])), Vec::<u8>::new());
+        assert_eq!(decompress(&compress(&[0, 0, 1])), vec![0, 0, 1]);
+    }
+
+    #[test]
+    fn test_seek_any_frame() {
+        let mut cpu = cpu();
+        let mut rewind = Rewind::new(10, 100);
+        let states = run(&mut rewind, &mut cpu, 95);
+
+        assert_eq!(rewind.len(), 10);
+        assert_eq!(rewind.frame(), 95);
+
+        // Each snapshot after the first one only stores what changed
+        let state_size = states[0].to_bytes().len();
+        assert!(rewind.stored_bytes() < state_size);
+
+        assert_eq!(rewind.step_back(&mut cpu), Ok(true));
+        assert_eq!(cpu.save_state(), states[94]);
+
+        for frame in [93, 61, 60, 59, 0] {
+            assert_eq!(rewind.seek(&mut cpu, frame), Ok(true));
+            assert_eq!(cpu.save_state(), states[frame as usize]);
+            assert_eq!(rewind.frame(), frame);
+        }
+
+        assert_eq!(rewind.step_back(&mut cpu), Ok(false));
+
+        // The history goes on from there, the same way it did the first time
+        let again = run(&mut rewind, &mut cpu, 40);
+        assert_eq!(again[..], states[..40]);
+        assert_eq!(rewind.seek(&mut cpu, 25), Ok(true));
+        assert_eq!(cpu.save_state(), states[25]);
+    }
+
+    #[test]
+    fn test_failed_seek_keeps_the_machine() {
+        // Returns without a call after a few frames
+        let program = Assembler::new()
+            .assemble(
+                "
+                    LD V0, 12
+                    LD DT, V0
+                wait:
+                    LD V0, DT
+                    SE V0, 0
+                    JP wait
+                    RET
+                ",
+            )
+            .unwrap();
+        let mut cpu = CPU::with_seed(Quirks::default(), 3);
+        cpu.load_rom(&Rom::from_bytes(program, DEFAULT_LOAD_ADDRESS).unwrap())
+            .unwrap();
+
+        let mut rewind = Rewind::new(5, 10);
+        while rewind.run_frame(&mut cpu).is_ok() {}
+
+        let state = cpu.save_state();
+        let frame = rewind.frame();
+
+        // Going to the end runs the faulty frame again
+        assert!(rewind.seek(&mut cpu, frame).is_err());
+        assert_eq!(cpu.save_state(), state);
+        assert_eq!(rewind.frame(), frame);
+
+        assert_eq!(rewind.seek(&mut cpu, frame - 1), Ok(true));
+        assert_ne!(cpu.save_state(), state);
+    }
+
+    #[test]
+    fn test_bounded_history() {
+        let mut cpu = cpu();
+        let mut rewind = Rewind::new(4, 3);
+        let states = run(&mut rewind, &mut cpu, 30);
+
+        // The snapshots of the frames 20, 24 and 28
+        assert_eq!(rewind.len(), 3);
+        assert_eq!(rewind.oldest_frame(), Some(20));
+        assert_eq!(rewind.seek(&mut cpu, 19), Ok(false));
+        assert_eq!(rewind.seek(&mut cpu, 31), Ok(false));
+
+        assert_eq!(rewind.seek(&mut cpu, 22), Ok(true));
+        assert_eq!(cpu.save_state(), states[22]);
+        assert_eq!(rewind.seek(&mut cpu, 20), Ok(true));
+        assert_eq!(cpu.save_state(), states[20]);
+    }
+}
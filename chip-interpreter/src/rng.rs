@@ -0,0 +1,84 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Source of the random bytes used by the **RND Vx, NN** instruction.
+/// A host can provide its own one, so the runs can be reproduced
+pub trait Rng: std::fmt::Debug {
+    fn next_u8(&mut self) -> u8;
+
+    /**
+     * Returns the state a save state keeps, if the generator has one
+     */
+    fn state(&self) -> Option<u64> {
+        None
+    }
+
+    /**
+     * Puts back a state returned by `state`
+     */
+    fn set_state(&mut self, _state: u64) {}
+}
+
+/// Default random generator (SplitMix64).
+/// The same seed always produces the same sequence of bytes
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /**
+     * Creates a generator seeded with the current time
+     */
+    pub fn from_time() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default();
+
+        Self::new(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+
+        z ^ (z >> 31)
+    }
+}
+
+impl Rng for SeededRng {
+    fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn state(&self) -> Option<u64> {
+        Some(self.state)
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Rng, SeededRng};
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut rng1 = SeededRng::new(42);
+        let mut rng2 = SeededRng::new(42);
+
+        for _ in 0..64 {
+            assert_eq!(rng1.next_u8(), rng2.next_u8());
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+/// Where the programs are loaded on most of the platforms
+pub const DEFAULT_LOAD_ADDRESS: u16 = 0x200;
+/// Where the programs are loaded on the ETI-660
+pub const ETI660_LOAD_ADDRESS: u16 = 0x600;
+
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    /// The ROM file has no bytes at all
+    Empty,
+    /// The ROM does not fit between the load address and the end of the memory
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "{err}"),
+            RomError::Empty => write!(f, "the ROM is empty"),
+            RomError::TooLarge { size, max } => write!(
+                f,
+                "the ROM is too large: {size} bytes, but only {max} bytes fit on memory"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+/// A program ready to be loaded into memory
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rom {
+    data: Vec<u8>,
+    load_address: u16,
+}
+
+impl Rom {
+    /**
+     * Creates a ROM from its bytes, to be loaded on the given address
+     */
+    pub fn from_bytes(data: Vec<u8>, load_address: u16) -> Result<Self, RomError> {
+        if data.is_empty() {
+            return Err(RomError::Empty);
+        }
+
+        Ok(Self { data, load_address })
+    }
+
+    /**
+     * Reads the whole reader, no matter how many reads it takes
+     */
+    pub fn from_reader<R: Read>(mut reader: R, load_address: u16) -> Result<Self, RomError> {
+        let mut data = Vec::new();
+
+        reader.read_to_end(&mut data)?;
+
+        Self::from_bytes(data, load_address)
+    }
+
+    /**
+     * Reads a ROM file (.ch8)
+     */
+    pub fn from_file<P: AsRef<Path>>(path: P, load_address: u16) -> Result<Self, RomError> {
+        Self::from_reader(File::open(path)?, load_address)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn load_address(&self) -> u16 {
+        self.load_address
+    }
+
+    /**
+     * Hashes the bytes of the ROM (64 bit FNV-1a), to tell whether two
+     * files hold the same program
+     */
+    pub fn hash(&self) -> u64 {
+        self.data.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+    }
+
+    /**
+     * Every instruction is 2 bytes long, so an odd length usually
+     * means that the ROM is truncated (or that it ends with data)
+     */
+    pub fn has_odd_length(&self) -> bool {
+        !self.data.len().is_multiple_of(2)
+    }
+
+    /**
+     * Checks that the ROM fits on a memory of the given size
+     */
+    pub fn check_fits(&self, memory_size: usize) -> Result<(), RomError> {
+        let max = memory_size.saturating_sub(self.load_address as usize);
+
+        if self.data.len() > max {
+            return Err(RomError::TooLarge {
+                size: self.data.len(),
+                max,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::{Rom, RomError, DEFAULT_LOAD_ADDRESS, ETI660_LOAD_ADDRESS};
+
+    /// Reader which returns a single byte on each read
+    struct ByteReader(Vec<u8>);
+
+    impl Read for ByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+
+            buf[0] = self.0.remove(0);
+
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn test_rom_partial_reads() {
+        let rom =
+            Rom::from_reader(ByteReader(vec![0x60, 0x01, 0x12]), DEFAULT_LOAD_ADDRESS).unwrap();
+
+        assert_eq!(rom.data(), &[0x60, 0x01, 0x12]);
+        assert_eq!(rom.len(), 3);
+        assert!(rom.has_odd_length());
+    }
+
+    #[test]
+    fn test_rom_too_large() {
+        let rom = Rom::from_bytes(vec![0x0; 3584], DEFAULT_LOAD_ADDRESS).unwrap();
+
+        assert!(rom.check_fits(4096).is_ok());
+
+        let rom = Rom::from_bytes(vec![0x0; 3584], ETI660_LOAD_ADDRESS).unwrap();
+
+        assert!(matches!(
+            rom.check_fits(4096),
+            Err(RomError::TooLarge {
+                size: 3584,
+                max: 2560
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rom_empty() {
+        assert!(matches!(
+            Rom::from_bytes(Vec::new(), DEFAULT_LOAD_ADDRESS),
+            Err(RomError::Empty)
+        ));
+    }
+}
@@ -0,0 +1,523 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    audio::{AudioPattern, AUDIO_PATTERN_SIZE},
+    display::Framebuffer,
+    flags::N_RPL_FLAGS,
+    keypad::{Keypad, N_KEYS},
+    memory::{MAX_STACK_SIZE, MEMORY_SIZE, XO_CHIP_MEMORY_SIZE},
+    quirks::Quirks,
+};
+
+/// The first bytes of every save state file
+pub const SAVE_STATE_MAGIC: [u8; 4] = *b"C8SS";
+/// Version of the format `to_bytes` writes. `from_bytes` reads it and
+/// every older version, and rejects the newer ones
+pub const SAVE_STATE_VERSION: u16 = 2;
+
+#[derive(Debug)]
+pub enum SaveStateError {
+    Io(io::Error),
+    /// The bytes do not start with [`SAVE_STATE_MAGIC`]
+    NotASaveState,
+    /// Written by a newer version of the emulator
+    UnsupportedVersion(u16),
+    /// The bytes end before the whole state is read
+    Truncated,
+    /// A value no machine can have, like a memory of an unknown size
+    Invalid(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::Io(err) => write!(f, "{err}"),
+            SaveStateError::NotASaveState => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state version {version} is not supported (the latest one is {SAVE_STATE_VERSION})"
+            ),
+            SaveStateError::Truncated => write!(f, "the save state is truncated"),
+            SaveStateError::Invalid(what) => write!(f, "invalid save state: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl From<io::Error> for SaveStateError {
+    fn from(err: io::Error) -> Self {
+        SaveStateError::Io(err)
+    }
+}
+
+/// Everything the machine needs to go on exactly where it was, taken by
+/// [`CPU::save_state`](crate::CPU::save_state) and put back by
+/// [`CPU::restore_state`](crate::CPU::restore_state).
+///
+/// The random generator is only saved when it has a state to save, like
+/// [`SeededRng`](crate::SeededRng). The flag storage is not saved, as it
+/// belongs to the host
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub quirks: Quirks,
+    pub registers: [u8; 16],
+    pub i_register: u16,
+    pub program_counter: u16,
+    /// The whole memory: 4 KiB, or 64 KiB on XO-CHIP
+    pub memory: Vec<u8>,
+    pub stack: [u16; MAX_STACK_SIZE],
+    pub stack_pointer: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub framebuffer: Framebuffer,
+    pub keypad: Keypad,
+    /// The register of a pending **LD Vx, K**, and the key pressed so far
+    pub key_wait: Option<(u8, Option<u8>)>,
+    pub halted: bool,
+    /// A sprite was drawn on this frame (`display_wait` quirk)
+    pub drawn_this_frame: bool,
+    pub rpl_flags: [u8; N_RPL_FLAGS],
+    pub audio: AudioPattern,
+    pub rng_state: Option<u64>,
+    pub instructions_per_tick: u32,
+}
+
+/// Appends the values in little endian
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /**
+     * Writes the length (u32) and then the bytes
+     */
+    fn block(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.bytes.extend_from_slice(bytes);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], SaveStateError> {
+        if self.bytes.len() < size {
+            return Err(SaveStateError::Truncated);
+        }
+
+        let (taken, rest) = self.bytes.split_at(size);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let mut array = [0x0; N];
+        array.copy_from_slice(self.take(N)?);
+
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SaveStateError::Invalid("boolean other than 0 or 1")),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn block(&mut self) -> Result<&'a [u8], SaveStateError> {
+        let size = self.u32()? as usize;
+
+        self.take(size)
+    }
+}
+
+impl SaveState {
+    /**
+     * Encodes the state: the magic, the version (u16) and then the
+     * machine, every number in little endian
+     */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer {
+            bytes: SAVE_STATE_MAGIC.to_vec(),
+        };
+
+        writer.u16(SAVE_STATE_VERSION);
+        writer.u16(self.quirks.to_bits());
+        writer.bytes.extend_from_slice(&self.registers);
+        writer.u16(self.i_register);
+        writer.u16(self.program_counter);
+        writer.u16(self.stack_pointer);
+
+        for address in self.stack {
+            writer.u16(address);
+        }
+
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.block(&self.memory);
+
+        writer.bool(self.framebuffer.is_hires());
+        writer.u8(self.framebuffer.planes());
+        writer.block(self.framebuffer.pixels());
+
+        writer.u16(self.keypad.bits());
+
+        // 0: not waiting, 1: waiting, 2: waiting for the release of a key
+        match self.key_wait {
+            None => writer.bytes.extend_from_slice(&[0, 0, 0]),
+            Some((register, None)) => writer.bytes.extend_from_slice(&[1, register, 0]),
+            Some((register, Some(key))) => writer.bytes.extend_from_slice(&[2, register, key]),
+        }
+
+        writer.bool(self.halted);
+        writer.bool(self.drawn_this_frame);
+        writer.bytes.extend_from_slice(&self.rpl_flags);
+
+        match self.audio.pattern() {
+            Some(pattern) => {
+                writer.bool(true);
+                writer.bytes.extend_from_slice(pattern);
+            }
+            None => {
+                writer.bool(false);
+                writer.bytes.extend_from_slice(&[0x0; AUDIO_PATTERN_SIZE]);
+            }
+        }
+        writer.u8(self.audio.pitch());
+
+        writer.bool(self.rng_state.is_some());
+        writer.u64(self.rng_state.unwrap_or_default());
+        writer.u32(self.instructions_per_tick);
+
+        writer.bytes
+    }
+
+    /**
+     * Decodes a state written by `to_bytes`, on this or an older version.
+     * A new version adds its own `read_vN`, and the old ones fill the
+     * new fields with the values a fresh machine has
+     */
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SaveStateError> {
+        if !bytes.starts_with(&SAVE_STATE_MAGIC) {
+            return Err(SaveStateError::NotASaveState);
+        }
+
+        let mut reader = Reader {
+            bytes: &bytes[SAVE_STATE_MAGIC.len()..],
+        };
+
+        let state = match reader.u16()? {
+            1 => Self::read_v1(&mut reader)?,
+            2 => {
+                let quirks = Quirks::from_bits(reader.u16()?);
+                Self::read_machine(&mut reader, quirks)?
+            }
+            version => return Err(SaveStateError::UnsupportedVersion(version)),
+        };
+
+        if !reader.bytes.is_empty() {
+            return Err(SaveStateError::Invalid("unexpected bytes after the state"));
+        }
+
+        Ok(state)
+    }
+
+    /**
+     * Version 1 packed the quirks on a byte, and always ran the SUPER-CHIP
+     * instructions, drew the 16x16 sprites and cleared the screen on a
+     * resolution switch
+     */
+    fn read_v1(reader: &mut Reader) -> Result<Self, SaveStateError> {
+        let quirks = Quirks {
+            large_sprites: true,
+            hires_clears: true,
+            super_chip: true,
+            ..Quirks::from_bits(reader.u8()?.into())
+        };
+
+        Self::read_machine(reader, quirks)
+    }
+
+    /**
+     * Reads everything after the quirks, which every version has kept as it is
+     */
+    fn read_machine(reader: &mut Reader, quirks: Quirks) -> Result<Self, SaveStateError> {
+        let registers = reader.array()?;
+        let i_register = reader.u16()?;
+        let program_counter = reader.u16()?;
+        let stack_pointer = reader.u16()?;
+
+        let mut stack = [0x0; MAX_STACK_SIZE];
+        for address in stack.iter_mut() {
+            *address = reader.u16()?;
+        }
+
+        if stack_pointer as usize > MAX_STACK_SIZE {
+            return Err(SaveStateError::Invalid("stack pointer out of the stack"));
+        }
+
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+
+        let memory = reader.block()?.to_vec();
+        let memory_size = match quirks.xo_chip {
+            true => XO_CHIP_MEMORY_SIZE,
+            false => MEMORY_SIZE,
+        };
+
+        if memory.len() != memory_size {
+            return Err(SaveStateError::Invalid("memory size"));
+        }
+
+        let hires = reader.bool()?;
+        let planes = reader.u8()?;
+        let framebuffer = Framebuffer::from_pixels(reader.block()?.to_vec(), hires, planes)
+            .ok_or(SaveStateError::Invalid("framebuffer"))?;
+
+        let keys = reader.u16()?;
+        let mut keypad = Keypad::new();
+        for key in 0..N_KEYS as u8 {
+            if keys & (1 << key) != 0 {
+                keypad.press(key);
+            }
+        }
+
+        let key_wait = match reader.array::<3>()? {
+            [0, _, _] => None,
+            [1, register, _] if register < 16 => Some((register, None)),
+            [2, register, key] if register < 16 && key < 16 => Some((register, Some(key))),
+            _ => return Err(SaveStateError::Invalid("key wait")),
+        };
+
+        let halted = reader.bool()?;
+        let drawn_this_frame = reader.bool()?;
+        let rpl_flags = reader.array()?;
+
+        let mut audio = AudioPattern::new();
+        let has_pattern = reader.bool()?;
+        let pattern = reader.array()?;
+        if has_pattern {
+            audio.set_pattern(pattern);
+        }
+        audio.set_pitch(reader.u8()?);
+
+        let has_rng_state = reader.bool()?;
+        let rng_state = reader.u64()?;
+
+        Ok(Self {
+            quirks,
+            registers,
+            i_register,
+            program_counter,
+            memory,
+            stack,
+            stack_pointer,
+            delay_timer,
+            sound_timer,
+            framebuffer,
+            keypad,
+            key_wait,
+            halted,
+            drawn_this_frame,
+            rpl_flags,
+            audio,
+            rng_state: has_rng_state.then_some(rng_state),
+            instructions_per_tick: reader.u32()?,
+        })
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), SaveStateError> {
+        fs::write(path, self.to_bytes())?;
+
+        Ok(())
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, SaveStateError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+/**
+ * Returns the file of a quick-save slot, next to the ROM:
+ * `games/pong.ch8` keeps the slot 1 on `games/pong.1.state`
+ */
+pub fn slot_path<P: AsRef<Path>>(rom_path: P, slot: u8) -> PathBuf {
+    rom_path.as_ref().with_extension(format!("{slot}.state"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{slot_path, SaveState, SaveStateError, SAVE_STATE_MAGIC, SAVE_STATE_VERSION};
+    use crate::{
+        asm::Assembler,
+        cpu::CPU,
+        quirks::Quirks,
+        rom::{Rom, DEFAULT_LOAD_ADDRESS},
+    };
+
+    fn cpu_running(quirks: Quirks) -> CPU {
+        let program = Assembler::new()
+            .assemble(
+                "
+                    LD V0, 12
+                    LD F, V0
+                    LD V1, 3
+                    LD DT, V1
+                    CALL draw
+                    LD V2, K
+                draw:
+                    RND V3, 0xFF
+                    DRW V1, V1, 5
+                    RET
+                ",
+            )
+            .unwrap();
+        let mut cpu = CPU::with_seed(quirks, 7);
+
+        cpu.load_rom(&Rom::from_bytes(program, DEFAULT_LOAD_ADDRESS).unwrap())
+            .unwrap();
+        cpu.press_key(0x4);
+
+        for _ in 0..9 {
+            cpu.step().unwrap();
+        }
+
+        // Pressed while waiting, stored once released
+        cpu.press_key(0x9);
+
+        cpu
+    }
+
+    #[test]
+    fn test_save_and_restore_state() {
+        for quirks in [Quirks::default(), Quirks::xo_chip()] {
+            let mut cpu = cpu_running(quirks);
+
+            assert!(cpu.is_waiting_for_key());
+            assert_eq!(cpu.stack_pointer(), 0);
+
+            let state = cpu.save_state();
+            let bytes = state.to_bytes();
+
+            assert_eq!(&bytes[..6], b"C8SS\x02\x00");
+            assert_eq!(SaveState::from_bytes(&bytes).unwrap(), state);
+
+            // Version 1 had a byte of quirks, and no quirks for SUPER-CHIP
+            let mut v1 = bytes.clone();
+            v1[4] = 1;
+            v1.remove(7);
+            let old = SaveState::from_bytes(&v1).unwrap();
+            assert!(old.quirks.large_sprites && old.quirks.hires_clears && old.quirks.super_chip);
+            assert_eq!(old.registers, state.registers);
+
+            // The machine goes on differently, and then back to the state
+            let mut other = CPU::with_seed(Quirks::default(), 99);
+            cpu.release_key(0x9);
+            cpu.step().unwrap();
+            cpu.step().unwrap();
+
+            other.restore_state(&SaveState::from_bytes(&bytes).unwrap());
+            cpu.restore_state(&state);
+
+            assert_eq!(other.save_state(), state);
+            assert_eq!(cpu.save_state(), state);
+            assert_eq!(cpu.framebuffer(), other.framebuffer());
+
+            // Both go on exactly the same way, random numbers included
+            for cpu in [&mut cpu, &mut other] {
+                cpu.release_key(0x9);
+                cpu.step().unwrap();
+                cpu.step().unwrap();
+            }
+
+            assert_eq!(cpu.save_state(), other.save_state());
+            assert_eq!(cpu.registers[2], 0x9);
+        }
+    }
+
+    #[test]
+    fn test_rejected_states() {
+        let bytes = cpu_running(Quirks::default()).save_state().to_bytes();
+
+        assert!(matches!(
+            SaveState::from_bytes(b"CHIP8"),
+            Err(SaveStateError::NotASaveState)
+        ));
+
+        let mut newer = bytes.clone();
+        newer[4..6].copy_from_slice(&(SAVE_STATE_VERSION + 1).to_le_bytes());
+        assert!(matches!(
+            SaveState::from_bytes(&newer),
+            Err(SaveStateError::UnsupportedVersion(3))
+        ));
+
+        assert!(matches!(
+            SaveState::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SaveStateError::Truncated)
+        ));
+        assert!(matches!(
+            SaveState::from_bytes(&[&bytes[..], &[0]].concat()),
+            Err(SaveStateError::Invalid(_))
+        ));
+
+        // A 4 KiB memory with the XO-CHIP quirks
+        let mut xo_chip = bytes.clone();
+        xo_chip[SAVE_STATE_MAGIC.len() + 2] |= 1 << 6;
+        assert!(matches!(
+            SaveState::from_bytes(&xo_chip),
+            Err(SaveStateError::Invalid("memory size"))
+        ));
+
+        assert_eq!(
+            slot_path("games/pong.ch8", 1),
+            PathBuf::from("games/pong.1.state")
+        );
+    }
+}
@@ -0,0 +1,537 @@
+use std::{
+    io::{self, Write},
+    panic,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor,
+    event::{
+        self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute, queue,
+    style::Print,
+    terminal::{self, ClearType},
+};
+
+use crate::{
+    cpu::CPU,
+    display::Framebuffer,
+    keypad::{keypad_key, N_KEYS},
+    movie::Movie,
+    savestate::{slot_path, SaveState},
+};
+
+/// Time between two frames: the timers run at 60 Hz
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// Frames a message stays on the status line: 2 seconds
+const MESSAGE_FRAMES: u16 = 120;
+/// Most terminals only report the presses, so on those a key is held for
+/// this many frames after its last press (or its last auto-repeat)
+const KEY_HOLD_FRAMES: u8 = 12;
+/// How the pixels are packed on the characters of the terminal
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TuiMode {
+    /// 1x2 pixels per character (`▀`, `▄` and `█`), so 64x16 characters
+    /// on low resolution
+    #[default]
+    HalfBlock,
+    /// 2x4 pixels per character (`⣿`), so 32x8 characters on low
+    /// resolution, for the small terminals
+    Braille,
+}
+
+impl TuiMode {
+    /**
+     * Returns how many pixels a character holds (width, height)
+     */
+    fn cell_size(&self) -> (usize, usize) {
+        match self {
+            TuiMode::HalfBlock => (1, 2),
+            TuiMode::Braille => (2, 4),
+        }
+    }
+
+    /**
+     * Returns the character of the pixels of a cell, given as
+     * `lit(x, y)` within the cell
+     */
+    fn character(&self, lit: impl Fn(usize, usize) -> bool) -> char {
+        match self {
+            TuiMode::HalfBlock => match (lit(0, 0), lit(0, 1)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            },
+            TuiMode::Braille => {
+                // The dots of a braille character, column by column
+                const DOTS: [(usize, usize, u32); 8] = [
+                    (0, 0, 0x01),
+                    (0, 1, 0x02),
+                    (0, 2, 0x04),
+                    (1, 0, 0x08),
+                    (1, 1, 0x10),
+                    (1, 2, 0x20),
+                    (0, 3, 0x40),
+                    (1, 3, 0x80),
+                ];
+
+                let bits = DOTS
+                    .iter()
+                    .filter(|(x, y, _)| lit(*x, *y))
+                    .fold(0, |bits, (_, _, bit)| bits | bit);
+
+                char::from_u32(0x2800 + bits).unwrap_or(' ')
+            }
+        }
+    }
+}
+
+/// How [`run_tui`] runs
+
+#[derive(Debug, Default)]
+pub struct TuiOptions<'a> {
+    pub mode: TuiMode,
+    /// Records the keys of every frame on the movie. The CPU must come
+    /// from [`Movie::start`]
+    pub movie: Option<&'a mut Movie>,
+    /// Quits after this many frames
+    pub frames: Option<u64>,
+    /// Enables the quick-save slots (F1 - F10 load the slots 0 - 9, and
+    /// Shift+F1 - F10 save them), kept next to the ROM
+    pub rom_path: Option<&'a Path>,
+}
+
+/// Turns the framebuffer into characters, and remembers the ones on the
+/// terminal to only redraw the cells which changed
+
+#[derive(Debug, Clone, Default)]
+pub struct CellRenderer {
+    mode: TuiMode,
+    width: usize,
+    height: usize,
+    /// The characters on the terminal, row by row
+    cells: Vec<char>,
+}
+
+impl CellRenderer {
+    pub fn new(mode: TuiMode) -> Self {
+        Self {
+            mode,
+            ..Self::default()
+        }
+    }
+
+    /**
+     * Forgets the characters on the terminal, so the next update
+     * redraws every cell (like after the terminal is resized)
+     */
+    pub fn invalidate(&mut self) {
+        self.cells.clear();
+    }
+
+    /**
+     * Returns the size of the screen in characters
+     */
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /**
+     * Returns the cells which changed since the last update,
+     * as `(column, row, character)`
+     */
+    pub fn update(&mut self, framebuffer: &Framebuffer) -> Vec<(usize, usize, char)> {
+        let (cell_width, cell_height) = self.mode.cell_size();
+        let width = framebuffer.width().div_ceil(cell_width);
+        let height = framebuffer.height().div_ceil(cell_height);
+
+        // A new resolution redraws the whole screen
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.cells.clear();
+        }
+
+        let redraw = self.cells.is_empty();
+        if redraw {
+            self.cells = vec![' '; width * height];
+        }
+
+        let mut changes = Vec::new();
+
+        for row in 0..height {
+            for column in 0..width {
+                let character = self.mode.character(|x, y| {
+                    let (x, y) = (column * cell_width + x, row * cell_height + y);
+
+                    x < framebuffer.width()
+                        && y < framebuffer.height()
+                        && framebuffer.color(x, y) != 0
+                });
+
+                let cell = &mut self.cells[row * width + column];
+                if redraw || *cell != character {
+                    *cell = character;
+                    changes.push((column, row, character));
+                }
+            }
+        }
+
+        changes
+    }
+}
+
+/**
+ * Puts the terminal back as it was
+ */
+fn restore_terminal() {
+    let mut stdout = io::stdout();
+
+    let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+}
+
+/// Puts the terminal on raw mode and on the alternate screen while it
+/// lives, and restores it when it is dropped, even while unwinding a panic
+struct TerminalGuard {
+    /// Whether the terminal reports the key releases
+    reports_releases: bool,
+}
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+
+        let mut guard = Self {
+            reports_releases: false,
+        };
+        let mut stdout = io::stdout();
+
+        execute!(
+            stdout,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(ClearType::All)
+        )?;
+
+        // The terminals which support it also report the key releases
+        if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+            guard.reports_releases = true;
+        }
+
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Runs the CPU at 60 Hz on the terminal
+struct Tui<'a> {
+    cpu: &'a mut CPU,
+    movie: Option<&'a mut Movie>,
+    /// Frames left until it quits
+    frames_left: Option<u64>,
+    renderer: CellRenderer,
+    /// Frames left until each key is released, when the terminal does not
+    /// report the releases
+    held: [u8; N_KEYS],
+    reports_releases: bool,
+    /// The size of the terminal, to not draw outside of it
+    columns: usize,
+    rows: usize,
+    rom_path: Option<&'a Path>,
+    /// The status line on the terminal
+    status: Option<String>,
+    /// Shown on the status line for a few frames, like after a quick save
+    message: Option<(String, u16)>,
+    quit: bool,
+}
+
+impl Tui<'_> {
+    /**
+     * Saves the machine on a quick-save slot, or loads it, and shows how
+     * it went on the status line
+     */
+    fn quick_save(&mut self, save: bool, slot: u8) {
+        let Some(rom_path) = self.rom_path else {
+            return;
+        };
+        let path = slot_path(rom_path, slot);
+
+        let result = match save {
+            true => self.cpu.save_state().save_to_file(&path),
+            false => SaveState::from_file(&path).map(|state| self.cpu.restore_state(&state)),
+        };
+
+        let message = match (result, save) {
+            (Ok(()), true) => format!("Saved on the slot {slot}"),
+            (Ok(()), false) => format!("Loaded the slot {slot}"),
+            (Err(err), _) => format!("{err} ({})", path.display()),
+        };
+        self.message = Some((message, MESSAGE_FRAMES));
+    }
+
+    /**
+     * Forwards the keypad keys to the CPU, saves or loads the quick-save
+     * slots on F1 - F10, and quits on Esc or Ctrl+C
+     */
+    fn handle_key(&mut self, event: KeyEvent) {
+        let ctrl_c =
+            event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL);
+
+        if event.code == KeyCode::Esc || ctrl_c {
+            self.quit = true;
+            return;
+        }
+
+        if let KeyCode::F(number @ 1..=10) = event.code {
+            if event.kind == KeyEventKind::Press {
+                let save = event.modifiers.contains(KeyModifiers::SHIFT);
+                self.quick_save(save, number - 1);
+            }
+            return;
+        }
+
+        let KeyCode::Char(character) = event.code else {
+            return;
+        };
+        let Some(key) = keypad_key(character) else {
+            return;
+        };
+
+        match event.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => {
+                if !self.reports_releases {
+                    self.held[key as usize] = KEY_HOLD_FRAMES;
+                }
+                self.cpu.press_key(key);
+            }
+            KeyEventKind::Release => {
+                self.held[key as usize] = 0;
+                self.cpu.release_key(key);
+            }
+        }
+    }
+
+    /**
+     * Releases the keys which have not been pressed for a while
+     */
+    fn release_keys(&mut self) {
+        for (key, frames) in self.held.iter_mut().enumerate() {
+            if *frames == 1 {
+                self.cpu.release_key(key as u8);
+            }
+
+            *frames = frames.saturating_sub(1);
+        }
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        let size = self.renderer.size();
+        let cells = self.renderer.update(self.cpu.framebuffer());
+
+        // The screen of another resolution would leave the old one behind
+        if self.renderer.size() != size {
+            queue!(out, terminal::Clear(ClearType::All))?;
+            self.status = None;
+        }
+
+        for (column, row, character) in cells {
+            if column < self.columns && row < self.rows {
+                queue!(
+                    out,
+                    cursor::MoveTo(column as u16, row as u16),
+                    Print(character)
+                )?;
+            }
+        }
+
+        let status = match (&self.message, self.cpu.is_halted()) {
+            (Some((message, _)), _) => message,
+            (None, true) => "Halted. Esc: quit",
+            (None, false) if self.movie.is_some() => {
+                "Recording  1234/QWER/ASDF/ZXCV: keypad  Esc: quit"
+            }
+            (None, false) if self.rom_path.is_some() => {
+                "1234/QWER/ASDF/ZXCV: keypad  F1-F10: load (Shift: save)  Esc: quit"
+            }
+            (None, false) => "1234/QWER/ASDF/ZXCV: keypad  Esc: quit",
+        };
+        let (_, height) = self.renderer.size();
+
+        if self.status.as_deref() != Some(status) && height < self.rows {
+            queue!(
+                out,
+                cursor::MoveTo(0, height as u16),
+                terminal::Clear(ClearType::CurrentLine),
+                Print(status)
+            )?;
+            self.status = Some(status.to_string());
+        }
+
+        out.flush()
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        let mut out = io::BufWriter::new(io::stdout());
+        let mut next_frame = Instant::now();
+
+        while !self.quit {
+            // The events until the next frame is due
+            loop {
+                let timeout = next_frame.saturating_duration_since(Instant::now());
+
+                if !event::poll(timeout)? {
+                    break;
+                }
+
+                match event::read()? {
+                    Event::Key(event) => self.handle_key(event),
+                    Event::Resize(columns, rows) => {
+                        (self.columns, self.rows) = (columns as usize, rows as usize);
+                        self.renderer.invalidate();
+                        self.status = None;
+                        queue!(out, terminal::Clear(ClearType::All))?;
+                    }
+                    _ => {}
+                }
+
+                if self.quit {
+                    return Ok(());
+                }
+            }
+
+            if !self.cpu.is_halted() {
+                match self.movie.as_deref_mut() {
+                    Some(movie) => movie.record_frame(self.cpu),
+                    None => self.cpu.run_frame(),
+                }
+                .map_err(io::Error::other)?;
+            }
+
+            if let Some(frames) = &mut self.frames_left {
+                *frames = frames.saturating_sub(1);
+                self.quit = *frames == 0;
+            }
+
+            self.release_keys();
+
+            if let Some((_, frames)) = &mut self.message {
+                *frames -= 1;
+                if *frames == 0 {
+                    self.message = None;
+                }
+            }
+
+            self.draw(&mut out)?;
+
+            // After a stall (like a suspended process) go on from now,
+            // instead of running the missed frames at once
+            next_frame += FRAME_DURATION;
+            if next_frame < Instant::now() {
+                next_frame = Instant::now() + FRAME_DURATION;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/**
+ * Runs the CPU on the terminal until Esc (or Ctrl+C) is pressed, reading
+ * the keys on raw mode and drawing the screen at 60 Hz. The terminal is
+ * restored when it returns, and also if it panics
+ */
+pub fn run_tui(cpu: &mut CPU, options: TuiOptions) -> io::Result<()> {
+    // The panic message must be printed on the restored terminal, and the
+    // hook runs before the guard is dropped
+    let previous_hook: Arc<dyn Fn(&panic::PanicHookInfo) + Sync + Send> =
+        Arc::from(panic::take_hook());
+    panic::set_hook(Box::new({
+        let previous_hook = previous_hook.clone();
+
+        move |info| {
+            restore_terminal();
+            previous_hook(info);
+        }
+    }));
+
+    let result = TerminalGuard::enter().and_then(|guard| {
+        let (columns, rows) = terminal::size()?;
+        let mut tui = Tui {
+            cpu,
+            movie: options.movie,
+            frames_left: options.frames,
+            renderer: CellRenderer::new(options.mode),
+            held: [0; N_KEYS],
+            reports_releases: guard.reports_releases,
+            columns: columns as usize,
+            rows: rows as usize,
+            rom_path: options.rom_path,
+            status: None,
+            message: None,
+            quit: false,
+        };
+
+        tui.run()
+    });
+
+    // Back to the hook of the caller
+    panic::set_hook(Box::new(move |info| previous_hook(info)));
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CellRenderer, TuiMode};
+    use crate::display::Framebuffer;
+
+    #[test]
+    fn test_cell_rendering() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.draw_sprite(0, 0, &[0b1000_0000, 0b1100_0000, 0b0100_0000], false);
+
+        let mut renderer = CellRenderer::new(TuiMode::HalfBlock);
+        let cells = renderer.update(&framebuffer);
+
+        // The first update draws every cell
+        assert_eq!(renderer.size(), (64, 16));
+        assert_eq!(cells.len(), 64 * 16);
+        assert_eq!(cells[0], (0, 0, '█'));
+        assert_eq!(cells[1], (1, 0, '▄'));
+        assert_eq!(cells[64], (0, 1, ' '));
+        assert_eq!(cells[65], (1, 1, '▀'));
+
+        // And then only the changes
+        assert!(renderer.update(&framebuffer).is_empty());
+        framebuffer.draw_sprite(10, 10, &[0b1000_0000], false);
+        assert_eq!(renderer.update(&framebuffer), vec![(10, 5, '▀')]);
+
+        framebuffer.set_hires(true, true);
+        assert_eq!(renderer.update(&framebuffer).len(), 128 * 32);
+
+        let mut renderer = CellRenderer::new(TuiMode::Braille);
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.draw_sprite(0, 0, &[0b1100_0000, 0, 0, 0b0100_0000], false);
+
+        let cells = renderer.update(&framebuffer);
+        assert_eq!(renderer.size(), (32, 8));
+        assert_eq!(cells[0], (0, 0, '⢉'));
+    }
+}